@@ -0,0 +1,37 @@
+#[cfg(feature = "capi")]
+extern crate cbindgen;
+
+// The header is always generated into OUT_DIR, so builds never write to the source tree (which would break
+//  `cargo package` and read-only checkouts). Set this to a directory to also write it there, eg. to refresh the
+//  copy in include/.
+#[cfg(feature = "capi")]
+const HEADER_DIR_VAR: &str = "SNES_APU_HEADER_DIR";
+
+#[cfg(feature = "capi")]
+fn generate_header() {
+    use std::env;
+    use std::path::Path;
+
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let config = cbindgen::Config::from_file(Path::new(&crate_dir).join("cbindgen.toml")).unwrap();
+    let bindings = cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Unable to generate C header");
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    bindings.write_to_file(Path::new(&out_dir).join("snes_apu.h"));
+    if let Some(header_dir) = env::var_os(HEADER_DIR_VAR) {
+        bindings.write_to_file(Path::new(&header_dir).join("snes_apu.h"));
+    }
+
+    println!("cargo:rerun-if-changed=src/capi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed={}", HEADER_DIR_VAR);
+}
+
+fn main() {
+    #[cfg(feature = "capi")]
+    generate_header();
+}
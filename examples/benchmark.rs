@@ -0,0 +1,54 @@
+extern crate snes_apu;
+
+use snes_apu::apu::Apu;
+use snes_apu::dsp::dsp::SAMPLE_RATE;
+
+use std::env;
+use std::fs;
+use std::time::Instant;
+
+const DEFAULT_SECONDS: u32 = 60;
+const BLOCK_LEN: i32 = 512;
+
+// Renders each spc (the bundled ones by default) as fast as possible and reports how many times faster than
+//  realtime that is. Best run in release config: cargo run --release --example benchmark [seconds] [files...]
+fn main() {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let seconds = match args.first().and_then(|arg| arg.parse::<u32>().ok()) {
+        Some(seconds) => {
+            args.remove(0);
+            seconds
+        },
+        _ => DEFAULT_SECONDS
+    };
+    if args.is_empty() {
+        args = vec!["test/ferris-nu.spc".to_string(), "test/smashit.spc".to_string()];
+    }
+
+    let mut left = [0; BLOCK_LEN as usize];
+    let mut right = [0; BLOCK_LEN as usize];
+    for file_name in args.iter() {
+        let data = match fs::read(file_name) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("ERROR: Unable to read {}: {}", file_name, e);
+                std::process::exit(1);
+            }
+        };
+        let mut apu = match Apu::from_spc_bytes(&data) {
+            Ok(apu) => apu,
+            Err(e) => {
+                println!("ERROR: Unable to load {}: {}", file_name, e);
+                std::process::exit(1);
+            }
+        };
+
+        let num_blocks = seconds * (SAMPLE_RATE as u32) / (BLOCK_LEN as u32);
+        let start = Instant::now();
+        for _ in 0..num_blocks {
+            apu.render(&mut left, &mut right, BLOCK_LEN);
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        println!("{}: {}s rendered in {:.3}s ({:.1}x realtime)", file_name, seconds, elapsed, (seconds as f64) / elapsed);
+    }
+}
@@ -10,12 +10,15 @@ use futures::task::{self, Executor, Run};
 
 use snes_apu::apu::Apu;
 use snes_apu::dsp::dsp::{BUFFER_LEN, SAMPLE_RATE};
+use snes_apu::silence_detector::SilenceDetector;
+use snes_apu::xid6::Xid6Tag;
 
 use spc::spc::{Emulator, Spc};
 
 use std::borrow::Cow;
 use std::env;
 use std::fmt::Display;
+use std::fs;
 use std::io::{stdout, Write};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
@@ -47,19 +50,37 @@ fn do_it() -> Result<(), Cow<'static, str>> {
 
 fn play_spc_file<P: AsRef<Path> + Display>(path: P) -> Result<(), Cow<'static, str>> {
     let spc = Spc::load(&path).map_err(|e| format!("Could not load spc file: {}", e))?;
+    let data = fs::read(path.as_ref()).map_err(|e| format!("Could not read spc file: {}", e))?;
+    let xid6_tag = Xid6Tag::from_spc_bytes(&data).map_err(|e| format!("Could not load xid6 tag: {}", e))?;
 
-    print_spc_info(path, &spc);
+    print_spc_info(path, &spc, &xid6_tag);
 
     let mut apu = Apu::from_spc(&spc);
     // Most SPC's have crap in the echo buffer on startup, so while it's not technically correct, we'll clear that.
     // The example for blargg's APU emulator (which is known to be the most accurate there is) also does this, so I
     //  think we're OK to do it too :)
     apu.clear_echo_buffer();
+    // Sound effects and jingles end in silence long before their tagged length (if they have one), so stop
+    //  once the output has been completely silent for a few seconds
+    apu.set_silence_detector(Some(SilenceDetector::new(0, 3 * (SAMPLE_RATE as i32))));
+    if let Some(muted_voices) = xid6_tag.as_ref().and_then(|tag| tag.muted_voices) {
+        for (i, voice) in apu.dsp.as_mut().unwrap().voices.iter_mut().enumerate() {
+            voice.is_muted = (muted_voices & (1 << i)) != 0;
+        }
+    }
 
     let mut left = Box::new([0; BUFFER_LEN]);
     let mut right = Box::new([0; BUFFER_LEN]);
 
-    let mut end_state = if let Some(ref id666_tag) = spc.id666_tag {
+    let xid6_lengths = xid6_tag.as_ref().and_then(|tag| tag.play_samples().map(|play_samples| (play_samples, tag.fade_samples().unwrap_or(0))));
+    // The xid6 lengths are exact, so they win over the id666 tag's whole seconds
+    let mut end_state = if let Some((play_samples, fade_samples)) = xid6_lengths {
+        Some(SpcEndState {
+            sample_pos: 0,
+            fade_out_sample: play_samples as i32,
+            end_sample: (play_samples + fade_samples) as i32,
+        })
+    } else if let Some(ref id666_tag) = spc.id666_tag {
         let fade_out_sample = id666_tag.seconds_to_play_before_fading_out * (SAMPLE_RATE as i32);
         let end_sample = fade_out_sample + id666_tag.fade_out_length * (SAMPLE_RATE as i32) / 1000;
         Some(SpcEndState {
@@ -110,6 +131,10 @@ fn play_spc_file<P: AsRef<Path> + Display>(path: P) -> Result<(), Cow<'static, s
             }
         }
 
+        if apu.is_end_of_track() {
+            break;
+        }
+
         print!("\r[{}]", chars[char_index]);
         stdout().flush().ok();
         char_index = (char_index + 1) % chars.len();
@@ -120,7 +145,7 @@ fn play_spc_file<P: AsRef<Path> + Display>(path: P) -> Result<(), Cow<'static, s
     Ok(())
 }
 
-fn print_spc_info<P: AsRef<Path> + Display>(path: P, spc: &Spc) {
+fn print_spc_info<P: AsRef<Path> + Display>(path: P, spc: &Spc, xid6_tag: &Option<Xid6Tag>) {
     println!("SPC: {}", path);
     println!(" Version Minor: {}", spc.version_minor);
     println!(" PC: {}", spc.pc);
@@ -149,6 +174,34 @@ fn print_spc_info<P: AsRef<Path> + Display>(path: P, spc: &Spc) {
     } else {
         println!(" No ID666 tag present.");
     };
+
+    if let Some(ref xid6_tag) = *xid6_tag {
+        println!(" xid6 tag present:");
+        if let Some(ref ost_title) = xid6_tag.ost_title {
+            println!("  OST title: {}", ost_title);
+        }
+        if let Some(ost_disc) = xid6_tag.ost_disc {
+            println!("  OST disc: {}", ost_disc);
+        }
+        if let Some((track, suffix)) = xid6_tag.ost_track {
+            match suffix {
+                0 => println!("  OST track: {}", track),
+                _ => println!("  OST track: {}{}", track, suffix as char)
+            }
+        }
+        if let Some(ref publisher) = xid6_tag.publisher {
+            println!("  Publisher: {}", publisher);
+        }
+        if let Some(copyright_year) = xid6_tag.copyright_year {
+            println!("  Copyright year: {}", copyright_year);
+        }
+        if let Some(play_samples) = xid6_tag.play_samples() {
+            println!("  Length before fading out: {}ms", play_samples * 1000 / (SAMPLE_RATE as u64));
+        }
+        if let Some(fade_samples) = xid6_tag.fade_samples() {
+            println!("  Fade out length: {}ms", fade_samples * 1000 / (SAMPLE_RATE as u64));
+        }
+    }
 }
 
 pub struct RingBuffer {
@@ -0,0 +1,146 @@
+#ifndef SNES_APU_H
+#define SNES_APU_H
+
+/* Generated from src/capi.rs by cbindgen when building with the capi feature; don't edit by hand. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define SNES_APU_OK 0
+
+#define SNES_APU_ERROR_NULL_POINTER -1
+
+#define SNES_APU_ERROR_INVALID_SPC -2
+
+#define SNES_APU_ERROR_INVALID_ARGUMENT -3
+
+#define SNES_APU_ERROR_INVALID_STATE -4
+
+#define SNES_APU_ERROR_BUFFER_TOO_SMALL -5
+
+#define SNES_APU_ERROR_PANIC -6
+
+#define SNES_APU_SAMPLE_RATE 32000
+
+#define SNES_APU_NUM_VOICES 8
+
+typedef struct SnesApu SnesApu;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Creates an apu from the contents of an spc file. The data is copied, so it needn't outlive the call.
+
+ # Safety
+
+ `data` must point to `len` readable bytes, and `out` must be valid for writing a pointer. The handle written
+ to `out` must eventually be freed with `snes_apu_destroy`.
+ */
+int snes_apu_create_from_spc(const uint8_t *data,
+                             size_t len,
+                             struct SnesApu **out);
+
+/*
+ Frees an apu.
+
+ # Safety
+
+ `apu` must be null or a live handle, and mustn't be used again afterwards.
+ */
+void snes_apu_destroy(struct SnesApu *apu);
+
+/*
+ Renders `num_frames` stereo frames at 32khz into `out` as interleaved samples.
+
+ # Safety
+
+ `apu` must be null or a live handle, and `out` must be valid for writing `num_frames * 2` samples. A
+ `num_frames` too large for that to fit in a `size_t` is rejected with `SNES_APU_ERROR_INVALID_ARGUMENT`
+ before `out` is touched.
+ */
+int snes_apu_render_i16(struct SnesApu *apu,
+                        int16_t *out,
+                        size_t num_frames);
+
+/*
+ As `snes_apu_render_i16`, with samples scaled to [-1, 1).
+
+ # Safety
+
+ `apu` must be null or a live handle, and `out` must be valid for writing `num_frames * 2` samples. A
+ `num_frames` too large for that to fit in a `size_t` is rejected with `SNES_APU_ERROR_INVALID_ARGUMENT`
+ before `out` is touched.
+ */
+int snes_apu_render_f32(struct SnesApu *apu,
+                        float *out,
+                        size_t num_frames);
+
+/*
+ Mutes or unmutes one of the 8 voices.
+
+ # Safety
+
+ `apu` must be null or a live handle.
+ */
+int snes_apu_set_voice_muted(struct SnesApu *apu, uint32_t voice, bool is_muted);
+
+/*
+ Solos one of the 8 voices; while any voice is solo'd, only solo'd voices are heard.
+
+ # Safety
+
+ `apu` must be null or a live handle.
+ */
+int snes_apu_set_voice_solo(struct SnesApu *apu, uint32_t voice, bool is_solod);
+
+/*
+ Writes the current playback position, in frames since the spc was loaded, to `out`.
+
+ # Safety
+
+ `apu` must be null or a live handle, and `out` must be valid for writing a `uint64_t`.
+ */
+int snes_apu_position(struct SnesApu *apu, uint64_t *out);
+
+/*
+ Seeks to a position in frames. Seeking forward emulates up to the target; seeking backward reloads the spc
+ first, so it costs as much as seeking forward from the start.
+
+ # Safety
+
+ `apu` must be null or a live handle.
+ */
+int snes_apu_seek(struct SnesApu *apu,
+                  uint64_t position);
+
+/*
+ Saves the complete emulator state into `buffer`, writing its size to `out_len`. Pass a null buffer to query
+ the required size.
+
+ # Safety
+
+ `apu` must be null or a live handle, `out_len` must be valid for writing a `size_t`, and `buffer` must be
+ null or valid for writing `buffer_len` bytes.
+ */
+int snes_apu_save_state(struct SnesApu *apu,
+                        uint8_t *buffer,
+                        size_t buffer_len,
+                        size_t *out_len);
+
+/*
+ Restores state saved by `snes_apu_save_state`. On failure the apu is left as it was.
+
+ # Safety
+
+ `apu` must be null or a live handle, and `data` must point to `len` readable bytes.
+ */
+int snes_apu_load_state(struct SnesApu *apu, const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* SNES_APU_H */
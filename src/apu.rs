@@ -1,9 +1,42 @@
 use super::smp::Smp;
-use super::dsp::dsp::Dsp;
+use super::dsp::dsp::{Dsp, CYCLES_PER_SAMPLE, REG_LEN};
 use super::timer::Timer;
-use super::spc::spc::{Spc, RAM_LEN, IPL_ROM_LEN};
+use super::silence_detector::SilenceDetector;
+use super::state::{SmpState, DspState, VoiceState, TimerState};
+use super::error::SpcError;
 
-static DEFAULT_IPL_ROM: [u8; IPL_ROM_LEN] = [
+use std::boxed::Box;
+use std::cmp;
+use std::vec::Vec;
+
+#[cfg(feature = "std")]
+use super::spc::spc::Spc;
+#[cfg(feature = "std")]
+use std::io::Read;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer};
+#[cfg(feature = "serde")]
+use serde::de::Error;
+#[cfg(feature = "serde")]
+use super::dsp::dsp::NUM_VOICES;
+
+pub const RAM_LEN: usize = 0x10000;
+pub const IPL_ROM_LEN: usize = 64;
+
+// Frames rendered at a time by the interleaved renderers
+const INTERLEAVED_CHUNK_LEN: usize = 1024;
+
+// Layout of an spc file
+pub const SPC_MAGIC: &[u8] = b"SNES-SPC700 Sound File Data";
+pub const SPC_VERSION: &[u8] = b" v0.30";
+pub const SPC_VERSION_OFFSET: usize = 0x1b;
+pub const SPC_PADDING_OFFSET: usize = 0x21;
+pub const SPC_RAM_OFFSET: usize = 0x100;
+pub const SPC_REGS_OFFSET: usize = SPC_RAM_OFFSET + RAM_LEN;
+pub const SPC_IPL_ROM_OFFSET: usize = 0x101c0;
+
+pub static DEFAULT_IPL_ROM: [u8; IPL_ROM_LEN] = [
     0xcd, 0xef, 0xbd, 0xe8, 0x00, 0xc6, 0x1d, 0xd0,
     0xfc, 0x8f, 0xaa, 0xf4, 0x8f, 0xbb, 0xf5, 0x78,
     0xcc, 0xf4, 0xd0, 0xfb, 0x2f, 0x19, 0xeb, 0xf4,
@@ -14,6 +47,7 @@ static DEFAULT_IPL_ROM: [u8; IPL_ROM_LEN] = [
     0x5d, 0xd0, 0xdb, 0x1f, 0x00, 0x00, 0xc0, 0xff,
 ];
 
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Apu {
     ram: Box<[u8]>,
     ipl_rom: Box<[u8]>,
@@ -23,8 +57,102 @@ pub struct Apu {
 
     timers: [Timer; 3],
 
+    silence_detector: Option<SilenceDetector>,
+
     is_ipl_rom_enabled: bool,
-    dsp_reg_address: u8
+    dsp_reg_address: u8,
+
+    // What the smp last wrote to $f4-$f7. What it reads there is whatever the host last wrote, which lives in
+    //  ram (that's also where spc files keep it).
+    output_ports: [u8; 4]
+}
+
+// The smp and dsp point back at the apu they belong to, so it's only ever handed out boxed. Deserializing goes
+//  through a plain copy of its fields, and the pointers are wired up again once the new apu is in place.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct ApuFields {
+    ram: Box<[u8]>,
+    ipl_rom: Box<[u8]>,
+
+    smp: Option<Box<Smp>>,
+    dsp: Option<Box<Dsp>>,
+
+    timers: [Timer; 3],
+
+    silence_detector: Option<SilenceDetector>,
+
+    is_ipl_rom_enabled: bool,
+    dsp_reg_address: u8,
+
+    #[serde(default)]
+    output_ports: [u8; 4]
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Box<Apu> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Box<Apu>, D::Error> {
+        let fields = ApuFields::deserialize(deserializer)?;
+        if fields.ram.len() != RAM_LEN {
+            return Err(D::Error::invalid_length(fields.ram.len(), &"64kb of ram"));
+        }
+        if fields.ipl_rom.len() != IPL_ROM_LEN {
+            return Err(D::Error::invalid_length(fields.ipl_rom.len(), &"64 bytes of ipl rom"));
+        }
+        if fields.smp.is_none() {
+            return Err(D::Error::missing_field("smp"));
+        }
+        match fields.dsp {
+            Some(ref dsp) if dsp.voices.len() != NUM_VOICES => {
+                return Err(D::Error::invalid_length(dsp.voices.len(), &"8 voices"));
+            },
+            None => {
+                return Err(D::Error::missing_field("dsp"));
+            },
+            _ => ()
+        }
+
+        let mut ret = Box::new(Apu {
+            ram: fields.ram,
+            ipl_rom: fields.ipl_rom,
+
+            smp: fields.smp,
+            dsp: fields.dsp,
+
+            timers: fields.timers,
+
+            silence_detector: fields.silence_detector,
+
+            is_ipl_rom_enabled: fields.is_ipl_rom_enabled,
+            dsp_reg_address: fields.dsp_reg_address,
+
+            output_ports: fields.output_ports
+        });
+        let ret_ptr = &mut *ret as *mut _;
+        ret.smp.as_mut().unwrap().set_emulator(ret_ptr);
+        ret.dsp.as_mut().unwrap().set_emulator(ret_ptr);
+        Ok(ret)
+    }
+}
+
+// Checks that data holds a complete spc file, without loading it
+pub fn validate_spc_bytes(data: &[u8]) -> Result<(), SpcError> {
+    // Only as much of the magic as there is can be checked before deciding whether data is an spc cut short
+    let magic_len = cmp::min(data.len(), SPC_MAGIC.len());
+    if data[..magic_len] != SPC_MAGIC[..magic_len] {
+        return Err(SpcError::InvalidMagic);
+    }
+    let expected = SPC_IPL_ROM_OFFSET + IPL_ROM_LEN;
+    if data.len() < expected {
+        return Err(SpcError::Truncated { len: data.len(), expected });
+    }
+    if data[SPC_PADDING_OFFSET..SPC_PADDING_OFFSET + 2] != [0x1a, 0x1a] {
+        return Err(SpcError::InvalidMagic);
+    }
+    if &data[SPC_VERSION_OFFSET..SPC_PADDING_OFFSET] != SPC_VERSION {
+        return Err(SpcError::UnsupportedVersion);
+    }
+    Ok(())
 }
 
 impl Apu {
@@ -36,10 +164,14 @@ impl Apu {
             smp: None,
             dsp: None,
 
-            timers: [Timer::new(256), Timer::new(256), Timer::new(32)],
+            timers: [Timer::new(128), Timer::new(128), Timer::new(16)],
+
+            silence_detector: None,
 
             is_ipl_rom_enabled: true,
-            dsp_reg_address: 0
+            dsp_reg_address: 0,
+
+            output_ports: [0; 4]
         });
         let ret_ptr = &mut *ret as *mut _;
         ret.smp = Some(Box::new(Smp::new(ret_ptr)));
@@ -47,49 +179,162 @@ impl Apu {
         ret
     }
 
+    #[cfg(feature = "std")]
     pub fn from_spc(spc: &Spc) -> Box<Apu> {
         let mut ret = Apu::new();
+        ret.load_smp_regs(spc.pc, spc.a, spc.x, spc.y, spc.psw, spc.sp);
+        ret.load(&spc.ram, &spc.regs, &spc.ipl_rom);
+        ret
+    }
 
-        for i in 0..RAM_LEN {
-            ret.ram[i] = spc.ram[i];
-        }
-        for i in 0..IPL_ROM_LEN {
-            ret.ipl_rom[i] = spc.ipl_rom[i];
-        }
+    // Loads the contents of an spc file straight from memory, without going through the spc crate (and so
+    //  without std)
+    pub fn from_spc_bytes(data: &[u8]) -> Result<Box<Apu>, SpcError> {
+        validate_spc_bytes(data)?;
 
-        {
-            let smp = ret.smp.as_mut().unwrap();
-            smp.reg_pc = spc.pc;
-            smp.reg_a = spc.a;
-            smp.reg_x = spc.x;
-            smp.reg_y = spc.y;
-            smp.set_psw(spc.psw);
-            smp.reg_sp = spc.sp;
-        }
+        let mut ret = Apu::new();
+        ret.load_smp_regs((data[0x25] as u16) | ((data[0x26] as u16) << 8), data[0x27], data[0x28], data[0x29], data[0x2a], data[0x2b]);
+        ret.load(
+            &data[SPC_RAM_OFFSET..SPC_RAM_OFFSET + RAM_LEN],
+            &data[SPC_REGS_OFFSET..SPC_REGS_OFFSET + REG_LEN],
+            &data[SPC_IPL_ROM_OFFSET..SPC_IPL_ROM_OFFSET + IPL_ROM_LEN]);
+        Ok(ret)
+    }
+
+    // Reads a whole spc file from reader, eg. a network stream or a file inside an archive
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Box<Apu>, SpcError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Apu::from_spc_bytes(&data)
+    }
+
+    fn load_smp_regs(&mut self, pc: u16, a: u8, x: u8, y: u8, psw: u8, sp: u8) {
+        let smp = self.smp.as_mut().unwrap();
+        smp.reg_pc = pc;
+        smp.reg_a = a;
+        smp.reg_x = x;
+        smp.reg_y = y;
+        smp.set_psw(psw);
+        smp.reg_sp = sp;
+    }
 
-        ret.dsp.as_mut().unwrap().set_state(spc);
+    fn load(&mut self, ram: &[u8], regs: &[u8], ipl_rom: &[u8]) {
+        self.ram.copy_from_slice(ram);
+        self.ipl_rom.copy_from_slice(ipl_rom);
+
+        self.dsp.as_mut().unwrap().load_registers(regs);
 
         for i in 0..3 {
-            let target = ret.ram[0xfa + i];
-            ret.timers[i].set_target(target);
+            let target = self.ram[0xfa + i];
+            self.timers[i].set_target(target);
+        }
+        let control_reg = self.ram[0xf1];
+        self.set_control_reg(control_reg);
+        for i in 0..3 {
+            let counter = self.ram[0xfd + i];
+            self.timers[i].set_counter(counter);
         }
-        let control_reg = ret.ram[0xf1];
-        ret.set_control_reg(control_reg);
-
-        ret.dsp_reg_address = ret.ram[0xf2];
 
-        ret
+        self.dsp_reg_address = self.ram[0xf2];
     }
 
     pub fn render(&mut self, left_buffer: &mut [i16], right_buffer: &mut [i16], num_samples: i32) {
         let smp = self.smp.as_mut().unwrap();
         let dsp = self.dsp.as_mut().unwrap();
         while dsp.output_buffer.get_sample_count() < num_samples {
-            smp.run(num_samples * 64);
+            smp.run(num_samples * CYCLES_PER_SAMPLE);
             dsp.flush();
         }
 
         dsp.output_buffer.read(left_buffer, right_buffer, num_samples);
+
+        if let Some(ref mut detector) = self.silence_detector {
+            if !detector.is_end_of_track() {
+                detector.process(&left_buffer[..num_samples as usize], &right_buffer[..num_samples as usize]);
+                if detector.is_output_silent() && dsp.are_voices_silent() && dsp.is_echo_silent(detector.threshold()) {
+                    detector.end_track();
+                }
+            }
+        }
+    }
+
+    // Fills out with out.len() / 2 stereo frames, left then right, as most audio apis want them
+    pub fn render_interleaved(&mut self, out: &mut [i16]) {
+        self.render_interleaved_with(out, |sample| sample);
+    }
+
+    // As render_interleaved, with samples scaled to [-1, 1)
+    pub fn render_interleaved_f32(&mut self, out: &mut [f32]) {
+        self.render_interleaved_with(out, |sample| (sample as f32) / 32768.0);
+    }
+
+    fn render_interleaved_with<T, F: Fn(i16) -> T>(&mut self, out: &mut [T], convert: F) {
+        let mut left = [0; INTERLEAVED_CHUNK_LEN];
+        let mut right = [0; INTERLEAVED_CHUNK_LEN];
+        for chunk in out.chunks_mut(INTERLEAVED_CHUNK_LEN * 2) {
+            let num_frames = chunk.len() / 2;
+            if num_frames == 0 {
+                break;
+            }
+            self.render(&mut left, &mut right, num_frames as i32);
+            for (i, frame) in chunk.chunks_exact_mut(2).enumerate() {
+                frame[0] = convert(left[i]);
+                frame[1] = convert(right[i]);
+            }
+        }
+    }
+
+    pub fn set_silence_detector(&mut self, silence_detector: Option<SilenceDetector>) {
+        self.silence_detector = silence_detector;
+    }
+
+    pub fn silence_detector(&self) -> Option<&SilenceDetector> {
+        self.silence_detector.as_ref()
+    }
+
+    pub fn is_end_of_track(&self) -> bool {
+        match self.silence_detector {
+            Some(ref detector) => detector.is_end_of_track(),
+            _ => false
+        }
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    // The host (snes cpu) side of the four ports at $f4-$f7
+    pub fn write_port(&mut self, port: usize, value: u8) {
+        self.ram[0xf4 + (port & 3)] = value;
+    }
+
+    pub fn read_port(&self, port: usize) -> u8 {
+        self.output_ports[port & 3]
+    }
+
+    pub fn timers(&self) -> &[Timer] {
+        &self.timers
+    }
+
+    pub fn smp_state(&self) -> SmpState {
+        self.smp.as_ref().unwrap().state()
+    }
+
+    pub fn dsp_state(&self) -> DspState {
+        self.dsp.as_ref().unwrap().state()
+    }
+
+    pub fn voice_state(&self, index: usize) -> VoiceState {
+        self.dsp.as_ref().unwrap().voices[index].state()
+    }
+
+    pub fn timer_state(&self, index: usize) -> TimerState {
+        self.timers[index].state()
     }
 
     pub fn cpu_cycles_callback(&mut self, num_cycles: i32) {
@@ -132,7 +377,8 @@ impl Apu {
                 0xf2 => { self.dsp_reg_address = value; },
                 0xf3 => { self.dsp.as_mut().unwrap().set_register(self.dsp_reg_address, value); },
 
-                0xf4 ... 0xf9 => { self.ram[address as usize] = value; },
+                0xf4 ... 0xf7 => { self.output_ports[(address - 0xf4) as usize] = value; },
+                0xf8 | 0xf9 => { self.ram[address as usize] = value; },
 
                 0xfa => { self.timers[0].set_target(value); },
                 0xfb => { self.timers[1].set_target(value); },
@@ -147,13 +393,13 @@ impl Apu {
 
     pub fn clear_echo_buffer(&mut self) {
         let dsp = self.dsp.as_mut().unwrap();
-        let length = dsp.calculate_echo_length();
-        let mut end_addr = dsp.get_echo_start_address() as i32 + length;
-        if end_addr > RAM_LEN as i32 {
-            end_addr = RAM_LEN as i32;
+        let mut length = dsp.calculate_echo_length();
+        if length == 0 {
+            length = 4;
         }
-        for i in dsp.get_echo_start_address() as i32..end_addr {
-            self.ram[i as usize] = 0xff;
+        let start_address = dsp.get_echo_start_address();
+        for i in 0..length {
+            self.ram[start_address.wrapping_add(i as u16) as usize] = 0xff;
         }
     }
 
@@ -164,13 +410,14 @@ impl Apu {
 
     fn set_control_reg(&mut self, value: u8) {
         self.is_ipl_rom_enabled = (value & 0x80) != 0;
+        // These clear the input latches, which live in ram; writes through the bus would go to the output ports
         if (value & 0x20) != 0 {
-            self.write_u8(0xf6, 0x00);
-            self.write_u8(0xf7, 0x00);
+            self.ram[0xf6] = 0x00;
+            self.ram[0xf7] = 0x00;
         }
         if (value & 0x10) != 0 {
-            self.write_u8(0xf4, 0x00);
-            self.write_u8(0xf5, 0x00);
+            self.ram[0xf4] = 0x00;
+            self.ram[0xf5] = 0x00;
         }
         self.timers[0].set_start_stop_bit((value & 0x01) != 0);
         self.timers[1].set_start_stop_bit((value & 0x02) != 0);
@@ -0,0 +1,1068 @@
+// SPC700 assembler and disassembler, for test programs and small drivers. The syntax is the usual one:
+//
+//  start:      mov a, #$12         ; labels end in a colon, comments start with a semicolon
+//              mov $12, a          ; direct page
+//              mov !$0312, a       ; absolute, forced with ! when the address would fit in the direct page
+//              mov a, table+x      ; indexed, also (x), (x)+, (y), [dp+x], [dp]+y and [!abs+x]
+//              set1 $12.3          ; direct page bit
+//              mov1 c, !$1234.5    ; absolute bit (13-bit address), negated with /: and1 c, /$1234.5
+//              bne start
+//  count = 4                       ; constants
+//              .org $0400          ; also .db/.byte, .dw/.word and .ds
+//  table:      .db 1, 2, count, "text"
+//
+// Expressions are numbers ($hex, 0xhex, %binary, decimal or 'c'), symbols and * for the current address,
+//  combined with + - * / and parentheses, and < or > for the low or high byte. An operand that refers to a
+//  symbol not yet defined is assembled as absolute where there's a choice, so sizes are settled in one pass.
+
+use super::apu::Apu;
+use super::error::AsmError;
+use super::host::UploadBlock;
+
+use std::boxed::Box;
+use std::collections::BTreeMap;
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Mode {
+    A,
+    X,
+    Y,
+    Sp,
+    Psw,
+    Ya,
+    C,
+    Imm,
+    Dp,
+    DpX,
+    DpY,
+    Abs,
+    AbsX,
+    AbsY,
+    IndX,
+    IndXInc,
+    IndY,
+    DpXInd,
+    DpIndY,
+    AbsXInd,
+    // The bit number is part of the opcode
+    DpBit(u8),
+    MemBit,
+    NotMemBit,
+    Rel,
+    Tcall(u8),
+    Upage
+}
+
+use self::Mode::*;
+
+static OPCODES: [(&str, &[Mode]); 256] = [
+    ("nop", &[]),
+    ("tcall", &[Tcall(0)]),
+    ("set1", &[DpBit(0)]),
+    ("bbs", &[DpBit(0), Rel]),
+    ("or", &[A, Dp]),
+    ("or", &[A, Abs]),
+    ("or", &[A, IndX]),
+    ("or", &[A, DpXInd]),
+    ("or", &[A, Imm]),
+    ("or", &[Dp, Dp]),
+    ("or1", &[C, MemBit]),
+    ("asl", &[Dp]),
+    ("asl", &[Abs]),
+    ("push", &[Psw]),
+    ("tset1", &[Abs]),
+    ("brk", &[]),
+
+    ("bpl", &[Rel]),
+    ("tcall", &[Tcall(1)]),
+    ("clr1", &[DpBit(0)]),
+    ("bbc", &[DpBit(0), Rel]),
+    ("or", &[A, DpX]),
+    ("or", &[A, AbsX]),
+    ("or", &[A, AbsY]),
+    ("or", &[A, DpIndY]),
+    ("or", &[Dp, Imm]),
+    ("or", &[IndX, IndY]),
+    ("decw", &[Dp]),
+    ("asl", &[DpX]),
+    ("asl", &[A]),
+    ("dec", &[X]),
+    ("cmp", &[X, Abs]),
+    ("jmp", &[AbsXInd]),
+
+    ("clrp", &[]),
+    ("tcall", &[Tcall(2)]),
+    ("set1", &[DpBit(1)]),
+    ("bbs", &[DpBit(1), Rel]),
+    ("and", &[A, Dp]),
+    ("and", &[A, Abs]),
+    ("and", &[A, IndX]),
+    ("and", &[A, DpXInd]),
+    ("and", &[A, Imm]),
+    ("and", &[Dp, Dp]),
+    ("or1", &[C, NotMemBit]),
+    ("rol", &[Dp]),
+    ("rol", &[Abs]),
+    ("push", &[A]),
+    ("cbne", &[Dp, Rel]),
+    ("bra", &[Rel]),
+
+    ("bmi", &[Rel]),
+    ("tcall", &[Tcall(3)]),
+    ("clr1", &[DpBit(1)]),
+    ("bbc", &[DpBit(1), Rel]),
+    ("and", &[A, DpX]),
+    ("and", &[A, AbsX]),
+    ("and", &[A, AbsY]),
+    ("and", &[A, DpIndY]),
+    ("and", &[Dp, Imm]),
+    ("and", &[IndX, IndY]),
+    ("incw", &[Dp]),
+    ("rol", &[DpX]),
+    ("rol", &[A]),
+    ("inc", &[X]),
+    ("cmp", &[X, Dp]),
+    ("call", &[Abs]),
+
+    ("setp", &[]),
+    ("tcall", &[Tcall(4)]),
+    ("set1", &[DpBit(2)]),
+    ("bbs", &[DpBit(2), Rel]),
+    ("eor", &[A, Dp]),
+    ("eor", &[A, Abs]),
+    ("eor", &[A, IndX]),
+    ("eor", &[A, DpXInd]),
+    ("eor", &[A, Imm]),
+    ("eor", &[Dp, Dp]),
+    ("and1", &[C, MemBit]),
+    ("lsr", &[Dp]),
+    ("lsr", &[Abs]),
+    ("push", &[X]),
+    ("tclr1", &[Abs]),
+    ("pcall", &[Upage]),
+
+    ("bvc", &[Rel]),
+    ("tcall", &[Tcall(5)]),
+    ("clr1", &[DpBit(2)]),
+    ("bbc", &[DpBit(2), Rel]),
+    ("eor", &[A, DpX]),
+    ("eor", &[A, AbsX]),
+    ("eor", &[A, AbsY]),
+    ("eor", &[A, DpIndY]),
+    ("eor", &[Dp, Imm]),
+    ("eor", &[IndX, IndY]),
+    ("cmpw", &[Ya, Dp]),
+    ("lsr", &[DpX]),
+    ("lsr", &[A]),
+    ("mov", &[X, A]),
+    ("cmp", &[Y, Abs]),
+    ("jmp", &[Abs]),
+
+    ("clrc", &[]),
+    ("tcall", &[Tcall(6)]),
+    ("set1", &[DpBit(3)]),
+    ("bbs", &[DpBit(3), Rel]),
+    ("cmp", &[A, Dp]),
+    ("cmp", &[A, Abs]),
+    ("cmp", &[A, IndX]),
+    ("cmp", &[A, DpXInd]),
+    ("cmp", &[A, Imm]),
+    ("cmp", &[Dp, Dp]),
+    ("and1", &[C, NotMemBit]),
+    ("ror", &[Dp]),
+    ("ror", &[Abs]),
+    ("push", &[Y]),
+    ("dbnz", &[Dp, Rel]),
+    ("ret", &[]),
+
+    ("bvs", &[Rel]),
+    ("tcall", &[Tcall(7)]),
+    ("clr1", &[DpBit(3)]),
+    ("bbc", &[DpBit(3), Rel]),
+    ("cmp", &[A, DpX]),
+    ("cmp", &[A, AbsX]),
+    ("cmp", &[A, AbsY]),
+    ("cmp", &[A, DpIndY]),
+    ("cmp", &[Dp, Imm]),
+    ("cmp", &[IndX, IndY]),
+    ("addw", &[Ya, Dp]),
+    ("ror", &[DpX]),
+    ("ror", &[A]),
+    ("mov", &[A, X]),
+    ("cmp", &[Y, Dp]),
+    ("reti", &[]),
+
+    ("setc", &[]),
+    ("tcall", &[Tcall(8)]),
+    ("set1", &[DpBit(4)]),
+    ("bbs", &[DpBit(4), Rel]),
+    ("adc", &[A, Dp]),
+    ("adc", &[A, Abs]),
+    ("adc", &[A, IndX]),
+    ("adc", &[A, DpXInd]),
+    ("adc", &[A, Imm]),
+    ("adc", &[Dp, Dp]),
+    ("eor1", &[C, MemBit]),
+    ("dec", &[Dp]),
+    ("dec", &[Abs]),
+    ("mov", &[Y, Imm]),
+    ("pop", &[Psw]),
+    ("mov", &[Dp, Imm]),
+
+    ("bcc", &[Rel]),
+    ("tcall", &[Tcall(9)]),
+    ("clr1", &[DpBit(4)]),
+    ("bbc", &[DpBit(4), Rel]),
+    ("adc", &[A, DpX]),
+    ("adc", &[A, AbsX]),
+    ("adc", &[A, AbsY]),
+    ("adc", &[A, DpIndY]),
+    ("adc", &[Dp, Imm]),
+    ("adc", &[IndX, IndY]),
+    ("subw", &[Ya, Dp]),
+    ("dec", &[DpX]),
+    ("dec", &[A]),
+    ("mov", &[X, Sp]),
+    ("div", &[Ya, X]),
+    ("xcn", &[A]),
+
+    ("ei", &[]),
+    ("tcall", &[Tcall(10)]),
+    ("set1", &[DpBit(5)]),
+    ("bbs", &[DpBit(5), Rel]),
+    ("sbc", &[A, Dp]),
+    ("sbc", &[A, Abs]),
+    ("sbc", &[A, IndX]),
+    ("sbc", &[A, DpXInd]),
+    ("sbc", &[A, Imm]),
+    ("sbc", &[Dp, Dp]),
+    ("mov1", &[C, MemBit]),
+    ("inc", &[Dp]),
+    ("inc", &[Abs]),
+    ("cmp", &[Y, Imm]),
+    ("pop", &[A]),
+    ("mov", &[IndXInc, A]),
+
+    ("bcs", &[Rel]),
+    ("tcall", &[Tcall(11)]),
+    ("clr1", &[DpBit(5)]),
+    ("bbc", &[DpBit(5), Rel]),
+    ("sbc", &[A, DpX]),
+    ("sbc", &[A, AbsX]),
+    ("sbc", &[A, AbsY]),
+    ("sbc", &[A, DpIndY]),
+    ("sbc", &[Dp, Imm]),
+    ("sbc", &[IndX, IndY]),
+    ("movw", &[Ya, Dp]),
+    ("inc", &[DpX]),
+    ("inc", &[A]),
+    ("mov", &[Sp, X]),
+    ("das", &[A]),
+    ("mov", &[A, IndXInc]),
+
+    ("di", &[]),
+    ("tcall", &[Tcall(12)]),
+    ("set1", &[DpBit(6)]),
+    ("bbs", &[DpBit(6), Rel]),
+    ("mov", &[Dp, A]),
+    ("mov", &[Abs, A]),
+    ("mov", &[IndX, A]),
+    ("mov", &[DpXInd, A]),
+    ("cmp", &[X, Imm]),
+    ("mov", &[Abs, X]),
+    ("mov1", &[MemBit, C]),
+    ("mov", &[Dp, Y]),
+    ("mov", &[Abs, Y]),
+    ("mov", &[X, Imm]),
+    ("pop", &[X]),
+    ("mul", &[Ya]),
+
+    ("bne", &[Rel]),
+    ("tcall", &[Tcall(13)]),
+    ("clr1", &[DpBit(6)]),
+    ("bbc", &[DpBit(6), Rel]),
+    ("mov", &[DpX, A]),
+    ("mov", &[AbsX, A]),
+    ("mov", &[AbsY, A]),
+    ("mov", &[DpIndY, A]),
+    ("mov", &[Dp, X]),
+    ("mov", &[DpY, X]),
+    ("movw", &[Dp, Ya]),
+    ("mov", &[DpX, Y]),
+    ("dec", &[Y]),
+    ("mov", &[A, Y]),
+    ("cbne", &[DpX, Rel]),
+    ("daa", &[A]),
+
+    ("clrv", &[]),
+    ("tcall", &[Tcall(14)]),
+    ("set1", &[DpBit(7)]),
+    ("bbs", &[DpBit(7), Rel]),
+    ("mov", &[A, Dp]),
+    ("mov", &[A, Abs]),
+    ("mov", &[A, IndX]),
+    ("mov", &[A, DpXInd]),
+    ("mov", &[A, Imm]),
+    ("mov", &[X, Abs]),
+    ("not1", &[MemBit]),
+    ("mov", &[Y, Dp]),
+    ("mov", &[Y, Abs]),
+    ("notc", &[]),
+    ("pop", &[Y]),
+    ("sleep", &[]),
+
+    ("beq", &[Rel]),
+    ("tcall", &[Tcall(15)]),
+    ("clr1", &[DpBit(7)]),
+    ("bbc", &[DpBit(7), Rel]),
+    ("mov", &[A, DpX]),
+    ("mov", &[A, AbsX]),
+    ("mov", &[A, AbsY]),
+    ("mov", &[A, DpIndY]),
+    ("mov", &[X, Dp]),
+    ("mov", &[X, DpY]),
+    ("mov", &[Dp, Dp]),
+    ("mov", &[Y, DpX]),
+    ("inc", &[Y]),
+    ("mov", &[Y, A]),
+    ("dbnz", &[Y, Rel]),
+    ("stop", &[]),
+];
+
+fn mode_len(mode: Mode) -> usize {
+    match mode {
+        Imm | Dp | DpX | DpY | DpXInd | DpIndY | DpBit(_) | Rel | Upage => 1,
+        Abs | AbsX | AbsY | AbsXInd | MemBit | NotMemBit => 2,
+        _ => 0
+    }
+}
+
+// Where each operand's bytes start, counting the opcode. When an instruction has two operands with bytes the
+//  second comes first (mov dp, #imm is 8f imm dp), except for branches, which keep the offset last.
+fn operand_offsets(modes: &[Mode]) -> Vec<usize> {
+    let mut ret = Vec::new();
+    let mut offset = 1;
+    for &mode in modes.iter() {
+        ret.push(offset);
+        offset += mode_len(mode);
+    }
+    if modes.len() == 2 && mode_len(modes[0]) != 0 && mode_len(modes[1]) != 0 && modes[1] != Rel {
+        ret = vec![1 + mode_len(modes[1]), 1];
+    }
+    ret
+}
+
+pub fn instruction_len(opcode: u8) -> usize {
+    1 + OPCODES[opcode as usize].1.iter().map(|&mode| mode_len(mode)).sum::<usize>()
+}
+
+// Disassembles the instruction at the start of data, which is at address. Returns its text and length;
+//  bytes past the end of data are taken to be zero.
+pub fn disassemble_instruction(data: &[u8], address: u16) -> (String, usize) {
+    let byte = |i: usize| data.get(i).cloned().unwrap_or(0);
+    let word = |i: usize| (byte(i) as u16) | ((byte(i + 1) as u16) << 8);
+
+    let opcode = byte(0);
+    let (mnemonic, modes) = OPCODES[opcode as usize];
+    let len = instruction_len(opcode);
+    let next = address.wrapping_add(len as u16);
+
+    let mut operands = Vec::new();
+    for (&mode, offset) in modes.iter().zip(operand_offsets(modes)) {
+        operands.push(match mode {
+            A => "a".to_string(),
+            X => "x".to_string(),
+            Y => "y".to_string(),
+            Sp => "sp".to_string(),
+            Psw => "psw".to_string(),
+            Ya => "ya".to_string(),
+            C => "c".to_string(),
+            Imm => format!("#${:02x}", byte(offset)),
+            Dp => format!("${:02x}", byte(offset)),
+            DpX => format!("${:02x}+x", byte(offset)),
+            DpY => format!("${:02x}+y", byte(offset)),
+            Abs => format!("!${:04x}", word(offset)),
+            AbsX => format!("!${:04x}+x", word(offset)),
+            AbsY => format!("!${:04x}+y", word(offset)),
+            IndX => "(x)".to_string(),
+            IndXInc => "(x)+".to_string(),
+            IndY => "(y)".to_string(),
+            DpXInd => format!("[${:02x}+x]", byte(offset)),
+            DpIndY => format!("[${:02x}]+y", byte(offset)),
+            AbsXInd => format!("[!${:04x}+x]", word(offset)),
+            DpBit(bit) => format!("${:02x}.{}", byte(offset), bit),
+            MemBit => format!("!${:04x}.{}", word(offset) & 0x1fff, word(offset) >> 13),
+            NotMemBit => format!("/!${:04x}.{}", word(offset) & 0x1fff, word(offset) >> 13),
+            Rel => format!("${:04x}", next.wrapping_add(byte(offset) as i8 as u16)),
+            Tcall(n) => format!("{}", n),
+            Upage => format!("${:02x}", byte(offset))
+        });
+    }
+
+    let text = if operands.is_empty() {
+        mnemonic.to_string()
+    } else {
+        format!("{} {}", mnemonic, operands.join(", "))
+    };
+    (text, len)
+}
+
+// Disassembles all of data, which starts at address, one instruction per line
+pub fn disassemble(data: &[u8], address: u16) -> Vec<(u16, String)> {
+    let mut ret = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let instruction_address = address.wrapping_add(offset as u16);
+        let (text, len) = disassemble_instruction(&data[offset..], instruction_address);
+        ret.push((instruction_address, text));
+        offset += len;
+    }
+    ret
+}
+
+pub struct Assembly {
+    // One block per .org, ready to copy into ram or hand to an IplUploader
+    pub blocks: Vec<UploadBlock>,
+    // Labels and constants
+    pub symbols: BTreeMap<String, u16>
+}
+
+impl Assembly {
+    pub fn symbol(&self, name: &str) -> Option<u16> {
+        self.symbols.get(name).cloned()
+    }
+
+    pub fn load_into(&self, apu: &mut Apu) {
+        let ram = apu.ram_mut();
+        for block in self.blocks.iter() {
+            let address = block.address as usize;
+            ram[address..address + block.data.len()].copy_from_slice(&block.data);
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Expr {
+    Number(i64),
+    Symbol(String),
+    Pc,
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    Neg(Box<Expr>),
+    Low(Box<Expr>),
+    High(Box<Expr>)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Operand {
+    Register(Mode),
+    Immediate(Expr),
+    // The flag is set when the address is forced to absolute with !
+    Direct(Expr, bool),
+    Indexed(Expr, bool, Mode),
+    IndexedIndirect(Expr, bool),
+    IndirectIndexed(Expr),
+    Bit(Expr, Expr, bool),
+    // (x), (x)+ or (y)
+    Indirect(Mode)
+}
+
+enum DataItem {
+    Value(Expr),
+    Bytes(Vec<u8>)
+}
+
+enum Statement {
+    Label(String),
+    Constant(String, Expr),
+    Org(Expr),
+    Bytes(Vec<DataItem>),
+    Words(Vec<Expr>),
+    Space(Expr, Option<Expr>),
+    Instruction(String, Vec<Operand>)
+}
+
+struct Assembler {
+    symbols: BTreeMap<String, i64>,
+    pc: i64,
+    blocks: Vec<UploadBlock>,
+    // Chosen in the first pass, by statement index
+    opcodes: BTreeMap<usize, u8>
+}
+
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let mut statements = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        for statement in parse_line(line, i + 1)? {
+            statements.push((i + 1, statement));
+        }
+    }
+
+    let mut assembler = Assembler {
+        symbols: BTreeMap::new(),
+        pc: 0,
+        blocks: Vec::new(),
+        opcodes: BTreeMap::new()
+    };
+    assembler.pass(&statements, false)?;
+    assembler.pass(&statements, true)?;
+
+    Ok(Assembly {
+        blocks: assembler.blocks.into_iter().filter(|block| !block.data.is_empty()).collect(),
+        symbols: assembler.symbols.into_iter().map(|(name, value)| (name, value as u16)).collect()
+    })
+}
+
+impl Assembler {
+    // The first pass settles symbols and instruction sizes, the second emits everything
+    fn pass(&mut self, statements: &[(usize, Statement)], is_final: bool) -> Result<(), AsmError> {
+        self.pc = 0;
+        self.blocks = vec![UploadBlock { address: 0, data: Vec::new() }];
+        for (index, &(line, ref statement)) in statements.iter().enumerate() {
+            match *statement {
+                Statement::Label(ref name) => {
+                    if !is_final {
+                        if self.symbols.contains_key(name) {
+                            return Err(AsmError::DuplicateSymbol { line, name: name.clone() });
+                        }
+                        self.symbols.insert(name.clone(), self.pc);
+                    }
+                },
+                Statement::Constant(ref name, ref expr) => {
+                    if !is_final && self.symbols.contains_key(name) {
+                        return Err(AsmError::DuplicateSymbol { line, name: name.clone() });
+                    }
+                    // Constants that refer forward are only known by the second pass
+                    match self.eval(expr, line) {
+                        Ok(value) => { self.symbols.insert(name.clone(), value); },
+                        Err(e) => if is_final { return Err(e); }
+                    }
+                },
+                Statement::Org(ref expr) => {
+                    let address = self.eval(expr, line)?;
+                    check_range(address, 0, 0xffff, line)?;
+                    self.pc = address;
+                    self.blocks.push(UploadBlock { address: address as u16, data: Vec::new() });
+                },
+                Statement::Bytes(ref items) => {
+                    for item in items.iter() {
+                        match *item {
+                            DataItem::Value(ref expr) => {
+                                let value = self.eval_final(expr, line, is_final)?;
+                                check_range(value, -0x80, 0xff, line)?;
+                                self.emit(&[value as u8], line)?;
+                            },
+                            DataItem::Bytes(ref bytes) => self.emit(bytes, line)?
+                        }
+                    }
+                },
+                Statement::Words(ref exprs) => {
+                    for expr in exprs.iter() {
+                        let value = self.eval_final(expr, line, is_final)?;
+                        check_range(value, -0x8000, 0xffff, line)?;
+                        self.emit(&[value as u8, (value >> 8) as u8], line)?;
+                    }
+                },
+                Statement::Space(ref count, ref fill) => {
+                    let count = self.eval(count, line)?;
+                    check_range(count, 0, 0x10000, line)?;
+                    let fill = match *fill {
+                        Some(ref fill) => self.eval_final(fill, line, is_final)?,
+                        _ => 0
+                    };
+                    check_range(fill, -0x80, 0xff, line)?;
+                    self.emit(&vec![fill as u8; count as usize], line)?;
+                },
+                Statement::Instruction(ref mnemonic, ref operands) => {
+                    let opcode = if is_final {
+                        self.opcodes[&index]
+                    } else {
+                        let opcode = self.choose_opcode(mnemonic, operands, line)?;
+                        self.opcodes.insert(index, opcode);
+                        opcode
+                    };
+                    let bytes = if is_final {
+                        self.encode(opcode, operands, line)?
+                    } else {
+                        vec![0; instruction_len(opcode)]
+                    };
+                    self.emit(&bytes, line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, bytes: &[u8], line: usize) -> Result<(), AsmError> {
+        if self.pc + (bytes.len() as i64) > 0x10000 {
+            return Err(AsmError::OutOfRange { line, value: self.pc + (bytes.len() as i64) });
+        }
+        self.blocks.last_mut().unwrap().data.extend_from_slice(bytes);
+        self.pc += bytes.len() as i64;
+        Ok(())
+    }
+
+    fn eval(&self, expr: &Expr, line: usize) -> Result<i64, AsmError> {
+        Ok(match *expr {
+            Expr::Number(value) => value,
+            Expr::Symbol(ref name) => match self.symbols.get(name) {
+                Some(&value) => value,
+                _ => return Err(AsmError::UndefinedSymbol { line, name: name.clone() })
+            },
+            Expr::Pc => self.pc,
+            Expr::Add(ref a, ref b) => self.eval(a, line)?.wrapping_add(self.eval(b, line)?),
+            Expr::Sub(ref a, ref b) => self.eval(a, line)?.wrapping_sub(self.eval(b, line)?),
+            Expr::Mul(ref a, ref b) => self.eval(a, line)?.wrapping_mul(self.eval(b, line)?),
+            Expr::Div(ref a, ref b) => match self.eval(b, line)? {
+                0 => return Err(AsmError::Syntax { line, message: "Division by zero" }),
+                b => self.eval(a, line)?.wrapping_div(b)
+            },
+            Expr::Neg(ref a) => self.eval(a, line)?.wrapping_neg(),
+            Expr::Low(ref a) => self.eval(a, line)? & 0xff,
+            Expr::High(ref a) => (self.eval(a, line)? >> 8) & 0xff
+        })
+    }
+
+    // Data only needs its value in the second pass
+    fn eval_final(&self, expr: &Expr, line: usize, is_final: bool) -> Result<i64, AsmError> {
+        if is_final {
+            self.eval(expr, line)
+        } else {
+            Ok(0)
+        }
+    }
+
+    // Picks the shortest encoding the operands fit. Values that aren't known yet don't fit the direct page
+    //  unless nothing else does.
+    fn choose_opcode(&self, mnemonic: &str, operands: &[Operand], line: usize) -> Result<u8, AsmError> {
+        if !OPCODES.iter().any(|&(x, _)| x == mnemonic) {
+            return Err(AsmError::UnknownInstruction { line, mnemonic: mnemonic.to_string() });
+        }
+        for &allow_unknown in [false, true].iter() {
+            let mut ret: Option<u8> = None;
+            for (opcode, &(x, modes)) in OPCODES.iter().enumerate() {
+                if x != mnemonic || modes.len() != operands.len() {
+                    continue;
+                }
+                if !operands.iter().zip(modes.iter()).all(|(operand, &mode)| self.fits(operand, mode, allow_unknown)) {
+                    continue;
+                }
+                let is_shorter = match ret {
+                    Some(best) => instruction_len(opcode as u8) < instruction_len(best),
+                    _ => true
+                };
+                if is_shorter {
+                    ret = Some(opcode as u8);
+                }
+            }
+            if let Some(opcode) = ret {
+                return Ok(opcode);
+            }
+        }
+        Err(AsmError::InvalidOperands { line })
+    }
+
+    fn fits(&self, operand: &Operand, mode: Mode, allow_unknown: bool) -> bool {
+        let is_dp = |expr: &Expr, is_forced_abs: bool| {
+            !is_forced_abs && match self.eval(expr, 0) {
+                Ok(value) => (0..0x100).contains(&value),
+                _ => allow_unknown
+            }
+        };
+        match (operand, mode) {
+            (&Operand::Register(x), mode) => x == mode,
+            (&Operand::Indirect(x), mode) => x == mode,
+            (&Operand::Immediate(_), Imm) => true,
+            (&Operand::Direct(ref expr, is_forced_abs), Dp) => is_dp(expr, is_forced_abs),
+            (&Operand::Direct(_, _), Abs) => true,
+            (&Operand::Direct(_, is_forced_abs), Rel) | (&Operand::Direct(_, is_forced_abs), Upage) => !is_forced_abs,
+            (&Operand::Direct(ref expr, is_forced_abs), Tcall(n)) => !is_forced_abs && self.eval(expr, 0).ok() == Some(n as i64),
+            (&Operand::Indexed(ref expr, is_forced_abs, X), DpX) => is_dp(expr, is_forced_abs),
+            (&Operand::Indexed(ref expr, is_forced_abs, Y), DpY) => is_dp(expr, is_forced_abs),
+            (&Operand::Indexed(_, _, X), AbsX) => true,
+            (&Operand::Indexed(_, _, Y), AbsY) => true,
+            (&Operand::IndexedIndirect(ref expr, is_forced_abs), DpXInd) => is_dp(expr, is_forced_abs),
+            (&Operand::IndexedIndirect(_, _), AbsXInd) => true,
+            (Operand::IndirectIndexed(expr), DpIndY) => is_dp(expr, false),
+            (&Operand::Bit(ref expr, ref bit, is_negated), DpBit(n)) => !is_negated && is_dp(expr, false) && self.eval(bit, 0).ok() == Some(n as i64),
+            (&Operand::Bit(_, _, is_negated), MemBit) => !is_negated,
+            (&Operand::Bit(_, _, is_negated), NotMemBit) => is_negated,
+            _ => false
+        }
+    }
+
+    fn encode(&self, opcode: u8, operands: &[Operand], line: usize) -> Result<Vec<u8>, AsmError> {
+        let modes = OPCODES[opcode as usize].1;
+        let len = instruction_len(opcode);
+        let mut ret = vec![0; len];
+        ret[0] = opcode;
+
+        for ((operand, &mode), offset) in operands.iter().zip(modes.iter()).zip(operand_offsets(modes)) {
+            let value = match *operand {
+                Operand::Immediate(ref expr) | Operand::Direct(ref expr, _) | Operand::Indexed(ref expr, _, _) | Operand::IndexedIndirect(ref expr, _) | Operand::IndirectIndexed(ref expr) | Operand::Bit(ref expr, _, _) => self.eval(expr, line)?,
+                _ => 0
+            };
+            let value = match mode {
+                Imm => {
+                    check_range(value, -0x80, 0xff, line)?;
+                    value & 0xff
+                },
+                Dp | DpX | DpY | DpXInd | DpIndY | DpBit(_) => {
+                    check_range(value, 0, 0xff, line)?;
+                    value
+                },
+                Abs | AbsX | AbsY | AbsXInd => {
+                    check_range(value, 0, 0xffff, line)?;
+                    value
+                },
+                MemBit | NotMemBit => {
+                    let bit = match *operand {
+                        Operand::Bit(_, ref bit, _) => self.eval(bit, line)?,
+                        _ => 0
+                    };
+                    check_range(value, 0, 0x1fff, line)?;
+                    check_range(bit, 0, 7, line)?;
+                    value | (bit << 13)
+                },
+                Rel => {
+                    // Branches can wrap around the address space
+                    let next = (self.pc + (len as i64)) & 0xffff;
+                    let offset = ((value - next) & 0xffff) as u16 as i16 as i64;
+                    if !(-0x80..=0x7f).contains(&offset) {
+                        return Err(AsmError::BranchOutOfRange { line, offset });
+                    }
+                    offset & 0xff
+                },
+                Upage => {
+                    if !(0..0x100).contains(&value) && !(0xff00..=0xffff).contains(&value) {
+                        return Err(AsmError::OutOfRange { line, value });
+                    }
+                    value & 0xff
+                },
+                _ => continue
+            };
+            ret[offset] = value as u8;
+            if mode_len(mode) == 2 {
+                ret[offset + 1] = (value >> 8) as u8;
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+fn check_range(value: i64, min: i64, max: i64, line: usize) -> Result<(), AsmError> {
+    if value < min || value > max {
+        return Err(AsmError::OutOfRange { line, value });
+    }
+    Ok(())
+}
+
+fn syntax_error(line: usize, message: &'static str) -> AsmError {
+    AsmError::Syntax { line, message }
+}
+
+fn is_symbol_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.'
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+fn parse_line(line: &str, line_number: usize) -> Result<Vec<Statement>, AsmError> {
+    let mut ret = Vec::new();
+    let mut rest = strip_comment(line).trim();
+
+    // Any number of labels
+    loop {
+        let len = rest.find(|c: char| !is_symbol_char(c)).unwrap_or(rest.len());
+        if len > 0 && rest[len..].starts_with(':') && rest.starts_with(is_symbol_start) {
+            ret.push(Statement::Label(rest[..len].to_string()));
+            rest = rest[len + 1..].trim_start();
+        } else {
+            break;
+        }
+    }
+    if rest.is_empty() {
+        return Ok(ret);
+    }
+
+    let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let (word, args) = (&rest[..len], rest[len..].trim());
+
+    // name = value
+    if args.starts_with('=') && !args.starts_with("==") {
+        if !word.starts_with(is_symbol_start) || !word.chars().all(is_symbol_char) {
+            return Err(syntax_error(line_number, "Invalid constant name"));
+        }
+        ret.push(Statement::Constant(word.to_string(), parse_expr(args[1..].trim(), line_number)?));
+        return Ok(ret);
+    }
+    if let Some(index) = word.find('=') {
+        let name = &word[..index];
+        if name.is_empty() || !name.chars().all(is_symbol_char) {
+            return Err(syntax_error(line_number, "Invalid constant name"));
+        }
+        let value = format!("{} {}", &word[index + 1..], args);
+        ret.push(Statement::Constant(name.to_string(), parse_expr(value.trim(), line_number)?));
+        return Ok(ret);
+    }
+
+    let word = word.to_ascii_lowercase();
+    let statement = match word.trim_start_matches('.') {
+        "org" => Statement::Org(parse_expr(args, line_number)?),
+        "db" | "byte" => {
+            let mut items = Vec::new();
+            for arg in split_args(args) {
+                if arg.starts_with('"') {
+                    if arg.len() < 2 || !arg.ends_with('"') {
+                        return Err(syntax_error(line_number, "Unterminated string"));
+                    }
+                    items.push(DataItem::Bytes(arg[1..arg.len() - 1].bytes().collect()));
+                } else {
+                    items.push(DataItem::Value(parse_expr(arg, line_number)?));
+                }
+            }
+            Statement::Bytes(items)
+        },
+        "dw" | "word" => Statement::Words(split_args(args).into_iter().map(|arg| parse_expr(arg, line_number)).collect::<Result<Vec<_>, _>>()?),
+        "ds" => {
+            let args = split_args(args);
+            match args.len() {
+                1 => Statement::Space(parse_expr(args[0], line_number)?, None),
+                2 => Statement::Space(parse_expr(args[0], line_number)?, Some(parse_expr(args[1], line_number)?)),
+                _ => return Err(syntax_error(line_number, "Expected a count and optional fill value"))
+            }
+        },
+        _ if word.starts_with('.') => return Err(syntax_error(line_number, "Unknown directive")),
+        _ => {
+            let operands = if args.is_empty() {
+                Vec::new()
+            } else {
+                split_args(args).into_iter().map(|arg| parse_operand(arg, line_number)).collect::<Result<Vec<_>, _>>()?
+            };
+            Statement::Instruction(word, operands)
+        }
+    };
+    ret.push(statement);
+    Ok(ret)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut in_char = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' if !in_char => in_string = !in_string,
+            '\'' if !in_string => in_char = !in_char,
+            ';' if !in_string && !in_char => return &line[..i],
+            _ => ()
+        }
+    }
+    line
+}
+
+// Splits on commas outside of strings and character literals
+fn split_args(args: &str) -> Vec<&str> {
+    let mut ret = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut in_char = false;
+    for (i, c) in args.char_indices() {
+        match c {
+            '"' if !in_char => in_string = !in_string,
+            '\'' if !in_string => in_char = !in_char,
+            ',' if !in_string && !in_char => {
+                ret.push(args[start..i].trim());
+                start = i + 1;
+            },
+            _ => ()
+        }
+    }
+    ret.push(args[start..].trim());
+    ret
+}
+
+fn parse_operand(text: &str, line: usize) -> Result<Operand, AsmError> {
+    let compact = text.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    let lower = compact.to_ascii_lowercase();
+    let register = match lower.as_str() {
+        "a" => Some(Operand::Register(A)),
+        "x" => Some(Operand::Register(X)),
+        "y" => Some(Operand::Register(Y)),
+        "sp" => Some(Operand::Register(Sp)),
+        "psw" => Some(Operand::Register(Psw)),
+        "ya" => Some(Operand::Register(Ya)),
+        "c" => Some(Operand::Register(C)),
+        "(x)" => Some(Operand::Indirect(IndX)),
+        "(x)+" => Some(Operand::Indirect(IndXInc)),
+        "(y)" => Some(Operand::Indirect(IndY)),
+        _ => None
+    };
+    if let Some(register) = register {
+        return Ok(register);
+    }
+
+    let address = |text: &str| -> Result<(Expr, bool), AsmError> {
+        match text.strip_prefix('!') {
+            Some(text) => Ok((parse_expr(text, line)?, true)),
+            _ => Ok((parse_expr(text, line)?, false))
+        }
+    };
+
+    if let Some(value) = compact.strip_prefix('#') {
+        return Ok(Operand::Immediate(parse_expr(value, line)?));
+    }
+    if compact.starts_with('[') {
+        if lower.ends_with("]+y") {
+            return Ok(Operand::IndirectIndexed(parse_expr(&compact[1..compact.len() - 3], line)?));
+        }
+        if lower.ends_with("+x]") {
+            let (expr, is_forced_abs) = address(&compact[1..compact.len() - 3])?;
+            return Ok(Operand::IndexedIndirect(expr, is_forced_abs));
+        }
+        return Err(syntax_error(line, "Expected [dp+x], [dp]+y or [!abs+x]"));
+    }
+    if lower.ends_with("+x") || lower.ends_with("+y") {
+        let (expr, is_forced_abs) = address(&compact[..compact.len() - 2])?;
+        let index = if lower.ends_with("+x") { X } else { Y };
+        return Ok(Operand::Indexed(expr, is_forced_abs, index));
+    }
+    let is_negated = compact.starts_with('/');
+    let unnegated = if is_negated { &compact[1..] } else { &compact[..] };
+    if let Some(index) = unnegated.rfind('.') {
+        // A dot can also be part of a symbol name, but a bit number follows it directly
+        let bit = &unnegated[index + 1..];
+        if !bit.is_empty() && !bit.starts_with(is_symbol_start) {
+            let (expr, _) = address(&unnegated[..index])?;
+            return Ok(Operand::Bit(expr, parse_expr(bit, line)?, is_negated));
+        }
+    }
+    if is_negated {
+        return Err(syntax_error(line, "Expected a bit address after /"));
+    }
+    let (expr, is_forced_abs) = address(&compact)?;
+    Ok(Operand::Direct(expr, is_forced_abs))
+}
+
+fn parse_expr(text: &str, line: usize) -> Result<Expr, AsmError> {
+    let chars = text.chars().filter(|c| !c.is_whitespace()).collect::<Vec<_>>();
+    if chars.is_empty() {
+        return Err(syntax_error(line, "Expected an expression"));
+    }
+    let mut parser = ExprParser { chars, pos: 0, line };
+    let ret = parser.sum()?;
+    if parser.pos != parser.chars.len() {
+        return Err(syntax_error(line, "Unexpected characters in expression"));
+    }
+    Ok(ret)
+}
+
+struct ExprParser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize
+}
+
+impl ExprParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn sum(&mut self) -> Result<Expr, AsmError> {
+        let mut ret = self.product()?;
+        loop {
+            match self.peek() {
+                Some('+') => { self.pos += 1; ret = Expr::Add(Box::new(ret), Box::new(self.product()?)); },
+                Some('-') => { self.pos += 1; ret = Expr::Sub(Box::new(ret), Box::new(self.product()?)); },
+                _ => return Ok(ret)
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, AsmError> {
+        let mut ret = self.unary()?;
+        loop {
+            match self.peek() {
+                Some('*') => { self.pos += 1; ret = Expr::Mul(Box::new(ret), Box::new(self.unary()?)); },
+                Some('/') => { self.pos += 1; ret = Expr::Div(Box::new(ret), Box::new(self.unary()?)); },
+                _ => return Ok(ret)
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, AsmError> {
+        match self.peek() {
+            Some('-') => { self.pos += 1; Ok(Expr::Neg(Box::new(self.unary()?))) },
+            Some('<') => { self.pos += 1; Ok(Expr::Low(Box::new(self.unary()?))) },
+            Some('>') => { self.pos += 1; Ok(Expr::High(Box::new(self.unary()?))) },
+            _ => self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, AsmError> {
+        let line = self.line;
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let ret = self.sum()?;
+                if self.peek() != Some(')') {
+                    return Err(syntax_error(line, "Expected )"));
+                }
+                self.pos += 1;
+                Ok(ret)
+            },
+            Some('*') => {
+                self.pos += 1;
+                Ok(Expr::Pc)
+            },
+            Some('\'') => {
+                match (self.chars.get(self.pos + 1).cloned(), self.chars.get(self.pos + 2).cloned()) {
+                    (Some(c), Some('\'')) => {
+                        self.pos += 3;
+                        Ok(Expr::Number(c as i64))
+                    },
+                    _ => Err(syntax_error(line, "Invalid character literal"))
+                }
+            },
+            Some('$') => {
+                self.pos += 1;
+                self.number(16)
+            },
+            Some('%') => {
+                self.pos += 1;
+                self.number(2)
+            },
+            Some('0') if self.chars.get(self.pos + 1).is_some_and(|&c| c == 'x' || c == 'X') => {
+                self.pos += 2;
+                self.number(16)
+            },
+            Some(c) if c.is_ascii_digit() => self.number(10),
+            Some(c) if is_symbol_start(c) => {
+                let start = self.pos;
+                while self.peek().is_some_and(is_symbol_char) {
+                    self.pos += 1;
+                }
+                Ok(Expr::Symbol(self.chars[start..self.pos].iter().collect()))
+            },
+            _ => Err(syntax_error(line, "Expected a number or symbol"))
+        }
+    }
+
+    fn number(&mut self, radix: u32) -> Result<Expr, AsmError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_digit(radix)) {
+            self.pos += 1;
+        }
+        let digits = self.chars[start..self.pos].iter().collect::<String>();
+        match i64::from_str_radix(&digits, radix) {
+            Ok(value) if value <= 0xffffffff => Ok(Expr::Number(value)),
+            _ => Err(syntax_error(self.line, "Invalid number"))
+        }
+    }
+}
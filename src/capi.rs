@@ -0,0 +1,309 @@
+// C interface, enabled with the capi feature and built as a cdylib or staticlib through
+//  `cargo rustc --lib --crate-type`. The build script generates the matching header into OUT_DIR (and into
+//  $SNES_APU_HEADER_DIR if that's set); include/snes_apu.h is a copy of it.
+//
+// Every function returns one of the SNES_APU_* codes below; panics are caught here rather than being allowed to
+//  unwind into the host.
+//
+// The functions taking an apu handle check it for null, but otherwise trust it: it has to have come from
+//  snes_apu_create_from_spc, not have been passed to snes_apu_destroy yet, and not be in use on another thread
+//  at the same time. That's what "a live handle" means in the safety sections below.
+
+use std::os::raw::c_int;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use bincode;
+
+use super::apu::Apu;
+
+pub const SNES_APU_OK: c_int = 0;
+pub const SNES_APU_ERROR_NULL_POINTER: c_int = -1;
+pub const SNES_APU_ERROR_INVALID_SPC: c_int = -2;
+pub const SNES_APU_ERROR_INVALID_ARGUMENT: c_int = -3;
+pub const SNES_APU_ERROR_INVALID_STATE: c_int = -4;
+pub const SNES_APU_ERROR_BUFFER_TOO_SMALL: c_int = -5;
+pub const SNES_APU_ERROR_PANIC: c_int = -6;
+
+// dsp::SAMPLE_RATE, spelled out as cbindgen can only write literals into the header
+pub const SNES_APU_SAMPLE_RATE: u32 = 32000;
+pub const SNES_APU_NUM_VOICES: u32 = 8;
+
+const CHUNK_LEN: usize = 1024;
+
+// Opaque to C
+pub struct SnesApu {
+    apu: Box<Apu>,
+    spc_data: Vec<u8>,
+    position: u64
+}
+
+#[derive(Serialize)]
+struct SavedState<'a> {
+    position: u64,
+    apu: &'a Apu
+}
+
+#[derive(Deserialize)]
+struct LoadedState {
+    position: u64,
+    apu: Box<Apu>
+}
+
+impl SnesApu {
+    fn new(spc_data: Vec<u8>) -> Option<SnesApu> {
+        let apu = match load_apu(&spc_data) {
+            Some(apu) => apu,
+            _ => return None
+        };
+        Some(SnesApu {
+            apu,
+            spc_data,
+            position: 0
+        })
+    }
+
+    fn seek(&mut self, position: u64) {
+        if position < self.position {
+            let mut apu = load_apu(&self.spc_data).unwrap();
+            {
+                let dsp = apu.dsp.as_mut().unwrap();
+                let old_dsp = self.apu.dsp.as_ref().unwrap();
+                for (voice, old_voice) in dsp.voices.iter_mut().zip(old_dsp.voices.iter()) {
+                    voice.is_muted = old_voice.is_muted;
+                    voice.is_solod = old_voice.is_solod;
+                }
+            }
+            self.apu = apu;
+            self.position = 0;
+        }
+        let mut buffer = [0; CHUNK_LEN * 2];
+        while self.position < position {
+            let len = ::std::cmp::min(position - self.position, CHUNK_LEN as u64) as usize;
+            self.apu.render_interleaved(&mut buffer[..len * 2]);
+            self.position += len as u64;
+        }
+    }
+}
+
+fn load_apu(data: &[u8]) -> Option<Box<Apu>> {
+    Apu::from_spc_bytes(data).ok().map(|mut apu| {
+        apu.clear_echo_buffer();
+        apu
+    })
+}
+
+fn guard<F: FnOnce() -> c_int>(f: F) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(ret) => ret,
+        _ => SNES_APU_ERROR_PANIC
+    }
+}
+
+// apu must be null or a live handle
+unsafe fn with_apu<F: FnOnce(&mut SnesApu) -> c_int>(apu: *mut SnesApu, f: F) -> c_int {
+    if apu.is_null() {
+        return SNES_APU_ERROR_NULL_POINTER;
+    }
+    let apu = &mut *apu;
+    guard(|| f(apu))
+}
+
+unsafe fn set_voice_flag<F: FnOnce(&mut SnesApu, usize)>(apu: *mut SnesApu, voice: u32, f: F) -> c_int {
+    with_apu(apu, |apu| {
+        if voice >= SNES_APU_NUM_VOICES {
+            return SNES_APU_ERROR_INVALID_ARGUMENT;
+        }
+        f(apu, voice as usize);
+        SNES_APU_OK
+    })
+}
+
+/// Creates an apu from the contents of an spc file. The data is copied, so it needn't outlive the call.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, and `out` must be valid for writing a pointer. The handle written
+/// to `out` must eventually be freed with `snes_apu_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn snes_apu_create_from_spc(data: *const u8, len: usize, out: *mut *mut SnesApu) -> c_int {
+    if data.is_null() || out.is_null() {
+        return SNES_APU_ERROR_NULL_POINTER;
+    }
+    *out = ptr::null_mut();
+    let data = slice::from_raw_parts(data, len).to_vec();
+    guard(|| {
+        match SnesApu::new(data) {
+            Some(apu) => {
+                *out = Box::into_raw(Box::new(apu));
+                SNES_APU_OK
+            },
+            _ => SNES_APU_ERROR_INVALID_SPC
+        }
+    })
+}
+
+/// Frees an apu.
+///
+/// # Safety
+///
+/// `apu` must be null or a live handle, and mustn't be used again afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn snes_apu_destroy(apu: *mut SnesApu) {
+    if !apu.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(apu))));
+    }
+}
+
+/// Renders `num_frames` stereo frames at 32khz into `out` as interleaved samples.
+///
+/// # Safety
+///
+/// `apu` must be null or a live handle, and `out` must be valid for writing `num_frames * 2` samples. A
+/// `num_frames` too large for that to fit in a `size_t` is rejected with `SNES_APU_ERROR_INVALID_ARGUMENT`
+/// before `out` is touched.
+#[no_mangle]
+pub unsafe extern "C" fn snes_apu_render_i16(apu: *mut SnesApu, out: *mut i16, num_frames: usize) -> c_int {
+    if out.is_null() {
+        return SNES_APU_ERROR_NULL_POINTER;
+    }
+    let out = match num_frames.checked_mul(2) {
+        Some(len) => slice::from_raw_parts_mut(out, len),
+        _ => return SNES_APU_ERROR_INVALID_ARGUMENT
+    };
+    with_apu(apu, |apu| {
+        apu.apu.render_interleaved(out);
+        apu.position += num_frames as u64;
+        SNES_APU_OK
+    })
+}
+
+/// As `snes_apu_render_i16`, with samples scaled to [-1, 1).
+///
+/// # Safety
+///
+/// `apu` must be null or a live handle, and `out` must be valid for writing `num_frames * 2` samples. A
+/// `num_frames` too large for that to fit in a `size_t` is rejected with `SNES_APU_ERROR_INVALID_ARGUMENT`
+/// before `out` is touched.
+#[no_mangle]
+pub unsafe extern "C" fn snes_apu_render_f32(apu: *mut SnesApu, out: *mut f32, num_frames: usize) -> c_int {
+    if out.is_null() {
+        return SNES_APU_ERROR_NULL_POINTER;
+    }
+    let out = match num_frames.checked_mul(2) {
+        Some(len) => slice::from_raw_parts_mut(out, len),
+        _ => return SNES_APU_ERROR_INVALID_ARGUMENT
+    };
+    with_apu(apu, |apu| {
+        apu.apu.render_interleaved_f32(out);
+        apu.position += num_frames as u64;
+        SNES_APU_OK
+    })
+}
+
+/// Mutes or unmutes one of the 8 voices.
+///
+/// # Safety
+///
+/// `apu` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn snes_apu_set_voice_muted(apu: *mut SnesApu, voice: u32, is_muted: bool) -> c_int {
+    set_voice_flag(apu, voice, |apu, voice| apu.apu.dsp.as_mut().unwrap().voices[voice].is_muted = is_muted)
+}
+
+/// Solos one of the 8 voices; while any voice is solo'd, only solo'd voices are heard.
+///
+/// # Safety
+///
+/// `apu` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn snes_apu_set_voice_solo(apu: *mut SnesApu, voice: u32, is_solod: bool) -> c_int {
+    set_voice_flag(apu, voice, |apu, voice| apu.apu.dsp.as_mut().unwrap().voices[voice].is_solod = is_solod)
+}
+
+/// Writes the current playback position, in frames since the spc was loaded, to `out`.
+///
+/// # Safety
+///
+/// `apu` must be null or a live handle, and `out` must be valid for writing a `uint64_t`.
+#[no_mangle]
+pub unsafe extern "C" fn snes_apu_position(apu: *mut SnesApu, out: *mut u64) -> c_int {
+    if out.is_null() {
+        return SNES_APU_ERROR_NULL_POINTER;
+    }
+    with_apu(apu, |apu| {
+        *out = apu.position;
+        SNES_APU_OK
+    })
+}
+
+/// Seeks to a position in frames. Seeking forward emulates up to the target; seeking backward reloads the spc
+/// first, so it costs as much as seeking forward from the start.
+///
+/// # Safety
+///
+/// `apu` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn snes_apu_seek(apu: *mut SnesApu, position: u64) -> c_int {
+    with_apu(apu, |apu| {
+        apu.seek(position);
+        SNES_APU_OK
+    })
+}
+
+/// Saves the complete emulator state into `buffer`, writing its size to `out_len`. Pass a null buffer to query
+/// the required size.
+///
+/// # Safety
+///
+/// `apu` must be null or a live handle, `out_len` must be valid for writing a `size_t`, and `buffer` must be
+/// null or valid for writing `buffer_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn snes_apu_save_state(apu: *mut SnesApu, buffer: *mut u8, buffer_len: usize, out_len: *mut usize) -> c_int {
+    if out_len.is_null() {
+        return SNES_APU_ERROR_NULL_POINTER;
+    }
+    with_apu(apu, |apu| {
+        let state = SavedState {
+            position: apu.position,
+            apu: &apu.apu
+        };
+        let data = match bincode::serialize(&state) {
+            Ok(data) => data,
+            _ => return SNES_APU_ERROR_INVALID_STATE
+        };
+        *out_len = data.len();
+        if buffer.is_null() {
+            return SNES_APU_OK;
+        }
+        if buffer_len < data.len() {
+            return SNES_APU_ERROR_BUFFER_TOO_SMALL;
+        }
+        slice::from_raw_parts_mut(buffer, data.len()).copy_from_slice(&data);
+        SNES_APU_OK
+    })
+}
+
+/// Restores state saved by `snes_apu_save_state`. On failure the apu is left as it was.
+///
+/// # Safety
+///
+/// `apu` must be null or a live handle, and `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn snes_apu_load_state(apu: *mut SnesApu, data: *const u8, len: usize) -> c_int {
+    if data.is_null() {
+        return SNES_APU_ERROR_NULL_POINTER;
+    }
+    let data = slice::from_raw_parts(data, len);
+    with_apu(apu, |apu| {
+        match bincode::deserialize::<LoadedState>(data) {
+            Ok(state) => {
+                apu.apu = state.apu;
+                apu.position = state.position;
+                SNES_APU_OK
+            },
+            _ => SNES_APU_ERROR_INVALID_STATE
+        }
+    })
+}
@@ -1,5 +1,6 @@
 use super::dsp_helpers;
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BrrBlockDecoder {
     pub is_end: bool,
     pub is_looping: bool,
@@ -102,6 +103,10 @@ impl BrrBlockDecoder {
         ret
     }
 
+    pub fn sample_index(&self) -> i32 {
+        self.sample_index
+    }
+
     pub fn is_finished(&self) -> bool {
         self.sample_index >= 16
     }
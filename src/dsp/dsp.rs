@@ -1,14 +1,46 @@
 use super::super::apu::Apu;
-use super::voice::{Voice, ResamplingMode};
+use super::voice::{Voice, VoiceOutput, ResamplingMode};
 use super::filter::Filter;
 use super::ring_buffer::RingBuffer;
-use super::super::spc::spc::{Spc, REG_LEN};
 use super::dsp_helpers;
+use super::super::state::DspState;
+use super::super::silence_detector;
+
+use std::boxed::Box;
+use std::mem;
+use std::vec::Vec;
+
+#[cfg(feature = "std")]
+use super::super::spc::spc::Spc;
 
 pub const SAMPLE_RATE: usize = 32000;
 pub const BUFFER_LEN: usize = SAMPLE_RATE * 2;
 
-const NUM_VOICES: usize = 8;
+pub const NUM_VOICES: usize = 8;
+pub const REG_LEN: usize = 128;
+
+// The smp runs at 1.024MHz, 32 bus cycles per output sample
+pub const CYCLES_PER_SAMPLE: i32 = 32;
+const CLOCKS_PER_SAMPLE: i32 = 32;
+const CYCLES_PER_CLOCK: i32 = CYCLES_PER_SAMPLE / CLOCKS_PER_SAMPLE;
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AccuracyMode {
+    // Each sample is rendered in one go: all voices, then echo, then output
+    Sample,
+    // Voice and echo work is interleaved over the 32 clocks of each sample as on hardware, so register
+    //  writes take effect at the step that actually reads them
+    Clock,
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RegisterWrite {
+    pub sample: u64,
+    pub address: u8,
+    pub value: u8,
+}
 
 const COUNTER_RANGE: i32 = 30720;
 static COUNTER_RATES: [i32; 32] = [
@@ -20,7 +52,9 @@ static COUNTER_OFFSETS: [i32; 32] = [
     1, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040,
     536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 0, 0];
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Dsp {
+    #[cfg_attr(feature = "serde", serde(skip, default = "::std::ptr::null_mut"))]
     emulator: *mut Apu,
 
     pub voices: Vec<Box<Voice>>,
@@ -34,13 +68,22 @@ pub struct Dsp {
     echo_vol_left: u8,
     echo_vol_right: u8,
     noise_clock: u8,
+    is_soft_reset: bool,
+    is_output_muted: bool,
+    new_kon: u8,
+    kon: u8,
+    koff: u8,
+    every_other_sample: bool,
     echo_write_enabled: bool,
     echo_feedback: u8,
     source_dir: u8,
     echo_start_address: u16,
+    latched_echo_start_address: u16,
     echo_delay: u8,
 
     counter: i32,
+    sample_count: u64,
+    register_log: Option<Vec<RegisterWrite>>,
 
     cycles_since_last_flush: i32,
     is_flushing: bool,
@@ -48,7 +91,72 @@ pub struct Dsp {
     echo_pos: i32,
     echo_length: i32,
 
-    resampling_mode: ResamplingMode
+    resampling_mode: ResamplingMode,
+    accuracy_mode: AccuracyMode,
+
+    #[cfg_attr(feature = "serde", serde(with = "super::super::serde_regs"))]
+    regs: [u8; REG_LEN],
+    clock: ClockState
+}
+
+// Values latched between the steps of the per-clock pipeline
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct ClockState {
+    step: i32,
+
+    dir: u8,
+    dir_address: u32,
+    source: u8,
+    pmon: u8,
+    non: u8,
+    eon: u8,
+    is_echo_write_enabled: bool,
+    echo_address: u16,
+
+    main_out: [i32; 2],
+    echo_out: [i32; 2],
+    echo_in: [i32; 2],
+
+    voice_pitches: [i32; NUM_VOICES],
+    voice_samples: [i32; NUM_VOICES],
+    voice_left_outs: [i32; NUM_VOICES],
+    last_voice_out: i32,
+    looped: u8,
+
+    endx_buf: u8,
+    outx_buf: u8,
+    envx_buf: u8
+}
+
+impl ClockState {
+    fn new() -> ClockState {
+        ClockState {
+            step: 0,
+
+            dir: 0,
+            dir_address: 0,
+            source: 0,
+            pmon: 0,
+            non: 0,
+            eon: 0,
+            is_echo_write_enabled: false,
+            echo_address: 0,
+
+            main_out: [0; 2],
+            echo_out: [0; 2],
+            echo_in: [0; 2],
+
+            voice_pitches: [0; NUM_VOICES],
+            voice_samples: [0; NUM_VOICES],
+            voice_left_outs: [0; NUM_VOICES],
+            last_voice_out: 0,
+            looped: 0,
+
+            endx_buf: 0,
+            outx_buf: 0,
+            envx_buf: 0
+        }
+    }
 }
 
 impl Dsp {
@@ -68,13 +176,22 @@ impl Dsp {
             echo_vol_left: 0x9f,
             echo_vol_right: 0x9c,
             noise_clock: 0,
+            is_soft_reset: false,
+            is_output_muted: false,
+            new_kon: 0,
+            kon: 0,
+            koff: 0,
+            every_other_sample: false,
             echo_write_enabled: false,
             echo_feedback: 0,
             source_dir: 0,
             echo_start_address: Dsp::calculate_echo_start_address(0x60),
+            latched_echo_start_address: Dsp::calculate_echo_start_address(0x60),
             echo_delay: 0x0e,
 
             counter: 0,
+            sample_count: 0,
+            register_log: None,
 
             cycles_since_last_flush: 0,
             is_flushing: false,
@@ -83,6 +200,10 @@ impl Dsp {
             echo_length: 0,
 
             resampling_mode: resampling_mode,
+            accuracy_mode: AccuracyMode::Sample,
+
+            regs: [0; REG_LEN],
+            clock: ClockState::new()
         });
         let ret_ptr = &mut *ret as *mut _;
         for _ in 0..NUM_VOICES {
@@ -97,9 +218,30 @@ impl Dsp {
         ret.set_filter_coefficient(0x06, 0x0f);
         ret.set_filter_coefficient(0x07, 0xff);
         ret.set_resampling_mode(ResamplingMode::Gaussian);
+        ret.regs[0x0c] = ret.vol_left;
+        ret.regs[0x1c] = ret.vol_right;
+        ret.regs[0x2c] = ret.echo_vol_left;
+        ret.regs[0x3c] = ret.echo_vol_right;
+        ret.regs[0x6d] = (ret.echo_start_address >> 8) as u8;
+        ret.regs[0x7d] = ret.echo_delay;
+        // FLG powers up with soft reset, mute and echo write disable all set
+        ret.set_flg(0xe0);
+        ret.regs[0x6c] = 0xe0;
+        for i in 0..8 {
+            ret.regs[(i << 4) | 0x0f] = ret.left_filter.coefficients[i];
+        }
         ret
     }
 
+    #[cfg(feature = "serde")]
+    pub fn set_emulator(&mut self, emulator: *mut Apu) {
+        self.emulator = emulator;
+        let dsp = self as *mut _;
+        for voice in self.voices.iter_mut() {
+            voice.set_owners(dsp, emulator);
+        }
+    }
+
     #[inline]
     fn emulator(&self) -> &mut Apu {
         unsafe {
@@ -123,19 +265,95 @@ impl Dsp {
         }
     }
 
+    pub fn accuracy_mode(&self) -> AccuracyMode {
+        self.accuracy_mode
+    }
+
+    pub fn set_accuracy_mode(&mut self, accuracy_mode: AccuracyMode) {
+        if let AccuracyMode::Clock = self.accuracy_mode {
+            // Finish the sample in progress so the per-sample renderer starts on a sample boundary
+            while self.clock.step != 0 {
+                self.run_clock();
+            }
+        }
+        self.accuracy_mode = accuracy_mode;
+    }
+
+    pub fn source_dir(&self) -> u8 {
+        self.source_dir
+    }
+
+    pub fn sample_count(&self) -> u64 {
+        self.sample_count
+    }
+
+    pub fn state(&self) -> DspState {
+        let mut voices = [self.voices[0].state(); NUM_VOICES];
+        for (state, voice) in voices.iter_mut().zip(self.voices.iter()).skip(1) {
+            *state = voice.state();
+        }
+        DspState {
+            regs: self.regs,
+
+            vol_left: self.vol_left,
+            vol_right: self.vol_right,
+            echo_vol_left: self.echo_vol_left,
+            echo_vol_right: self.echo_vol_right,
+            noise_clock: self.noise_clock,
+            noise: self.noise,
+            is_soft_reset: self.is_soft_reset,
+            is_output_muted: self.is_output_muted,
+            kon: self.kon,
+            koff: self.koff,
+
+            source_dir: self.source_dir,
+            echo_write_enabled: self.echo_write_enabled,
+            echo_feedback: self.echo_feedback,
+            echo_start_address: self.echo_start_address,
+            echo_delay: self.echo_delay,
+            echo_pos: self.echo_pos,
+            echo_length: self.echo_length,
+
+            sample_count: self.sample_count,
+
+            voices
+        }
+    }
+
+    pub fn set_register_logging(&mut self, enabled: bool) {
+        self.register_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn take_register_log(&mut self) -> Vec<RegisterWrite> {
+        match self.register_log {
+            Some(ref mut log) => mem::take(log),
+            _ => Vec::new()
+        }
+    }
+
     fn calculate_echo_start_address(value: u8) -> u16 {
         (value as u16) << 8
     }
 
+    #[cfg(feature = "std")]
     pub fn set_state(&mut self, spc: &Spc) {
+        self.load_registers(&spc.regs);
+    }
+
+    pub fn load_registers(&mut self, regs: &[u8]) {
         for i in 0..REG_LEN {
             match i {
                 0x4c | 0x5c => (), // Do nothing
-                _ => { self.set_register(i as u8, spc.regs[i as usize]); }
+                _ => { self.set_register(i as u8, regs[i as usize]); }
             }
         }
 
-        self.set_kon(spc.regs[0x4c]);
+        self.set_kon(regs[0x4c]);
+        self.regs[0x4c] = regs[0x4c];
+        self.regs[0x5c] = regs[0x5c];
+        self.regs[0x7c] = regs[0x7c];
+
+        self.latched_echo_start_address = self.echo_start_address;
     }
 
     pub fn cycles_callback(&mut self, num_cycles: i32) {
@@ -150,78 +368,409 @@ impl Dsp {
         (self.echo_delay as i32) * 0x800
     }
 
-    pub fn flush(&mut self) {
-        self.is_flushing = true;
+    pub fn are_voices_silent(&self) -> bool {
+        self.voices.iter().all(|voice| voice.envelope.is_silent())
+    }
 
-        while self.cycles_since_last_flush > 64 {
-            if !self.read_counter(self.noise_clock as i32) {
-                let feedback = (self.noise << 13) ^ (self.noise << 14);
-                self.noise = (feedback & 0x4000) ^ (self.noise >> 1);
+    pub fn is_echo_silent(&self, threshold: i16) -> bool {
+        if self.echo_vol_left == 0 && self.echo_vol_right == 0 {
+            return true;
+        }
+
+        let ram = self.emulator().ram();
+        let mut length = self.calculate_echo_length();
+        if length == 0 {
+            length = 4;
+        }
+        for i in 0..(length / 2) {
+            let address = self.echo_start_address.wrapping_add((i * 2) as u16) as usize;
+            let sample = (((ram[(address + 1) & 0xffff] as u16) << 8) | (ram[address] as u16)) as i16;
+            if !silence_detector::is_silent(sample, threshold) {
+                return false;
             }
+        }
+        true
+    }
 
-            let mut are_any_voices_solod = false;
-            for voice in self.voices.iter() {
-                if voice.is_solod {
-                    are_any_voices_solod = true;
-                    break;
+    pub fn flush(&mut self) {
+        self.is_flushing = true;
+
+        match self.accuracy_mode {
+            AccuracyMode::Sample => {
+                while self.cycles_since_last_flush >= CYCLES_PER_SAMPLE {
+                    self.run_sample();
+                    self.cycles_since_last_flush -= CYCLES_PER_SAMPLE;
+                }
+            },
+            AccuracyMode::Clock => {
+                while self.cycles_since_last_flush >= CYCLES_PER_CLOCK {
+                    self.run_clock();
+                    self.cycles_since_last_flush -= CYCLES_PER_CLOCK;
                 }
             }
+        }
 
-            let mut left_out = 0;
-            let mut right_out = 0;
-            let mut left_echo_out = 0;
-            let mut right_echo_out = 0;
-            let mut last_voice_out = 0;
-            for voice in self.voices.iter_mut() {
-                let output = voice.render_sample(last_voice_out, self.noise, are_any_voices_solod);
+        self.is_flushing = false;
+    }
 
-                left_out = dsp_helpers::clamp(left_out + output.left_out);
-                right_out = dsp_helpers::clamp(right_out + output.right_out);
+    fn run_sample(&mut self) {
+        if !self.read_counter(self.noise_clock as i32) {
+            let feedback = (self.noise << 13) ^ (self.noise << 14);
+            self.noise = (feedback & 0x4000) ^ (self.noise >> 1);
+        }
 
-                if voice.echo_on {
-                    left_echo_out = dsp_helpers::clamp(left_echo_out + output.left_out);
-                    right_echo_out = dsp_helpers::clamp(right_echo_out + output.right_out);
-                }
+        self.misc_29();
+        self.poll_keys();
+
+        let are_any_voices_solod = self.are_any_voices_solod();
+
+        let mut left_out = 0;
+        let mut right_out = 0;
+        let mut left_echo_out = 0;
+        let mut right_echo_out = 0;
+        let mut last_voice_out = 0;
+        for i in 0..NUM_VOICES {
+            let (key_on, key_off) = self.key_events(i);
+            let voice = &mut self.voices[i];
+            let output = voice.render_sample(last_voice_out, self.noise, key_on, key_off, are_any_voices_solod);
+
+            left_out = dsp_helpers::clamp(left_out + output.left_out);
+            right_out = dsp_helpers::clamp(right_out + output.right_out);
+
+            if voice.echo_on {
+                left_echo_out = dsp_helpers::clamp(left_echo_out + output.left_out);
+                right_echo_out = dsp_helpers::clamp(right_echo_out + output.right_out);
+            }
+
+            last_voice_out = output.last_voice_out;
 
-                last_voice_out = output.last_voice_out;
+            if voice.is_end_reached() {
+                self.regs[0x7c] |= 1 << i;
             }
+            self.regs[(i << 4) | 0x08] = voice.envx();
+            self.regs[(i << 4) | 0x09] = voice.outx();
+        }
 
-            left_out = dsp_helpers::multiply_volume(left_out, self.vol_left);
-            right_out = dsp_helpers::multiply_volume(right_out, self.vol_right);
+        left_out = dsp_helpers::multiply_volume(left_out, self.vol_left);
+        right_out = dsp_helpers::multiply_volume(right_out, self.vol_right);
 
-            let echo_address = (self.echo_start_address + (self.echo_pos as u16)) as u32;
-            let mut left_echo_in = (((((self.emulator().read_u8(echo_address + 1) as i32) << 8) | (self.emulator().read_u8(echo_address) as i32)) as i16) & !1) as i32;
-            let mut right_echo_in = (((((self.emulator().read_u8(echo_address + 3) as i32) << 8) | (self.emulator().read_u8(echo_address + 2) as i32)) as i16) & !1) as i32;
+        let echo_address = self.latched_echo_start_address.wrapping_add(self.echo_pos as u16);
+        let mut left_echo_in = self.read_echo_sample(echo_address);
+        let mut right_echo_in = self.read_echo_sample(echo_address.wrapping_add(2));
 
-            left_echo_in = dsp_helpers::clamp(self.left_filter.next(left_echo_in));
-            right_echo_in = dsp_helpers::clamp(self.right_filter.next(right_echo_in));
+        left_echo_in = dsp_helpers::clamp(self.left_filter.next(left_echo_in));
+        right_echo_in = dsp_helpers::clamp(self.right_filter.next(right_echo_in));
 
-            let left_out = dsp_helpers::clamp(left_out + dsp_helpers::multiply_volume(left_echo_in, self.echo_vol_left)) as i16;
-            let right_out = dsp_helpers::clamp(right_out + dsp_helpers::multiply_volume(right_echo_in, self.echo_vol_right)) as i16;
+        let left_out = dsp_helpers::clamp(left_out + dsp_helpers::multiply_volume(left_echo_in, self.echo_vol_left)) as i16;
+        let right_out = dsp_helpers::clamp(right_out + dsp_helpers::multiply_volume(right_echo_in, self.echo_vol_right)) as i16;
+        if self.is_output_muted {
+            self.output_buffer.write_sample(0, 0);
+        } else {
             self.output_buffer.write_sample(left_out, right_out);
+        }
 
-            if self.echo_write_enabled {
-                left_echo_out = dsp_helpers::clamp(left_echo_out + ((((left_echo_in * ((self.echo_feedback as i8) as i32)) >> 7) as i16) as i32)) & !1;
-                right_echo_out = dsp_helpers::clamp(right_echo_out + ((((right_echo_in * ((self.echo_feedback as i8) as i32)) >> 7) as i16) as i32)) & !1;
+        if self.echo_write_enabled {
+            left_echo_out = dsp_helpers::clamp(left_echo_out + ((((left_echo_in * ((self.echo_feedback as i8) as i32)) >> 7) as i16) as i32)) & !1;
+            right_echo_out = dsp_helpers::clamp(right_echo_out + ((((right_echo_in * ((self.echo_feedback as i8) as i32)) >> 7) as i16) as i32)) & !1;
 
-                self.emulator().write_u8(echo_address + 0, left_echo_out as u8);
-                self.emulator().write_u8(echo_address + 1, (left_echo_out >> 8) as u8);
-                self.emulator().write_u8(echo_address + 2, right_echo_out as u8);
-                self.emulator().write_u8(echo_address + 3, (right_echo_out >> 8) as u8);
-            }
-            if self.echo_pos == 0 {
-                self.echo_length = self.calculate_echo_length();
-            }
-            self.echo_pos += 4;
-            if self.echo_pos >= self.echo_length {
-                self.echo_pos = 0;
-            }
+            self.write_echo_sample(echo_address, left_echo_out);
+            self.write_echo_sample(echo_address.wrapping_add(2), right_echo_out);
+        }
+        self.advance_echo_pos();
+
+        self.counter = (self.counter + 1) % COUNTER_RANGE;
+        self.sample_count += 1;
+    }
+
+    fn are_any_voices_solod(&self) -> bool {
+        self.voices.iter().any(|voice| voice.is_solod)
+    }
 
-            self.counter = (self.counter + 1) % COUNTER_RANGE;
-            self.cycles_since_last_flush -= 64;
+    // Runs a single step of the hardware pipeline. Voice n's steps (V1-V9) are staggered so that its sample
+    //  is computed in V3, output in V4/V5 and its registers are written back in V7-V9, while the echo steps
+    //  (E22-E30) and global register reads happen at the end of the sample.
+    fn run_clock(&mut self) {
+        match self.clock.step {
+            0 => { self.voice_v5(0); self.voice_v2(1); },
+            1 => { self.voice_v6(0); self.voice_v3(1); },
+            2 => { self.voice_v7_v4_v1(0); },
+            3 => { self.voice_v8_v5_v2(0); },
+            4 => { self.voice_v9_v6_v3(0); },
+            5 => { self.voice_v7_v4_v1(1); },
+            6 => { self.voice_v8_v5_v2(1); },
+            7 => { self.voice_v9_v6_v3(1); },
+            8 => { self.voice_v7_v4_v1(2); },
+            9 => { self.voice_v8_v5_v2(2); },
+            10 => { self.voice_v9_v6_v3(2); },
+            11 => { self.voice_v7_v4_v1(3); },
+            12 => { self.voice_v8_v5_v2(3); },
+            13 => { self.voice_v9_v6_v3(3); },
+            14 => { self.voice_v7_v4_v1(4); },
+            15 => { self.voice_v8_v5_v2(4); },
+            16 => { self.voice_v9_v6_v3(4); },
+            17 => { self.voice_v1(0); self.voice_v7(5); self.voice_v4(6); },
+            18 => { self.voice_v8_v5_v2(5); },
+            19 => { self.voice_v9_v6_v3(5); },
+            20 => { self.voice_v1(1); self.voice_v7(6); self.voice_v4(7); },
+            21 => { self.voice_v8(6); self.voice_v5(7); self.voice_v2(0); },
+            22 => { self.voice_v3a(0); self.voice_v9(6); self.voice_v6(7); self.echo_22(); },
+            23 => { self.voice_v7(7); self.echo_23(); },
+            24 => { self.voice_v8(7); },
+            25 => { self.voice_v9(7); },
+            26 => { self.echo_26(); },
+            27 => { self.misc_27(); self.echo_27(); },
+            28 => { self.misc_28(); self.echo_28(); },
+            29 => { self.misc_29(); self.echo_29(); },
+            30 => { self.misc_30(); self.voice_v3c(0); self.echo_30(); },
+            31 => { self.voice_v4(0); self.voice_v1(2); },
+            _ => unreachable!()
         }
 
-        self.is_flushing = false;
+        self.clock.step = (self.clock.step + 1) % CLOCKS_PER_SAMPLE;
+    }
+
+    fn voice_v7_v4_v1(&mut self, voice_index: usize) {
+        self.voice_v7(voice_index);
+        self.voice_v1(voice_index + 3);
+        self.voice_v4(voice_index + 1);
+    }
+
+    fn voice_v8_v5_v2(&mut self, voice_index: usize) {
+        self.voice_v8(voice_index);
+        self.voice_v5(voice_index + 1);
+        self.voice_v2(voice_index + 2);
+    }
+
+    fn voice_v9_v6_v3(&mut self, voice_index: usize) {
+        self.voice_v9(voice_index);
+        self.voice_v6(voice_index + 1);
+        self.voice_v3(voice_index + 2);
+    }
+
+    fn voice_v1(&mut self, voice_index: usize) {
+        // The directory entry address is formed from the previous voice's source number, which is why
+        //  the source number is read a step ahead of the entry
+        self.clock.dir_address = (self.clock.dir as u32) * 0x100 + (self.clock.source as u32) * 4;
+        self.clock.source = self.voices[voice_index].source;
+    }
+
+    fn voice_v2(&mut self, voice_index: usize) {
+        let entry_address = self.clock.dir_address + 2;
+        let mut loop_address = self.emulator().read_u8(entry_address) as u32;
+        loop_address |= (self.emulator().read_u8(entry_address + 1) as u32) << 8;
+        let voice = &mut self.voices[voice_index];
+        voice.set_loop_start_address(loop_address);
+        voice.envelope.latch_adsr0();
+        self.clock.voice_pitches[voice_index] = voice.pitch_low as i32;
+    }
+
+    fn voice_v3(&mut self, voice_index: usize) {
+        self.voice_v3a(voice_index);
+        self.voice_v3c(voice_index);
+    }
+
+    fn voice_v3a(&mut self, voice_index: usize) {
+        self.clock.voice_pitches[voice_index] |= (self.voices[voice_index].pitch() as i32) & 0x3f00;
+    }
+
+    fn voice_v3c(&mut self, voice_index: usize) {
+        let voice_bit = 1 << voice_index;
+        let are_any_voices_solod = self.are_any_voices_solod();
+        let last_voice_out = self.clock.last_voice_out;
+        let pitch_mod = (self.clock.pmon & voice_bit) != 0;
+        let noise_on = (self.clock.non & voice_bit) != 0;
+        let noise = self.noise;
+        let (key_on, key_off) = self.key_events(voice_index);
+
+        let voice = &mut self.voices[voice_index];
+        let pitch = voice.pitch_with_modulation(self.clock.voice_pitches[voice_index], pitch_mod, last_voice_out);
+        self.clock.voice_pitches[voice_index] = pitch;
+
+        let mut sample = voice.next_sample(noise, noise_on, key_on, key_off);
+        if !voice.is_solod && (voice.is_muted || are_any_voices_solod) {
+            sample = 0;
+        }
+        self.clock.voice_samples[voice_index] = sample;
+        self.clock.last_voice_out = sample;
+    }
+
+    fn voice_v4(&mut self, voice_index: usize) {
+        let pitch = self.clock.voice_pitches[voice_index];
+        let voice = &mut self.voices[voice_index];
+        voice.advance(pitch, false);
+        self.clock.looped = if voice.is_end_reached() { 1 << voice_index } else { 0 };
+
+        let left_out = dsp_helpers::multiply_volume(self.clock.voice_samples[voice_index], voice.vol_left);
+        self.clock.voice_left_outs[voice_index] = left_out;
+        self.voice_output(voice_index, 0, left_out);
+    }
+
+    fn voice_v5(&mut self, voice_index: usize) {
+        let sample = self.clock.voice_samples[voice_index];
+        let right_out = dsp_helpers::multiply_volume(sample, self.voices[voice_index].vol_right);
+        self.voice_output(voice_index, 1, right_out);
+
+        let left_out = self.clock.voice_left_outs[voice_index];
+        self.voices[voice_index].output_buffer.write(VoiceOutput {
+            left_out,
+            right_out,
+            last_voice_out: sample
+        });
+
+        self.clock.endx_buf = self.regs[0x7c] | self.clock.looped;
+    }
+
+    fn voice_v6(&mut self, voice_index: usize) {
+        self.clock.outx_buf = self.voices[voice_index].outx();
+    }
+
+    fn voice_v7(&mut self, voice_index: usize) {
+        self.regs[0x7c] = self.clock.endx_buf;
+        self.clock.envx_buf = self.voices[voice_index].envx();
+    }
+
+    fn voice_v8(&mut self, voice_index: usize) {
+        self.regs[(voice_index << 4) | 0x09] = self.clock.outx_buf;
+    }
+
+    fn voice_v9(&mut self, voice_index: usize) {
+        self.regs[(voice_index << 4) | 0x08] = self.clock.envx_buf;
+    }
+
+    fn voice_output(&mut self, voice_index: usize, channel: usize, amount: i32) {
+        self.clock.main_out[channel] = dsp_helpers::clamp(self.clock.main_out[channel] + amount);
+        if (self.clock.eon & (1 << voice_index)) != 0 {
+            self.clock.echo_out[channel] = dsp_helpers::clamp(self.clock.echo_out[channel] + amount);
+        }
+    }
+
+    // The echo buffer is accessed directly in ram (so the io registers and ipl rom never get in the way), and
+    //  all addresses wrap around at $ffff
+    fn read_echo_sample(&self, address: u16) -> i32 {
+        let ram = self.emulator().ram();
+        let low = ram[address as usize] as i32;
+        let high = ram[address.wrapping_add(1) as usize] as i32;
+        ((((high << 8) | low) as i16) & !1) as i32
+    }
+
+    fn write_echo_sample(&mut self, address: u16, value: i32) {
+        let ram = self.emulator().ram_mut();
+        ram[address as usize] = value as u8;
+        ram[address.wrapping_add(1) as usize] = (value >> 8) as u8;
+    }
+
+    fn advance_echo_pos(&mut self) {
+        // ESA is only latched once per sample, and EDL only when the buffer wraps. With an EDL of 0 the length is
+        //  0 as well, so the position always wraps straight back and the buffer is 4 bytes long.
+        self.latched_echo_start_address = self.echo_start_address;
+        if self.echo_pos == 0 {
+            self.echo_length = self.calculate_echo_length();
+        }
+        self.echo_pos += 4;
+        if self.echo_pos >= self.echo_length {
+            self.echo_pos = 0;
+        }
+    }
+
+    fn echo_22(&mut self) {
+        self.clock.echo_address = self.latched_echo_start_address.wrapping_add(self.echo_pos as u16);
+        let echo_address = self.clock.echo_address;
+        let left_echo_in = self.read_echo_sample(echo_address);
+        self.clock.echo_in[0] = dsp_helpers::clamp(self.left_filter.next(left_echo_in));
+    }
+
+    fn echo_23(&mut self) {
+        let echo_address = self.clock.echo_address.wrapping_add(2);
+        let right_echo_in = self.read_echo_sample(echo_address);
+        self.clock.echo_in[1] = dsp_helpers::clamp(self.right_filter.next(right_echo_in));
+    }
+
+    fn echo_output(&self, channel: usize) -> i32 {
+        let (vol, echo_vol) = match channel {
+            0 => (self.vol_left, self.echo_vol_left),
+            _ => (self.vol_right, self.echo_vol_right)
+        };
+        let main_out = dsp_helpers::multiply_volume(self.clock.main_out[channel], vol);
+        dsp_helpers::clamp(main_out + dsp_helpers::multiply_volume(self.clock.echo_in[channel], echo_vol))
+    }
+
+    fn echo_26(&mut self) {
+        // Left output is computed here and held until the right channel is ready
+        self.clock.main_out[0] = self.echo_output(0);
+
+        let echo_feedback = (self.echo_feedback as i8) as i32;
+        for channel in 0..2 {
+            let feedback = (((self.clock.echo_in[channel] * echo_feedback) >> 7) as i16) as i32;
+            self.clock.echo_out[channel] = dsp_helpers::clamp(self.clock.echo_out[channel] + feedback) & !1;
+        }
+    }
+
+    fn echo_27(&mut self) {
+        let left_out = self.clock.main_out[0];
+        let right_out = self.echo_output(1);
+        self.clock.main_out = [0; 2];
+
+        if self.is_output_muted {
+            self.output_buffer.write_sample(0, 0);
+        } else {
+            self.output_buffer.write_sample(left_out as i16, right_out as i16);
+        }
+        self.sample_count += 1;
+    }
+
+    fn echo_28(&mut self) {
+        self.clock.is_echo_write_enabled = self.echo_write_enabled;
+    }
+
+    fn echo_29(&mut self) {
+        self.advance_echo_pos();
+
+        if self.clock.is_echo_write_enabled {
+            let echo_address = self.clock.echo_address;
+            let left_echo_out = self.clock.echo_out[0];
+            self.write_echo_sample(echo_address, left_echo_out);
+        }
+        self.clock.echo_out[0] = 0;
+
+        self.clock.is_echo_write_enabled = self.echo_write_enabled;
+    }
+
+    fn echo_30(&mut self) {
+        if self.clock.is_echo_write_enabled {
+            let echo_address = self.clock.echo_address.wrapping_add(2);
+            let right_echo_out = self.clock.echo_out[1];
+            self.write_echo_sample(echo_address, right_echo_out);
+        }
+        self.clock.echo_out[1] = 0;
+    }
+
+    fn misc_27(&mut self) {
+        self.clock.pmon = self.regs[0x2d] & 0xfe;
+    }
+
+    fn misc_28(&mut self) {
+        self.clock.non = self.regs[0x3d];
+        self.clock.eon = self.regs[0x4d];
+        self.clock.dir = self.source_dir;
+    }
+
+    fn misc_29(&mut self) {
+        // Voices keyed on at the last poll are cleared from KON here, so a KON write is only acted on once
+        self.every_other_sample = !self.every_other_sample;
+        if self.every_other_sample {
+            self.new_kon &= !self.kon;
+        }
+    }
+
+    fn misc_30(&mut self) {
+        self.poll_keys();
+
+        self.counter = (self.counter + 1) % COUNTER_RANGE;
+
+        if !self.read_counter(self.noise_clock as i32) {
+            let feedback = (self.noise << 13) ^ (self.noise << 14);
+            self.noise = (feedback & 0x4000) ^ (self.noise >> 1);
+        }
     }
 
     pub fn set_register(&mut self, address: u8, value: u8) {
@@ -233,6 +782,16 @@ impl Dsp {
             self.flush();
         }
 
+        if let Some(ref mut log) = self.register_log {
+            log.push(RegisterWrite {
+                sample: self.sample_count,
+                address,
+                value
+            });
+        }
+
+        self.regs[address as usize] = value;
+
         let voice_index = address >> 4;
         let voice_address = address & 0x0f;
         if voice_address < 0x0a {
@@ -259,8 +818,9 @@ impl Dsp {
                 0x2c => { self.echo_vol_left = value; },
                 0x3c => { self.echo_vol_right = value; },
                 0x4c => { self.set_kon(value); },
-                0x5c => { self.set_kof(value); },
+                0x5c => (), // Polled along with KON
                 0x6c => { self.set_flg(value); },
+                0x7c => { self.regs[0x7c] = 0; }, // Writing any value clears ENDX
 
                 0x0d => { self.echo_feedback = value; },
 
@@ -281,8 +841,7 @@ impl Dsp {
             self.flush();
         }
 
-        let _ = address;
-        0
+        self.regs[(address & 0x7f) as usize]
     }
 
     pub fn read_counter(&self, rate: i32) -> bool {
@@ -306,22 +865,36 @@ impl Dsp {
     }
 
     fn set_kon(&mut self, voice_mask: u8) {
-        for i in 0..NUM_VOICES {
-            if ((voice_mask as usize) & (1 << i)) != 0 {
-                self.voices[i].key_on();
-            }
+        self.new_kon = voice_mask;
+    }
+
+    // KON and KOFF are only polled every other sample
+    fn poll_keys(&mut self) {
+        if self.every_other_sample {
+            self.kon = self.new_kon;
+            self.koff = self.regs[0x5c];
         }
     }
 
-    fn set_kof(&mut self, voice_mask: u8) {
-        for i in 0..NUM_VOICES {
-            if ((voice_mask as usize) & (1 << i)) != 0 {
-                self.voices[i].key_off();
-            }
+    fn key_events(&mut self, voice_index: usize) -> (bool, bool) {
+        if !self.every_other_sample {
+            return (false, false);
+        }
+        let voice_bit = 1 << voice_index;
+        let key_on = (self.kon & voice_bit) != 0;
+        if key_on {
+            self.regs[0x7c] &= !voice_bit;
         }
+        (key_on, (self.koff & voice_bit) != 0)
+    }
+
+    pub fn is_soft_reset(&self) -> bool {
+        self.is_soft_reset
     }
 
     fn set_flg(&mut self, value: u8) {
+        self.is_soft_reset = (value & 0x80) != 0;
+        self.is_output_muted = (value & 0x40) != 0;
         self.noise_clock = value & 0x1f;
         self.echo_write_enabled = (value & 0x20) == 0;
     }
@@ -1,18 +1,23 @@
 use super::dsp::Dsp;
 
-enum Mode {
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Mode {
     Attack,
     Decay,
     Sustain,
     Release
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Envelope {
+    #[cfg_attr(feature = "serde", serde(skip, default = "::std::ptr::null_mut"))]
     dsp: *mut Dsp,
 
     pub adsr0: u8,
     pub adsr1: u8,
     pub gain: u8,
+    latched_adsr0: u8,
 
     mode: Mode,
     pub level: i32,
@@ -27,6 +32,7 @@ impl Envelope {
             adsr0: 0,
             adsr1: 0,
             gain: 0,
+            latched_adsr0: 0,
 
             mode: Mode::Release,
             level: 0,
@@ -34,6 +40,11 @@ impl Envelope {
         }
     }
 
+    #[cfg(feature = "serde")]
+    pub fn set_dsp(&mut self, dsp: *mut Dsp) {
+        self.dsp = dsp;
+    }
+
     #[inline]
     fn dsp(&self) -> &mut Dsp {
         unsafe {
@@ -47,10 +58,32 @@ impl Envelope {
         self.hidden_level = 0;
     }
 
+    pub fn hold(&mut self) {
+        self.level = 0;
+        self.hidden_level = 0;
+    }
+
     pub fn key_off(&mut self) {
         self.mode = Mode::Release;
     }
 
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // ADSR0 is read once per sample ahead of the envelope update, while ADSR1 and GAIN are read as the update
+    //  happens
+    pub fn latch_adsr0(&mut self) {
+        self.latched_adsr0 = self.adsr0;
+    }
+
+    pub fn is_silent(&self) -> bool {
+        match self.mode {
+            Mode::Release => self.level == 0,
+            _ => false
+        }
+    }
+
     pub fn tick(&mut self) {
         let mut env = self.level;
         match self.mode {
@@ -63,12 +96,13 @@ impl Envelope {
             },
             _ => {
                 let rate: i32;
-                let env_data = self.adsr1 as i32;
-                if (self.adsr0 & 0x80) != 0 {
+                let mut env_data = self.adsr1 as i32;
+                let adsr0 = self.latched_adsr0 as i32;
+                if (adsr0 & 0x80) != 0 {
                     // Adsr mode
                     match self.mode {
                         Mode::Attack => {
-                            rate = ((self.adsr0 as i32) & 0x0f) * 2 + 1;
+                            rate = (adsr0 & 0x0f) * 2 + 1;
                             env += if rate < 31 { 0x20 } else { 0x400 };
                         },
                         _ => {
@@ -76,7 +110,7 @@ impl Envelope {
                             env -= env >> 8;
                             match self.mode {
                                 Mode::Decay => {
-                                    rate = (((self.adsr0 as i32) >> 3) & 0x0e) + 0x10;
+                                    rate = ((adsr0 >> 3) & 0x0e) + 0x10;
                                 },
                                 _ => {
                                     rate = env_data & 0x1f;
@@ -85,14 +119,15 @@ impl Envelope {
                         }
                     }
                 } else {
-                    // Gain mode
-                    let mode = self.gain >> 5;
+                    // Gain mode (the sustain level check below then compares against GAIN instead of ADSR1)
+                    env_data = self.gain as i32;
+                    let mode = env_data >> 5;
                     if mode < 4 {
                         // Direct
-                        env = (self.gain as i32) * 0x10;
+                        env = env_data * 0x10;
                         rate = 31;
                     } else {
-                        rate = (self.gain as i32) & 0x1f;
+                        rate = env_data & 0x1f;
                         if mode == 4 {
                             // Linear decrease
                             env -= 0x20;
@@ -119,7 +154,7 @@ impl Envelope {
                 self.hidden_level = env; // Super obscure quirk thingy here
 
                 // Unsigned because env < 0 should also trigger this logic
-                if (env as u32) >= 0x07ff {
+                if (env as u32) > 0x07ff {
                     env = if env < 0 { 0 } else { 0x07ff };
                     if let Mode::Attack = self.mode {
                         self.mode = Mode::Decay;
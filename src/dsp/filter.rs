@@ -1,5 +1,6 @@
 const NUM_TAPS: usize = 8;
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Filter {
     pub coefficients: [u8; NUM_TAPS],
 
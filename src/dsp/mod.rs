@@ -1,5 +1,5 @@
 mod dsp_helpers;
-mod envelope;
+pub mod envelope;
 mod brr_block_decoder;
 mod gaussian;
 pub mod voice;
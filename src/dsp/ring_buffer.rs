@@ -1,5 +1,8 @@
 use super::dsp::BUFFER_LEN;
 
+use std::boxed::Box;
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct RingBuffer {
     left_buffer: Box<[i16]>,
     right_buffer: Box<[i16]>,
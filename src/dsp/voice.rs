@@ -4,16 +4,21 @@ use super::envelope::Envelope;
 use super::brr_block_decoder::BrrBlockDecoder;
 use super::dsp_helpers;
 use super::gaussian::{HALF_KERNEL_SIZE, HALF_KERNEL};
+use super::super::state::VoiceState;
+
+use std::boxed::Box;
 
 const RESAMPLE_BUFFER_LEN: usize = 4;
 
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ResamplingMode {
     Linear,
     Gaussian,
 }
 
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct VoiceOutput {
     pub left_out: i32,
     pub right_out: i32,
@@ -32,6 +37,7 @@ impl VoiceOutput {
 
 pub const VOICE_BUFFER_LEN: usize = 128;
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct VoiceBuffer {
     pub buffer: Box<[VoiceOutput]>,
     pub pos: i32,
@@ -51,8 +57,11 @@ impl VoiceBuffer {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Voice {
+    #[cfg_attr(feature = "serde", serde(skip, default = "::std::ptr::null_mut"))]
     dsp: *mut Dsp,
+    #[cfg_attr(feature = "serde", serde(skip, default = "::std::ptr::null_mut"))]
     emulator: *mut Apu,
 
     pub envelope: Envelope,
@@ -71,6 +80,12 @@ pub struct Voice {
     brr_block_decoder: BrrBlockDecoder,
     sample_address: u32,
     sample_pos: i32,
+    is_end_reached: bool,
+    kon_delay: i32,
+    is_pitch_held: bool,
+
+    outx: u8,
+    envx: u8,
 
     pub resampling_mode: ResamplingMode,
     resample_buffer: [i32; RESAMPLE_BUFFER_LEN],
@@ -103,6 +118,12 @@ impl Voice {
             brr_block_decoder: BrrBlockDecoder::new(),
             sample_address: 0,
             sample_pos: 0,
+            is_end_reached: false,
+            kon_delay: 0,
+            is_pitch_held: false,
+
+            outx: 0,
+            envx: 0,
 
             resampling_mode: resampling_mode,
             resample_buffer: [0; RESAMPLE_BUFFER_LEN],
@@ -114,6 +135,13 @@ impl Voice {
         }
     }
 
+    #[cfg(feature = "serde")]
+    pub fn set_owners(&mut self, dsp: *mut Dsp, emulator: *mut Apu) {
+        self.dsp = dsp;
+        self.emulator = emulator;
+        self.envelope.set_dsp(dsp);
+    }
+
     #[inline]
     fn dsp(&self) -> &mut Dsp {
         unsafe {
@@ -128,9 +156,21 @@ impl Voice {
         }
     }
 
-    pub fn render_sample(&mut self, last_voice_out: i32, noise: i32, are_any_voices_solod: bool) -> VoiceOutput {
-        let mut pitch = ((self.pitch_high as i32) << 8) | (self.pitch_low as i32);
-        if self.pitch_mod {
+    pub fn render_sample(&mut self, last_voice_out: i32, noise: i32, key_on: bool, key_off: bool, are_any_voices_solod: bool) -> VoiceOutput {
+        let pitch = self.pitch_with_modulation(self.pitch() as i32, self.pitch_mod, last_voice_out);
+
+        self.envelope.latch_adsr0();
+        let sample = self.next_sample(noise, self.noise_on, key_on, key_off);
+        self.advance(pitch, true);
+
+        let ret = self.output(sample, are_any_voices_solod);
+        self.output_buffer.write(ret);
+        ret
+    }
+
+    pub fn pitch_with_modulation(&self, pitch: i32, pitch_mod: bool, last_voice_out: i32) -> i32 {
+        let mut pitch = pitch;
+        if pitch_mod {
             pitch += ((last_voice_out >> 5) * pitch) >> 10;
         }
         if pitch < 0 {
@@ -139,8 +179,24 @@ impl Voice {
         if pitch > 0x3fff {
             pitch = 0x3fff;
         }
+        pitch
+    }
 
-        let mut sample = if !self.noise_on {
+    // Interpolates (or takes noise for) the current sample, applies the envelope and runs it. key_on and
+    //  key_off are only ever set on the samples where KON/KOFF are polled.
+    pub fn next_sample(&mut self, noise: i32, noise_on: bool, key_on: bool, key_off: bool) -> i32 {
+        // For 5 samples after key on the voice restarts its sample, its envelope is held at 0 and its pitch
+        //  isn't added
+        self.is_pitch_held = self.kon_delay > 0;
+        if self.kon_delay > 0 {
+            if self.kon_delay == 5 {
+                self.restart();
+            }
+            self.envelope.hold();
+            self.kon_delay -= 1;
+        }
+
+        let mut sample = if !noise_on {
             let s1 = self.resample_buffer[self.resample_buffer_pos];
             let s2 = self.resample_buffer[(self.resample_buffer_pos + 1) % RESAMPLE_BUFFER_LEN];
             let resampled = match self.resampling_mode {
@@ -165,53 +221,134 @@ impl Voice {
             ((noise * 2) as i16) as i32
         };
 
-        self.envelope.tick();
+        // The current envelope level is applied first, and the envelope then runs for the next sample
         let env_level = self.envelope.level;
-
         sample = ((sample * env_level) >> 11) & !1;
+        self.outx = (sample >> 8) as u8;
+        self.envx = (env_level >> 4) as u8;
 
-        if self.brr_block_decoder.is_end && !self.brr_block_decoder.is_looping {
+        // Soft reset holds every voice in release with its envelope at 0 for as long as FLG bit 7 is set
+        if self.dsp().is_soft_reset() || (self.brr_block_decoder.is_end && !self.brr_block_decoder.is_looping) {
             self.envelope.key_off();
             self.envelope.level = 0;
         }
 
+        if key_off {
+            self.envelope.key_off();
+        }
+        if key_on {
+            self.kon_delay = 5;
+            self.envelope.key_on();
+        }
+
+        if self.kon_delay == 0 {
+            self.envelope.tick();
+        }
+
+        sample
+    }
+
+    // Steps the sample position by pitch, decoding new blocks as needed. If reload_entry is false, the loop
+    //  address already in loop_start_address (latched from the source directory) is used when looping.
+    pub fn advance(&mut self, pitch: i32, reload_entry: bool) {
+        self.is_end_reached = false;
+
+        if self.is_pitch_held {
+            return;
+        }
+
         self.sample_pos += pitch;
         while self.sample_pos >= 0x1000 {
             self.sample_pos -= 0x1000;
             self.read_next_sample();
 
             if self.brr_block_decoder.is_finished() {
+                if self.brr_block_decoder.is_end {
+                    self.is_end_reached = true;
+                }
                 if self.brr_block_decoder.is_end && self.brr_block_decoder.is_looping {
-                    self.read_entry();
+                    if reload_entry {
+                        self.read_entry();
+                    }
                     self.sample_address = self.loop_start_address;
                 }
                 self.read_next_block();
             }
         }
+    }
 
-        let ret =
-            if self.is_solod || (!self.is_muted && !are_any_voices_solod) {
-                VoiceOutput {
-                    left_out: dsp_helpers::multiply_volume(sample, self.vol_left),
-                    right_out: dsp_helpers::multiply_volume(sample, self.vol_right),
-                    last_voice_out: sample
-                }
-            } else {
-                VoiceOutput {
-                    left_out: 0,
-                    right_out: 0,
-                    last_voice_out: 0
-                }
-            };
-        self.output_buffer.write(ret);
-        ret
+    pub fn output(&self, sample: i32, are_any_voices_solod: bool) -> VoiceOutput {
+        if self.is_solod || (!self.is_muted && !are_any_voices_solod) {
+            VoiceOutput {
+                left_out: dsp_helpers::multiply_volume(sample, self.vol_left),
+                right_out: dsp_helpers::multiply_volume(sample, self.vol_right),
+                last_voice_out: sample
+            }
+        } else {
+            VoiceOutput {
+                left_out: 0,
+                right_out: 0,
+                last_voice_out: 0
+            }
+        }
+    }
+
+    pub fn set_loop_start_address(&mut self, address: u32) {
+        self.loop_start_address = address;
+    }
+
+    pub fn is_end_reached(&self) -> bool {
+        self.is_end_reached
+    }
+
+    pub fn outx(&self) -> u8 {
+        self.outx
+    }
+
+    pub fn envx(&self) -> u8 {
+        self.envx
+    }
+
+    pub fn pitch(&self) -> u16 {
+        ((self.pitch_high as u16) << 8) | (self.pitch_low as u16)
     }
 
     pub fn set_pitch_high(&mut self, value: u8) {
         self.pitch_high = value & 0x3f;
     }
 
-    pub fn key_on(&mut self) {
+    pub fn state(&self) -> VoiceState {
+        VoiceState {
+            vol_left: self.vol_left,
+            vol_right: self.vol_right,
+            pitch: self.pitch(),
+            source: self.source,
+            pitch_mod: self.pitch_mod,
+            noise_on: self.noise_on,
+            echo_on: self.echo_on,
+
+            sample_start_address: self.sample_start_address as u16,
+            loop_start_address: self.loop_start_address as u16,
+            brr_block_address: self.sample_address.wrapping_sub(9) as u16,
+            brr_sample_index: self.brr_block_decoder.sample_index(),
+            interpolation_pos: self.sample_pos,
+            kon_delay: self.kon_delay,
+
+            envelope_mode: self.envelope.mode(),
+            envelope_level: self.envelope.level,
+            adsr0: self.envelope.adsr0,
+            adsr1: self.envelope.adsr1,
+            gain: self.envelope.gain,
+
+            outx: self.outx,
+            envx: self.envx,
+
+            is_muted: self.is_muted,
+            is_solod: self.is_solod
+        }
+    }
+
+    fn restart(&mut self) {
         self.read_entry();
         self.sample_address = self.sample_start_address;
         self.brr_block_decoder.reset(0, 0);
@@ -221,11 +358,6 @@ impl Voice {
             self.resample_buffer[i] = 0;
         }
         self.read_next_sample();
-        self.envelope.key_on();
-    }
-
-    pub fn key_off(&mut self) {
-        self.envelope.key_off();
     }
 
     fn read_entry(&mut self) {
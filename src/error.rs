@@ -0,0 +1,227 @@
+#[cfg(feature = "std")]
+use std::error;
+use std::fmt;
+#[cfg(feature = "std")]
+use std::io;
+use std::string::String;
+
+#[cfg(feature = "zip")]
+use zip;
+
+// Why an spc file couldn't be loaded
+#[derive(Debug)]
+pub enum SpcError {
+    // Doesn't start with "SNES-SPC700 Sound File Data" followed by the 0x1a 0x1a padding (or "KSPC" 0x1a for spc2)
+    InvalidMagic,
+    // Has the spc magic, but a version other than v0.30 (or an spc2 major version other than 1)
+    UnsupportedVersion,
+    // Ends before the ram, dsp registers and ipl rom are all present
+    Truncated { len: usize, expected: usize },
+    // Has a chunk after the ipl rom that isn't a well-formed xid6 tag
+    InvalidXid6Tag,
+    // An spc2 song refers to a ram block past the end of the file
+    InvalidRamBlock(u16),
+    // Too many songs or distinct ram blocks to index in an spc2 file
+    Spc2TooLarge,
+    #[cfg(feature = "std")]
+    Io(io::Error)
+}
+
+impl fmt::Display for SpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SpcError::InvalidMagic => write!(f, "Invalid spc header magic"),
+            SpcError::UnsupportedVersion => write!(f, "Unsupported spc version"),
+            SpcError::Truncated { len, expected } => write!(f, "Truncated spc data ({} bytes, expected at least {})", len, expected),
+            SpcError::InvalidXid6Tag => write!(f, "Invalid xid6 tag"),
+            SpcError::InvalidRamBlock(index) => write!(f, "Invalid spc2 ram block index: {}", index),
+            SpcError::Spc2TooLarge => write!(f, "Too many songs or ram blocks for an spc2 file"),
+            #[cfg(feature = "std")]
+            SpcError::Io(ref e) => write!(f, "Unable to read spc data: {}", e)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for SpcError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            SpcError::Io(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for SpcError {
+    fn from(e: io::Error) -> SpcError {
+        SpcError::Io(e)
+    }
+}
+
+// Why a host script couldn't be run to the end
+#[derive(Debug)]
+pub enum HostError {
+    // The smp never answered the wait at this index in the script
+    Timeout { command: usize }
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HostError::Timeout { command } => write!(f, "Timed out waiting for the apu at host command {}", command)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for HostError {}
+
+// Why a program couldn't be assembled. Lines count from 1.
+#[derive(Debug)]
+pub enum AsmError {
+    Syntax { line: usize, message: &'static str },
+    UnknownInstruction { line: usize, mnemonic: String },
+    // The mnemonic exists, but not with these operands
+    InvalidOperands { line: usize },
+    UndefinedSymbol { line: usize, name: String },
+    DuplicateSymbol { line: usize, name: String },
+    // Eg. an immediate over $ff, or code running past $ffff
+    OutOfRange { line: usize, value: i64 },
+    BranchOutOfRange { line: usize, offset: i64 }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AsmError::Syntax { line, message } => write!(f, "Line {}: {}", line, message),
+            AsmError::UnknownInstruction { line, ref mnemonic } => write!(f, "Line {}: Unknown instruction: {}", line, mnemonic),
+            AsmError::InvalidOperands { line } => write!(f, "Line {}: Invalid operands", line),
+            AsmError::UndefinedSymbol { line, ref name } => write!(f, "Line {}: Undefined symbol: {}", line, name),
+            AsmError::DuplicateSymbol { line, ref name } => write!(f, "Line {}: Symbol defined twice: {}", line, name),
+            AsmError::OutOfRange { line, value } => write!(f, "Line {}: Value out of range: {}", line, value),
+            AsmError::BranchOutOfRange { line, offset } => write!(f, "Line {}: Branch target out of range: {}", line, offset)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for AsmError {}
+
+// Why a soundtrack set or one of its tracks couldn't be loaded
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum SoundtrackError {
+    Io(io::Error),
+    #[cfg(feature = "zip")]
+    Zip(zip::result::ZipError),
+    // Eg. a rar-based .rsn set, which would need unpacking first
+    UnsupportedArchive,
+    InvalidTrack { name: String, error: SpcError },
+    TrackIndexOutOfRange(usize)
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for SoundtrackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SoundtrackError::Io(ref e) => write!(f, "Unable to read soundtrack set: {}", e),
+            #[cfg(feature = "zip")]
+            SoundtrackError::Zip(ref e) => write!(f, "Invalid zip archive: {}", e),
+            SoundtrackError::UnsupportedArchive => write!(f, "Unsupported archive format"),
+            SoundtrackError::InvalidTrack { ref name, ref error } => write!(f, "Invalid track {}: {}", name, error),
+            SoundtrackError::TrackIndexOutOfRange(index) => write!(f, "Track index out of range: {}", index)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for SoundtrackError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            SoundtrackError::Io(ref e) => Some(e),
+            #[cfg(feature = "zip")]
+            SoundtrackError::Zip(ref e) => Some(e),
+            SoundtrackError::InvalidTrack { ref error, .. } => Some(error),
+            _ => None
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for SoundtrackError {
+    fn from(e: io::Error) -> SoundtrackError {
+        SoundtrackError::Io(e)
+    }
+}
+
+#[cfg(feature = "zip")]
+impl From<zip::result::ZipError> for SoundtrackError {
+    fn from(e: zip::result::ZipError) -> SoundtrackError {
+        SoundtrackError::Zip(e)
+    }
+}
+
+// Why an snsf (or one of the libs it names) couldn't be loaded or started
+#[cfg(feature = "snsf")]
+#[derive(Debug)]
+pub enum SnsfError {
+    Io(io::Error),
+    // Doesn't start with "PSF"
+    InvalidMagic,
+    Truncated { len: usize, expected: usize },
+    CrcMismatch,
+    Decompress(io::Error),
+    // A psf for some other system
+    UnsupportedVersion(u8),
+    // A rom or sram chunk runs past the end of its data
+    InvalidChunk,
+    // _lib tags nest too deeply, most likely in a loop
+    LibTooDeep,
+    // Nothing in the rom looks like an ipl upload
+    NoUpload,
+    Host(HostError)
+}
+
+#[cfg(feature = "snsf")]
+impl fmt::Display for SnsfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnsfError::Io(ref e) => write!(f, "Unable to read snsf: {}", e),
+            SnsfError::InvalidMagic => write!(f, "Invalid psf header magic"),
+            SnsfError::Truncated { len, expected } => write!(f, "Truncated psf data ({} bytes, expected at least {})", len, expected),
+            SnsfError::CrcMismatch => write!(f, "Psf program crc mismatch"),
+            SnsfError::Decompress(ref e) => write!(f, "Unable to decompress psf program: {}", e),
+            SnsfError::UnsupportedVersion(version) => write!(f, "Unsupported psf version: 0x{:02x}", version),
+            SnsfError::InvalidChunk => write!(f, "Invalid snsf rom or sram chunk"),
+            SnsfError::LibTooDeep => write!(f, "Psf _lib tags nested too deeply"),
+            SnsfError::NoUpload => write!(f, "No apu upload found in snsf rom"),
+            SnsfError::Host(ref e) => write!(f, "Unable to upload to apu: {}", e)
+        }
+    }
+}
+
+#[cfg(feature = "snsf")]
+impl error::Error for SnsfError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            SnsfError::Io(ref e) | SnsfError::Decompress(ref e) => Some(e),
+            SnsfError::Host(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+#[cfg(feature = "snsf")]
+impl From<io::Error> for SnsfError {
+    fn from(e: io::Error) -> SnsfError {
+        SnsfError::Io(e)
+    }
+}
+
+#[cfg(feature = "snsf")]
+impl From<HostError> for SnsfError {
+    fn from(e: HostError) -> SnsfError {
+        SnsfError::Host(e)
+    }
+}
@@ -0,0 +1,182 @@
+// A scripted stand-in for the snes cpu. As far as the apu can tell the cpu is just something on the other end
+//  of the four ports at $f4-$f7, so a script of port writes and waits is enough to replay anything that only
+//  talks to it in a fixed order - most importantly uploading a sound driver through the ipl rom.
+
+use super::apu::Apu;
+use super::error::HostError;
+
+use std::vec::Vec;
+
+// One second
+pub const DEFAULT_TIMEOUT_SAMPLES: u32 = 32000;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum HostCommand {
+    WritePort(usize, u8),
+    // Runs the apu until the smp writes value to port
+    WaitPort(usize, u8),
+    RunSamples(u32)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct UploadBlock {
+    pub address: u16,
+    pub data: Vec<u8>
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct HostScript {
+    pub commands: Vec<HostCommand>,
+    // How long a wait can go unanswered before the script fails
+    pub timeout_samples: u32
+}
+
+impl Default for HostScript {
+    fn default() -> HostScript {
+        HostScript::new()
+    }
+}
+
+impl HostScript {
+    pub fn new() -> HostScript {
+        HostScript {
+            commands: Vec::new(),
+            timeout_samples: DEFAULT_TIMEOUT_SAMPLES
+        }
+    }
+
+    // The whole of an IplUploader session as a script, eg. to keep alongside a recording
+    pub fn ipl_upload(blocks: &[UploadBlock], entry: u16) -> HostScript {
+        let mut ret = HostScript::new();
+        ret.commands.extend_from_slice(&handshake_commands());
+
+        let mut kick = FIRST_KICK;
+        for block in blocks.iter().filter(|block| !block.data.is_empty()) {
+            ret.commands.extend_from_slice(&kick_commands(block.address, 1, kick));
+            for (i, &value) in block.data.iter().enumerate() {
+                ret.commands.extend_from_slice(&byte_commands(i, value));
+            }
+            kick = next_kick(block.data.len());
+        }
+        ret.commands.extend_from_slice(&kick_commands(entry, 0, kick));
+
+        ret
+    }
+
+    // Runs the script against apu. Whatever the apu outputs meanwhile is thrown away.
+    pub fn run(&self, apu: &mut Apu) -> Result<(), HostError> {
+        for (index, command) in self.commands.iter().enumerate() {
+            run_command(apu, command, index, self.timeout_samples)?;
+        }
+        Ok(())
+    }
+}
+
+// Talks to the ipl rom of a freshly booted (or reset) apu to load code and data into ram, then starts it - the
+//  way real hardware loads a sound driver. Uploads go through the ipl rom's own loop, so a block mustn't cover
+//  the io registers at $f0-$ff, the pointer it uses at $00-$01, or the stack at the top of page 1.
+pub struct IplUploader<'a> {
+    apu: &'a mut Apu,
+    kick: u8,
+    // Commands sent so far, for the index in a timeout error
+    num_commands: usize,
+    pub timeout_samples: u32
+}
+
+impl<'a> IplUploader<'a> {
+    // Waits for the ipl rom to signal it's ready
+    pub fn new(apu: &'a mut Apu) -> Result<IplUploader<'a>, HostError> {
+        let mut ret = IplUploader {
+            apu,
+            kick: FIRST_KICK,
+            num_commands: 0,
+            timeout_samples: DEFAULT_TIMEOUT_SAMPLES
+        };
+        ret.run(&handshake_commands())?;
+        Ok(ret)
+    }
+
+    pub fn upload(&mut self, address: u16, data: &[u8]) -> Result<(), HostError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let kick = self.kick;
+        self.run(&kick_commands(address, 1, kick))?;
+        for (i, &value) in data.iter().enumerate() {
+            self.run(&byte_commands(i, value))?;
+        }
+        self.kick = next_kick(data.len());
+        Ok(())
+    }
+
+    // Hands control to the uploaded code. The apu can be rendered as usual from here on.
+    pub fn jump(mut self, entry: u16) -> Result<(), HostError> {
+        let kick = self.kick;
+        self.run(&kick_commands(entry, 0, kick))
+    }
+
+    fn run(&mut self, commands: &[HostCommand]) -> Result<(), HostError> {
+        for command in commands.iter() {
+            run_command(self.apu, command, self.num_commands, self.timeout_samples)?;
+            self.num_commands += 1;
+        }
+        Ok(())
+    }
+}
+
+// The cpu side of the ipl rom's transfer protocol: wait for $aa/$bb, then for each block send its address and a
+//  kick, and each byte along with its index, waiting for every one to be echoed back on port 0. A final kick
+//  with port 1 cleared makes the ipl rom jump to the address instead.
+const FIRST_KICK: u8 = 0xcc;
+
+fn handshake_commands() -> [HostCommand; 2] {
+    [HostCommand::WaitPort(0, 0xaa), HostCommand::WaitPort(1, 0xbb)]
+}
+
+fn kick_commands(address: u16, command: u8, kick: u8) -> [HostCommand; 5] {
+    [
+        HostCommand::WritePort(2, address as u8),
+        HostCommand::WritePort(3, (address >> 8) as u8),
+        HostCommand::WritePort(1, command),
+        HostCommand::WritePort(0, kick),
+        HostCommand::WaitPort(0, kick)]
+}
+
+fn byte_commands(index: usize, value: u8) -> [HostCommand; 3] {
+    [
+        HostCommand::WritePort(1, value),
+        HostCommand::WritePort(0, index as u8),
+        HostCommand::WaitPort(0, index as u8)]
+}
+
+// The kick after a block has to be ahead of its last index, and can't be 0 as that starts a block
+fn next_kick(len: usize) -> u8 {
+    match (len as u8).wrapping_add(1) {
+        0 => 1,
+        kick => kick
+    }
+}
+
+fn run_command(apu: &mut Apu, command: &HostCommand, index: usize, timeout_samples: u32) -> Result<(), HostError> {
+    let mut left = [0; 1];
+    let mut right = [0; 1];
+    match *command {
+        HostCommand::WritePort(port, value) => apu.write_port(port, value),
+        HostCommand::WaitPort(port, value) => {
+            let mut samples = 0;
+            while apu.read_port(port) != value {
+                if samples >= timeout_samples {
+                    return Err(HostError::Timeout { command: index });
+                }
+                apu.render(&mut left, &mut right, 1);
+                samples += 1;
+            }
+        },
+        HostCommand::RunSamples(num_samples) => {
+            for _ in 0..num_samples {
+                apu.render(&mut left, &mut right, 1);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,155 @@
+// Basic ID666 tag, stored in the spc file header. Unlike the spc crate's loader this works on data in memory
+//  and handles binary as well as text tags, and it can write a tag back out (always in the text format, which
+//  is what most players expect).
+
+use std::string::String;
+use std::vec::Vec;
+
+pub const ID666_TAG_PRESENT: u8 = 0x1a;
+pub const ID666_TAG_ABSENT: u8 = 0x1b;
+
+const TAG_PRESENT_OFFSET: usize = 0x23;
+const SONG_TITLE_OFFSET: usize = 0x2e;
+const GAME_TITLE_OFFSET: usize = 0x4e;
+const DUMPER_NAME_OFFSET: usize = 0x6e;
+const COMMENTS_OFFSET: usize = 0x7e;
+const DATE_OFFSET: usize = 0x9e;
+const SECONDS_OFFSET: usize = 0xa9;
+const FADE_OFFSET: usize = 0xac;
+const TAG_END: usize = 0x100;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Id666Tag {
+    pub song_title: String,
+    pub game_title: String,
+    pub dumper_name: String,
+    pub comments: String,
+    // MM/DD/YYYY
+    pub date_dumped: String,
+    pub seconds_to_play_before_fading_out: u32,
+    // In milliseconds
+    pub fade_out_length: u32,
+    pub artist_name: String,
+    pub default_channel_disables: u8,
+    pub dumping_emulator: u8
+}
+
+impl Default for Id666Tag {
+    fn default() -> Id666Tag {
+        Id666Tag::new()
+    }
+}
+
+impl Id666Tag {
+    pub fn new() -> Id666Tag {
+        Id666Tag {
+            song_title: String::new(),
+            game_title: String::new(),
+            dumper_name: String::new(),
+            comments: String::new(),
+            date_dumped: String::new(),
+            seconds_to_play_before_fading_out: 0,
+            fade_out_length: 0,
+            artist_name: String::new(),
+            default_channel_disables: 0,
+            dumping_emulator: 0
+        }
+    }
+
+    // Returns None if the header says there's no tag, or is too short to hold one
+    pub fn from_spc_bytes(data: &[u8]) -> Option<Id666Tag> {
+        if data.len() < TAG_END || data[TAG_PRESENT_OFFSET] != ID666_TAG_PRESENT {
+            return None;
+        }
+
+        // The tag is either all text or binary, with nothing to say which. As the spc crate does, look for
+        //  text where the date and length fields would be.
+        let is_text_format = is_text_region(&data[DATE_OFFSET..SECONDS_OFFSET]) && is_text_region(&data[SECONDS_OFFSET..FADE_OFFSET]);
+        let (date_dumped, seconds, fade_out_length, artist_offset) = if is_text_format {
+            (read_string(&data[DATE_OFFSET..SECONDS_OFFSET]), read_number(&data[SECONDS_OFFSET..FADE_OFFSET]), read_number(&data[FADE_OFFSET..0xb1]), 0xb1)
+        } else {
+            let year = read_le(&data[DATE_OFFSET..DATE_OFFSET + 2]);
+            let month = data[DATE_OFFSET + 2];
+            let day = data[DATE_OFFSET + 3];
+            let date_dumped = if year != 0 { format!("{:02}/{:02}/{:04}", month, day, year) } else { String::new() };
+            (date_dumped, read_le(&data[SECONDS_OFFSET..FADE_OFFSET]), read_le(&data[FADE_OFFSET..0xb0]), 0xb0)
+        };
+
+        let emulator = data[artist_offset + 33];
+        Some(Id666Tag {
+            song_title: read_string(&data[SONG_TITLE_OFFSET..GAME_TITLE_OFFSET]),
+            game_title: read_string(&data[GAME_TITLE_OFFSET..DUMPER_NAME_OFFSET]),
+            dumper_name: read_string(&data[DUMPER_NAME_OFFSET..COMMENTS_OFFSET]),
+            comments: read_string(&data[COMMENTS_OFFSET..DATE_OFFSET]),
+            date_dumped,
+            seconds_to_play_before_fading_out: seconds,
+            fade_out_length,
+            artist_name: read_string(&data[artist_offset..artist_offset + 32]),
+            default_channel_disables: data[artist_offset + 32],
+            dumping_emulator: if is_text_format && emulator >= b'0' { emulator - b'0' } else { emulator }
+        })
+    }
+
+    // Writes the tag into the header of an spc file, which must be at least 256 bytes long. Strings that don't
+    //  fit are truncated; an xid6 tag can hold them in full.
+    pub fn write_to_spc(&self, data: &mut [u8]) {
+        let tag = &mut data[..TAG_END];
+        tag[TAG_PRESENT_OFFSET] = ID666_TAG_PRESENT;
+        for x in tag[SONG_TITLE_OFFSET..].iter_mut() {
+            *x = 0;
+        }
+
+        write_string(&mut tag[SONG_TITLE_OFFSET..GAME_TITLE_OFFSET], &self.song_title);
+        write_string(&mut tag[GAME_TITLE_OFFSET..DUMPER_NAME_OFFSET], &self.game_title);
+        write_string(&mut tag[DUMPER_NAME_OFFSET..COMMENTS_OFFSET], &self.dumper_name);
+        write_string(&mut tag[COMMENTS_OFFSET..DATE_OFFSET], &self.comments);
+        write_string(&mut tag[DATE_OFFSET..SECONDS_OFFSET], &self.date_dumped);
+        write_number(&mut tag[SECONDS_OFFSET..FADE_OFFSET], self.seconds_to_play_before_fading_out);
+        write_number(&mut tag[FADE_OFFSET..0xb1], self.fade_out_length);
+        write_string(&mut tag[0xb1..0xd1], &self.artist_name);
+        tag[0xd1] = self.default_channel_disables;
+        tag[0xd2] = b'0' + (self.dumping_emulator % 10);
+    }
+}
+
+// Strings are latin-1, padded with zeroes. Spc2's metadata stores them the same way.
+pub(crate) fn read_string(data: &[u8]) -> String {
+    data.iter().take_while(|&&x| x != 0).map(|&x| x as char).collect()
+}
+
+pub(crate) fn write_string(data: &mut [u8], value: &str) {
+    for (x, c) in data.iter_mut().zip(value.chars()) {
+        *x = if (c as u32) < 0x100 { c as u8 } else { b'?' };
+    }
+}
+
+fn is_text_region(data: &[u8]) -> bool {
+    data.iter().all(|&x| x == 0 || x.is_ascii_digit() || x == b'/')
+}
+
+fn read_number(data: &[u8]) -> u32 {
+    data.iter().take_while(|x| x.is_ascii_digit()).fold(0, |acc, &x| acc * 10 + ((x - b'0') as u32))
+}
+
+fn write_number(data: &mut [u8], value: u32) {
+    let mut digits = Vec::new();
+    let mut value = value;
+    loop {
+        digits.push(b'0' + (value % 10) as u8);
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    // Numbers too big for the field saturate at all nines
+    if digits.len() > data.len() {
+        digits = vec![b'9'; data.len()];
+    }
+    for (x, &digit) in data.iter_mut().zip(digits.iter().rev()) {
+        *x = digit;
+    }
+}
+
+fn read_le(data: &[u8]) -> u32 {
+    data.iter().rev().fold(0, |acc, &x| (acc << 8) | (x as u32))
+}
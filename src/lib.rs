@@ -1,6 +1,54 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "std")]
 extern crate spc;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "capi")]
+extern crate bincode;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+#[cfg(feature = "zip")]
+extern crate zip;
+#[cfg(feature = "snsf")]
+extern crate flate2;
 
 pub mod apu;
+pub mod asm;
+pub mod error;
+pub mod id666;
 pub mod smp;
 pub mod dsp;
-mod timer;
+pub mod host;
+#[cfg(feature = "std")]
+pub mod midi;
+pub mod loop_detector;
+pub mod silence_detector;
+#[cfg(feature = "std")]
+pub mod soundtrack;
+pub mod spc2;
+#[cfg(feature = "snsf")]
+pub mod psf;
+#[cfg(feature = "snsf")]
+pub mod snsf;
+pub mod state;
+pub mod timer;
+pub mod xid6;
+#[cfg(feature = "serde")]
+mod serde_regs;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+// Modules name std paths as usual; without std those resolve to core and alloc instead
+#[cfg(not(feature = "std"))]
+mod std {
+    #[allow(unused_imports)]
+    pub use core::*;
+    pub use alloc::{boxed, collections, string, vec};
+}
@@ -0,0 +1,211 @@
+use super::apu::Apu;
+use super::dsp::dsp::SAMPLE_RATE;
+use super::silence_detector::is_silent;
+
+use std::vec::Vec;
+
+const NUM_VOICES: usize = 8;
+
+const RENDER_BLOCK_LEN: i32 = 1024;
+const DETECTION_INTERVAL: i32 = 10 * (SAMPLE_RATE as i32);
+
+// Timer-driven sequencers can drift by a tick or so between repetitions
+const DELTA_TOLERANCE: u64 = 16;
+
+pub enum SongLength {
+    Looping { intro_samples: u64, loop_samples: u64 },
+    Ends { length_samples: u64 },
+    Unknown,
+}
+
+pub struct PlayLength {
+    pub length: SongLength,
+    pub seconds_to_play_before_fading_out: i32,
+    pub fade_out_length: i32,
+}
+
+#[derive(Clone, Copy)]
+struct KeyOnEvent {
+    fingerprint: u64,
+    sample: u64,
+}
+
+pub struct LoopDetector {
+    pub max_seconds: i32,
+    pub min_loop_repetitions: i32,
+    pub silence_threshold: i16,
+    pub silence_seconds: i32,
+    pub loop_count: i32,
+    pub fade_out_length: i32,
+}
+
+impl Default for LoopDetector {
+    fn default() -> LoopDetector {
+        LoopDetector::new()
+    }
+}
+
+impl LoopDetector {
+    pub fn new() -> LoopDetector {
+        LoopDetector {
+            max_seconds: 600,
+            min_loop_repetitions: 3,
+            silence_threshold: 0,
+            silence_seconds: 10,
+            loop_count: 2,
+            fade_out_length: 10000,
+        }
+    }
+
+    // Runs the given apu headlessly until the key on stream is found to repeat, the output goes permanently
+    //  silent, or max_seconds have been rendered
+    pub fn detect(&self, apu: &mut Apu) -> PlayLength {
+        let max_samples = (self.max_seconds as u64) * (SAMPLE_RATE as u64);
+        let silence_samples = (self.silence_seconds as u64) * (SAMPLE_RATE as u64);
+
+        let mut regs = [0; 128];
+        let mut events = Vec::new();
+
+        let start_sample = {
+            let dsp = apu.dsp.as_mut().unwrap();
+            dsp.set_register_logging(true);
+            dsp.sample_count()
+        };
+
+        let mut left = vec![0; RENDER_BLOCK_LEN as usize];
+        let mut right = vec![0; RENDER_BLOCK_LEN as usize];
+        let mut samples_rendered = 0;
+        let mut next_detection = DETECTION_INTERVAL as u64;
+        let mut last_audible_sample = None;
+        let mut candidate = None;
+        let mut length = SongLength::Unknown;
+        while samples_rendered < max_samples {
+            apu.render(&mut left, &mut right, RENDER_BLOCK_LEN);
+
+            for (i, (&left_sample, &right_sample)) in left.iter().zip(right.iter()).enumerate() {
+                if !is_silent(left_sample, self.silence_threshold) || !is_silent(right_sample, self.silence_threshold) {
+                    last_audible_sample = Some(samples_rendered + (i as u64));
+                }
+            }
+            samples_rendered += RENDER_BLOCK_LEN as u64;
+
+            for write in apu.dsp.as_mut().unwrap().take_register_log() {
+                regs[write.address as usize] = write.value;
+                if write.address == 0x4c && write.value != 0 {
+                    events.push(KeyOnEvent {
+                        fingerprint: key_on_fingerprint(&regs, write.value),
+                        sample: write.sample - start_sample,
+                    });
+                }
+            }
+
+            let silent_since = match last_audible_sample {
+                Some(sample) => sample + 1,
+                _ => 0
+            };
+            if samples_rendered - silent_since >= silence_samples {
+                length = SongLength::Ends { length_samples: silent_since };
+                break;
+            }
+
+            // A phrase that's repeated a few times before the song moves on looks just like a loop until it does,
+            //  so a loop only counts once it's been found at two detections in a row
+            if samples_rendered >= next_detection {
+                next_detection += DETECTION_INTERVAL as u64;
+                let found = find_loop(&events, self.min_loop_repetitions);
+                if let Some((intro_samples, loop_samples)) = found {
+                    if candidate == found {
+                        length = SongLength::Looping { intro_samples, loop_samples };
+                        break;
+                    }
+                }
+                candidate = found;
+            }
+        }
+
+        if let SongLength::Unknown = length {
+            if let Some((intro_samples, loop_samples)) = find_loop(&events, 2) {
+                length = SongLength::Looping { intro_samples, loop_samples };
+            }
+        }
+
+        apu.dsp.as_mut().unwrap().set_register_logging(false);
+
+        let sample_rate = SAMPLE_RATE as u64;
+        let (seconds_to_play_before_fading_out, fade_out_length) = match length {
+            SongLength::Looping { intro_samples, loop_samples } => {
+                let play_samples = intro_samples + loop_samples * (self.loop_count as u64);
+                (play_samples.div_ceil(sample_rate) as i32, self.fade_out_length)
+            },
+            SongLength::Ends { length_samples } => (length_samples.div_ceil(sample_rate) as i32, 0),
+            SongLength::Unknown => (self.max_seconds, self.fade_out_length)
+        };
+
+        PlayLength {
+            length,
+            seconds_to_play_before_fading_out,
+            fade_out_length,
+        }
+    }
+}
+
+fn key_on_fingerprint(regs: &[u8; 128], voice_mask: u8) -> u64 {
+    // FNV-1a over the key on mask and the pitch and source of each voice being keyed on
+    let mut hash = 0xcbf29ce484222325;
+    let mut add = |value: u8| {
+        hash ^= value as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    };
+    add(voice_mask);
+    for i in 0..NUM_VOICES {
+        if (voice_mask & (1 << i)) != 0 {
+            let base = i << 4;
+            add(regs[base + 0x02]);
+            add(regs[base + 0x03]);
+            add(regs[base + 0x04]);
+        }
+    }
+    hash
+}
+
+// Finds the period (in events) that the tail of the event stream repeats with at least min_repetitions times,
+//  and returns the intro and loop lengths in samples. Anything less than two repetitions can't show a repeat,
+//  so smaller values count as two. A phrase repeated within the loop also repeats at the end of the stream, but
+//  only the whole loop's repeats reach back to the end of the intro, so the period reaching back furthest wins
+//  (the shortest of those, as multiples of the loop reach back just as far).
+fn find_loop(events: &[KeyOnEvent], min_repetitions: i32) -> Option<(u64, u64)> {
+    let min_repetitions = if min_repetitions < 2 { 2 } else { min_repetitions as usize };
+    let len = events.len();
+    let matches = |a: usize, b: usize| {
+        if events[a].fingerprint != events[b].fingerprint {
+            return false;
+        }
+        if b + 1 >= len {
+            return true;
+        }
+        let delta_a = events[a + 1].sample - events[a].sample;
+        let delta_b = events[b + 1].sample - events[b].sample;
+        delta_a.abs_diff(delta_b) <= DELTA_TOLERANCE
+    };
+
+    let mut ret = None;
+    let mut earliest_start = len;
+    for period in 1..(len / min_repetitions + 1) {
+        let mut start = len - period;
+        while start > 0 && matches(start - 1, start - 1 + period) {
+            start -= 1;
+        }
+        if len - start < period * min_repetitions || start >= earliest_start {
+            continue;
+        }
+        let intro_samples = events[start].sample;
+        let loop_samples = events[start + period].sample - events[start].sample;
+        if loop_samples == 0 {
+            continue;
+        }
+        earliest_start = start;
+        ret = Some((intro_samples, loop_samples));
+    }
+
+    ret
+}
@@ -0,0 +1,413 @@
+use super::apu::Apu;
+use super::dsp::dsp::{RegisterWrite, SAMPLE_RATE};
+
+const NUM_VOICES: usize = 8;
+const NUM_SOURCES: usize = 256;
+
+// With the default tempo of 120bpm, this makes one tick equal to one DSP sample
+const TICKS_PER_QUARTER_NOTE: u16 = (SAMPLE_RATE / 2) as u16;
+const TEMPO: u32 = 500000;
+
+const RENDER_BLOCK_LEN: i32 = 32;
+
+const DEFAULT_BASE_NOTE: f32 = 60.0;
+const DEFAULT_PITCH_BEND_RANGE: u8 = 12;
+
+struct VoiceTrack {
+    events: Vec<(u64, Vec<u8>)>,
+    note: Option<u8>,
+    // Index of the sounding note's note on in events, so its velocity can be settled once the note ends
+    note_on_index: usize,
+    vol_velocity: i32,
+    // Highest ENVX seen while the note was held, or 0 if it ended before it was ever seen
+    peak_envx: u8,
+    source: Option<u8>,
+    pitch_bend: u16,
+}
+
+impl VoiceTrack {
+    fn new() -> VoiceTrack {
+        VoiceTrack {
+            events: Vec::new(),
+            note: None,
+            note_on_index: 0,
+            vol_velocity: 0,
+            peak_envx: 0,
+            source: None,
+            pitch_bend: 0x2000,
+        }
+    }
+
+    fn push(&mut self, time: u64, event: Vec<u8>) {
+        self.events.push((time, event));
+    }
+}
+
+pub struct MidiExporter {
+    base_notes: Box<[Option<f32>]>,
+    pitch_bend_range: u8,
+}
+
+impl Default for MidiExporter {
+    fn default() -> MidiExporter {
+        MidiExporter::new()
+    }
+}
+
+impl MidiExporter {
+    pub fn new() -> MidiExporter {
+        MidiExporter {
+            base_notes: vec![None; NUM_SOURCES].into_boxed_slice(),
+            pitch_bend_range: DEFAULT_PITCH_BEND_RANGE,
+        }
+    }
+
+    // The (fractional) MIDI note a source plays at pitch $1000, overriding the estimate
+    pub fn set_base_note(&mut self, source: u8, note: f32) {
+        self.base_notes[source as usize] = Some(note);
+    }
+
+    pub fn set_pitch_bend_range(&mut self, semitones: u8) {
+        self.pitch_bend_range = if semitones == 0 { 1 } else { semitones & 0x7f };
+    }
+
+    // Renders num_samples samples of the given apu and returns them as a type 1 Standard MIDI File
+    pub fn export(&mut self, apu: &mut Apu, num_samples: i32) -> Vec<u8> {
+        let mut regs = [0; 128];
+        let mut base_notes = vec![None; NUM_SOURCES];
+        let mut tracks = (0..NUM_VOICES).map(|_| VoiceTrack::new()).collect::<Vec<_>>();
+
+        let start_sample = {
+            let dsp = apu.dsp.as_mut().unwrap();
+            dsp.set_register_logging(true);
+
+            regs[0x5d] = dsp.source_dir();
+            for (i, voice) in dsp.voices.iter().enumerate() {
+                let base = i << 4;
+                let pitch = voice.pitch();
+                regs[base] = voice.vol_left;
+                regs[base + 0x01] = voice.vol_right;
+                regs[base + 0x02] = pitch as u8;
+                regs[base + 0x03] = (pitch >> 8) as u8;
+                regs[base + 0x04] = voice.source;
+                regs[base + 0x05] = voice.envelope.adsr0;
+                regs[base + 0x06] = voice.envelope.adsr1;
+                regs[base + 0x07] = voice.envelope.gain;
+            }
+
+            dsp.sample_count()
+        };
+
+        // Voices that are already sounding when we start get a note at time 0
+        for (i, track) in tracks.iter_mut().enumerate() {
+            if !apu.dsp.as_ref().unwrap().voices[i].envelope.is_silent() {
+                self.key_on(apu, &regs, &mut base_notes, track, i, 0);
+            }
+        }
+
+        let mut left = vec![0; RENDER_BLOCK_LEN as usize];
+        let mut right = vec![0; RENDER_BLOCK_LEN as usize];
+        let mut samples_rendered = 0;
+        while samples_rendered < num_samples {
+            let block_len = if num_samples - samples_rendered < RENDER_BLOCK_LEN {
+                num_samples - samples_rendered
+            } else {
+                RENDER_BLOCK_LEN
+            };
+            apu.render(&mut left, &mut right, block_len);
+            samples_rendered += block_len;
+
+            MidiExporter::track_envelopes(apu, &mut tracks);
+            let log = apu.dsp.as_mut().unwrap().take_register_log();
+            for write in log.iter() {
+                let time = write.sample - start_sample;
+                if time >= num_samples as u64 {
+                    break;
+                }
+                self.handle_write(apu, &mut regs, &mut base_notes, &mut tracks, write, time);
+            }
+
+            // Catch voices that were silenced by their sample ending rather than a key off
+            let mut time = apu.dsp.as_ref().unwrap().sample_count() - start_sample;
+            if time > num_samples as u64 {
+                time = num_samples as u64;
+            }
+            MidiExporter::track_envelopes(apu, &mut tracks);
+            for (i, track) in tracks.iter_mut().enumerate() {
+                if track.note.is_some() && apu.dsp.as_ref().unwrap().voices[i].envelope.is_silent() {
+                    MidiExporter::note_off(track, i, time);
+                }
+            }
+        }
+
+        let end_time = num_samples as u64;
+        for (i, track) in tracks.iter_mut().enumerate() {
+            MidiExporter::note_off(track, i, end_time);
+        }
+
+        apu.dsp.as_mut().unwrap().set_register_logging(false);
+
+        self.write_file(&tracks, end_time)
+    }
+
+    fn handle_write(&self, apu: &Apu, regs: &mut [u8; 128], base_notes: &mut [Option<f32>], tracks: &mut [VoiceTrack], write: &RegisterWrite, time: u64) {
+        let address = write.address as usize;
+        regs[address] = write.value;
+
+        match address {
+            0x4c => {
+                for (i, track) in tracks.iter_mut().enumerate() {
+                    if (write.value & (1 << i)) != 0 {
+                        self.key_on(apu, regs, base_notes, track, i, time);
+                    }
+                }
+            },
+            0x5c => {
+                for (i, track) in tracks.iter_mut().enumerate() {
+                    if (write.value & (1 << i)) != 0 {
+                        MidiExporter::note_off(track, i, time);
+                    }
+                }
+            },
+            _ => {
+                let voice_index = address >> 4;
+                match address & 0x0f {
+                    0x02 | 0x03 => {
+                        let track = &mut tracks[voice_index];
+                        if let Some(note) = track.note {
+                            let exact_note = self.exact_note(apu, regs, base_notes, voice_index);
+                            let pitch_bend = self.pitch_bend(exact_note - (note as f32));
+                            if pitch_bend != track.pitch_bend {
+                                MidiExporter::push_pitch_bend(track, voice_index, time, pitch_bend);
+                            }
+                        }
+                    },
+                    _ => () // Do nothing
+                }
+            }
+        }
+    }
+
+    fn key_on(&self, apu: &Apu, regs: &[u8; 128], base_notes: &mut [Option<f32>], track: &mut VoiceTrack, voice_index: usize, time: u64) {
+        MidiExporter::note_off(track, voice_index, time);
+
+        let channel = voice_index as u8;
+        let base = voice_index << 4;
+
+        // There are twice as many sources as programs, so SRCN $00-$7f are programs in bank 0 and $80-$ff the
+        //  same programs in bank 1
+        let source = regs[base + 0x04];
+        if track.source != Some(source) {
+            if track.source.map(|x| x >> 7) != Some(source >> 7) {
+                track.push(time, vec![0xb0 | channel, 0, source >> 7]);
+            }
+            track.push(time, vec![0xc0 | channel, source & 0x7f]);
+            track.source = Some(source);
+        }
+
+        let exact_note = self.exact_note(apu, regs, base_notes, voice_index);
+        let note = clamp_note(exact_note.round());
+        let pitch_bend = self.pitch_bend(exact_note - (note as f32));
+        if pitch_bend != track.pitch_bend {
+            MidiExporter::push_pitch_bend(track, voice_index, time, pitch_bend);
+        }
+
+        // The velocity is only known once the envelope has had a chance to rise, so this one is a placeholder
+        track.vol_velocity = MidiExporter::vol_velocity(regs, voice_index);
+        track.peak_envx = 0;
+        track.note_on_index = track.events.len();
+        track.push(time, vec![0x90 | channel, note, 0]);
+        track.note = Some(note);
+    }
+
+    fn note_off(track: &mut VoiceTrack, voice_index: usize, time: u64) {
+        if let Some(note) = track.note {
+            let velocity = MidiExporter::velocity(track.vol_velocity, track.peak_envx);
+            track.events[track.note_on_index].1[2] = velocity;
+            track.push(time, vec![0x80 | (voice_index as u8), note, 0x40]);
+            track.note = None;
+        }
+    }
+
+    fn track_envelopes(apu: &Apu, tracks: &mut [VoiceTrack]) {
+        let dsp = apu.dsp.as_ref().unwrap();
+        for (track, voice) in tracks.iter_mut().zip(dsp.voices.iter()) {
+            if track.note.is_some() && voice.envx() > track.peak_envx {
+                track.peak_envx = voice.envx();
+            }
+        }
+    }
+
+    fn push_pitch_bend(track: &mut VoiceTrack, voice_index: usize, time: u64, pitch_bend: u16) {
+        track.push(time, vec![0xe0 | (voice_index as u8), (pitch_bend & 0x7f) as u8, (pitch_bend >> 7) as u8]);
+        track.pitch_bend = pitch_bend;
+    }
+
+    fn exact_note(&self, apu: &Apu, regs: &[u8; 128], base_notes: &mut [Option<f32>], voice_index: usize) -> f32 {
+        let base = voice_index << 4;
+        let source = regs[base + 0x04];
+        let pitch = (((regs[base + 0x03] & 0x3f) as u32) << 8) | (regs[base + 0x02] as u32);
+        if pitch == 0 {
+            return 0.0;
+        }
+
+        let base_note = match self.base_notes[source as usize] {
+            Some(note) => note,
+            _ => {
+                // Estimates are cached per source, as sample data rarely moves during playback
+                if base_notes[source as usize].is_none() {
+                    base_notes[source as usize] = Some(estimate_base_note(apu.ram(), regs[0x5d], source));
+                }
+                base_notes[source as usize].unwrap()
+            }
+        };
+
+        base_note + 12.0 * ((pitch as f32) / 4096.0).log2()
+    }
+
+    fn pitch_bend(&self, semitones: f32) -> u16 {
+        let value = 8192.0 + semitones / (self.pitch_bend_range as f32) * 8192.0;
+        if value < 0.0 {
+            0
+        } else if value > 16383.0 {
+            16383
+        } else {
+            value.round() as u16
+        }
+    }
+
+    fn vol_velocity(regs: &[u8; 128], voice_index: usize) -> i32 {
+        let base = voice_index << 4;
+        let vol_left = ((regs[base] as i8) as i32).abs();
+        let vol_right = ((regs[base + 0x01] as i8) as i32).abs();
+        if vol_left > vol_right { vol_left } else { vol_right }
+    }
+
+    // The louder of the two VOLs, scaled by the peak ENVX. Notes too short to be seen sounding keep the VOL
+    //  alone.
+    fn velocity(vol_velocity: i32, peak_envx: u8) -> u8 {
+        let velocity = if peak_envx != 0 {
+            vol_velocity * (peak_envx as i32) / 0x7f
+        } else {
+            vol_velocity
+        };
+
+        if velocity < 1 {
+            1
+        } else if velocity > 127 {
+            127
+        } else {
+            velocity as u8
+        }
+    }
+
+    fn write_file(&self, tracks: &[VoiceTrack], end_time: u64) -> Vec<u8> {
+        let mut ret = Vec::new();
+        ret.extend_from_slice(b"MThd");
+        write_u32(&mut ret, 6);
+        write_u16(&mut ret, 1);
+        write_u16(&mut ret, (tracks.len() + 1) as u16);
+        write_u16(&mut ret, TICKS_PER_QUARTER_NOTE);
+
+        let tempo_events = vec![
+            (0, vec![0xff, 0x03, 0x04, b'S', b'N', b'E', b'S']),
+            (0, vec![0xff, 0x51, 0x03, (TEMPO >> 16) as u8, (TEMPO >> 8) as u8, TEMPO as u8]),
+        ];
+        write_track(&mut ret, &tempo_events, end_time);
+
+        for (i, track) in tracks.iter().enumerate() {
+            let channel = i as u8;
+            let mut events = vec![
+                (0, vec![0xff, 0x03, 0x07, b'V', b'o', b'i', b'c', b'e', b' ', b'0' + channel]),
+                // RPN 0 (pitch bend sensitivity)
+                (0, vec![0xb0 | channel, 101, 0]),
+                (0, vec![0xb0 | channel, 100, 0]),
+                (0, vec![0xb0 | channel, 6, self.pitch_bend_range]),
+                (0, vec![0xb0 | channel, 38, 0]),
+            ];
+            events.extend(track.events.iter().cloned());
+            write_track(&mut ret, &events, end_time);
+        }
+
+        ret
+    }
+}
+
+// Estimates the note a source plays at pitch $1000 by assuming its loop spans a single waveform period
+fn estimate_base_note(ram: &[u8], source_dir: u8, source: u8) -> f32 {
+    let entry_address = (source_dir as usize) * 0x100 + (source as usize) * 4;
+    let read_u16 = |address: usize| (ram[address & 0xffff] as usize) | ((ram[(address + 1) & 0xffff] as usize) << 8);
+    let start_address = read_u16(entry_address);
+    let loop_address = read_u16(entry_address + 2);
+
+    let mut address = start_address;
+    for _ in 0..(0x10000 / 9) {
+        let header = ram[address & 0xffff];
+        if (header & 0x01) != 0 {
+            if (header & 0x02) == 0 || loop_address < start_address || loop_address > address {
+                return DEFAULT_BASE_NOTE;
+            }
+            let loop_len = ((address + 9 - loop_address) / 9 * 16) as f32;
+            let frequency = (SAMPLE_RATE as f32) / loop_len;
+            return 69.0 + 12.0 * (frequency / 440.0).log2();
+        }
+        address += 9;
+    }
+
+    DEFAULT_BASE_NOTE
+}
+
+fn clamp_note(note: f32) -> u8 {
+    if note < 0.0 {
+        0
+    } else if note > 127.0 {
+        127
+    } else {
+        note as u8
+    }
+}
+
+fn write_track(buf: &mut Vec<u8>, events: &[(u64, Vec<u8>)], end_time: u64) {
+    let mut data = Vec::new();
+    let mut last_time = 0;
+    for &(time, ref event) in events.iter() {
+        write_var_len(&mut data, time - last_time);
+        data.extend_from_slice(event);
+        last_time = time;
+    }
+    write_var_len(&mut data, end_time - last_time);
+    data.extend_from_slice(&[0xff, 0x2f, 0x00]);
+
+    buf.extend_from_slice(b"MTrk");
+    write_u32(buf, data.len() as u32);
+    buf.extend_from_slice(&data);
+}
+
+fn write_var_len(buf: &mut Vec<u8>, value: u64) {
+    let mut bytes = [0; 10];
+    let mut len = 0;
+    let mut value = value;
+    loop {
+        bytes[len] = (value & 0x7f) as u8;
+        len += 1;
+        value >>= 7;
+        if value == 0 {
+            break;
+        }
+    }
+    for i in (0..len).rev() {
+        buf.push(if i > 0 { bytes[i] | 0x80 } else { bytes[i] });
+    }
+}
+
+fn write_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.push((value >> 8) as u8);
+    buf.push(value as u8);
+}
+
+fn write_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.push((value >> 24) as u8);
+    buf.push((value >> 16) as u8);
+    buf.push((value >> 8) as u8);
+    buf.push(value as u8);
+}
@@ -0,0 +1,132 @@
+// PSF container, which the snsf format (among many others) is built on: a header naming the system, a
+//  reserved area whose meaning is up to the system, a zlib-compressed program, and optional text tags.
+
+use super::error::SnsfError;
+
+use std::io::Read;
+
+use flate2::Crc;
+use flate2::read::ZlibDecoder;
+
+const PSF_MAGIC: &[u8] = b"PSF";
+const TAG_MAGIC: &[u8] = b"[TAG]";
+const HEADER_LEN: usize = 16;
+
+pub struct PsfFile {
+    // Which system the file is for, eg. 0x23 for snes
+    pub version: u8,
+    pub reserved: Vec<u8>,
+    // Decompressed
+    pub program: Vec<u8>,
+    // In file order, with repeated names already joined by newlines as the spec says
+    pub tags: Vec<(String, String)>
+}
+
+impl PsfFile {
+    pub fn parse(data: &[u8]) -> Result<PsfFile, SnsfError> {
+        if !data.starts_with(PSF_MAGIC) {
+            return Err(SnsfError::InvalidMagic);
+        }
+        if data.len() < HEADER_LEN {
+            return Err(SnsfError::Truncated { len: data.len(), expected: HEADER_LEN });
+        }
+        let reserved_len = read_u32(&data[4..]) as usize;
+        let program_len = read_u32(&data[8..]) as usize;
+        let crc = read_u32(&data[12..]);
+        // The lengths come straight from the file, so a corrupt one can be anything up to 4gb
+        let tags_offset = match HEADER_LEN.checked_add(reserved_len).and_then(|x| x.checked_add(program_len)) {
+            Some(tags_offset) if tags_offset <= data.len() => tags_offset,
+            tags_offset => return Err(SnsfError::Truncated { len: data.len(), expected: tags_offset.unwrap_or(usize::MAX) })
+        };
+        let program_offset = HEADER_LEN + reserved_len;
+
+        let compressed = &data[program_offset..tags_offset];
+        let mut program = Vec::new();
+        if !compressed.is_empty() {
+            let mut check = Crc::new();
+            check.update(compressed);
+            if check.sum() != crc {
+                return Err(SnsfError::CrcMismatch);
+            }
+            ZlibDecoder::new(compressed).read_to_end(&mut program).map_err(SnsfError::Decompress)?;
+        }
+
+        let tags = if data[tags_offset..].starts_with(TAG_MAGIC) {
+            parse_tags(&data[tags_offset + TAG_MAGIC.len()..])
+        } else {
+            Vec::new()
+        };
+
+        Ok(PsfFile {
+            version: data[3],
+            reserved: data[HEADER_LEN..program_offset].to_vec(),
+            program,
+            tags
+        })
+    }
+
+    // Tag names aren't case sensitive
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|tag| tag.0.eq_ignore_ascii_case(name)).map(|tag| tag.1.as_str())
+    }
+
+    // Names of the files this one builds on: _lib, then _lib2, _lib3 and so on until one is missing
+    pub fn libs(&self) -> Vec<&str> {
+        let mut ret = Vec::new();
+        if let Some(lib) = self.tag("_lib") {
+            ret.push(lib);
+        }
+        let mut i = 2;
+        while let Some(lib) = self.tag(&format!("_lib{}", i)) {
+            ret.push(lib);
+            i += 1;
+        }
+        ret
+    }
+}
+
+// Tag text is utf-8 when it's valid as such, and otherwise taken to be latin-1
+fn parse_tags(data: &[u8]) -> Vec<(String, String)> {
+    let text = match String::from_utf8(data.to_vec()) {
+        Ok(text) => text,
+        _ => data.iter().map(|&x| x as char).collect()
+    };
+
+    let mut ret: Vec<(String, String)> = Vec::new();
+    for line in text.split('\n') {
+        let (name, value) = match line.find('=') {
+            Some(index) => (line[..index].trim(), line[index + 1..].trim()),
+            _ => continue
+        };
+        if name.is_empty() {
+            continue;
+        }
+        match ret.iter_mut().find(|&&mut (ref x, _)| x.eq_ignore_ascii_case(name)) {
+            Some(&mut (_, ref mut existing)) => {
+                existing.push('\n');
+                existing.push_str(value);
+            },
+            _ => ret.push((name.to_string(), value.to_string()))
+        }
+    }
+    ret
+}
+
+// Parses a length or fade tag into milliseconds. Lengths are written [[h:]m:]s[.fff], with a comma allowed in
+//  place of the point.
+pub fn parse_length(value: &str) -> Option<u64> {
+    let mut ret = 0.0;
+    for part in value.trim().split(':') {
+        let part = part.trim().replace(',', ".");
+        let x = match part.parse::<f64>() {
+            Ok(x) if x >= 0.0 => x,
+            _ => return None
+        };
+        ret = ret * 60.0 + x;
+    }
+    Some((ret * 1000.0).round() as u64)
+}
+
+pub fn read_u32(data: &[u8]) -> u32 {
+    (data[0] as u32) | ((data[1] as u32) << 8) | ((data[2] as u32) << 16) | ((data[3] as u32) << 24)
+}
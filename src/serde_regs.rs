@@ -0,0 +1,21 @@
+// serde only implements its traits for arrays of up to 32 elements, so register files go through a slice
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error;
+
+use super::dsp::dsp::REG_LEN;
+
+use std::vec::Vec;
+
+pub fn serialize<S: Serializer>(regs: &[u8; REG_LEN], serializer: S) -> Result<S::Ok, S::Error> {
+    regs[..].serialize(serializer)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; REG_LEN], D::Error> {
+    let regs = Vec::<u8>::deserialize(deserializer)?;
+    if regs.len() != REG_LEN {
+        return Err(D::Error::invalid_length(regs.len(), &"128 registers"));
+    }
+    let mut ret = [0; REG_LEN];
+    ret.copy_from_slice(&regs);
+    Ok(ret)
+}
@@ -0,0 +1,64 @@
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SilenceDetector {
+    threshold: i16,
+    duration: i32,
+
+    silent_samples: i32,
+    is_end_of_track: bool,
+}
+
+impl SilenceDetector {
+    // threshold is the largest absolute sample value still considered silent, and duration is how
+    //  many samples of silence it takes to end the track
+    pub fn new(threshold: i16, duration: i32) -> SilenceDetector {
+        SilenceDetector {
+            threshold,
+            duration,
+
+            silent_samples: 0,
+            is_end_of_track: false,
+        }
+    }
+
+    pub fn threshold(&self) -> i16 {
+        self.threshold
+    }
+
+    pub fn duration(&self) -> i32 {
+        self.duration
+    }
+
+    pub fn is_end_of_track(&self) -> bool {
+        self.is_end_of_track
+    }
+
+    pub fn reset(&mut self) {
+        self.silent_samples = 0;
+        self.is_end_of_track = false;
+    }
+
+    pub fn process(&mut self, left: &[i16], right: &[i16]) {
+        for (&left, &right) in left.iter().zip(right.iter()) {
+            if !is_silent(left, self.threshold) || !is_silent(right, self.threshold) {
+                self.silent_samples = 0;
+            } else if self.silent_samples < self.duration {
+                self.silent_samples += 1;
+            }
+        }
+    }
+
+    pub fn is_output_silent(&self) -> bool {
+        self.silent_samples >= self.duration
+    }
+
+    // Silent output alone isn't enough to end the track (a voice may be held with its volume down, or
+    //  echo feedback may bring sound back later), so the apu confirms those separately before we latch
+    pub fn end_track(&mut self) {
+        self.is_end_of_track = true;
+    }
+}
+
+// Widened first, as the output (and echo ram) can hold -32768
+pub(crate) fn is_silent(sample: i16, threshold: i16) -> bool {
+    (sample as i32).abs() <= (threshold as i32)
+}
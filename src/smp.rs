@@ -1,6 +1,20 @@
 use super::apu::Apu;
+use super::state::SmpState;
 
+use std::mem;
+use std::vec::Vec;
+
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BusCycle {
+    Read(u16, u8),
+    Write(u16, u8),
+    Idle,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Smp {
+    #[cfg_attr(feature = "serde", serde(skip, default = "::std::ptr::null_mut"))]
     emulator: *mut Apu,
 
     pub reg_pc: u16,
@@ -20,7 +34,14 @@ pub struct Smp {
 
     is_stopped: bool,
 
-    cycle_count: i32
+    cycle_count: i32,
+    // Cycles the timers and dsp haven't been told about yet
+    #[cfg_attr(feature = "serde", serde(skip))]
+    unsynced_cycles: i32,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    is_eager_sync_enabled: bool,
+
+    bus_log: Option<Vec<BusCycle>>
 }
 
 impl Smp {
@@ -45,10 +66,19 @@ impl Smp {
 
             is_stopped: false,
 
-            cycle_count: 0
+            cycle_count: 0,
+            unsynced_cycles: 0,
+            is_eager_sync_enabled: false,
+
+            bus_log: None
         }
     }
 
+    #[cfg(feature = "serde")]
+    pub fn set_emulator(&mut self, emulator: *mut Apu) {
+        self.emulator = emulator;
+    }
+
     #[inline]
     fn emulator(&self) -> &mut Apu {
         unsafe {
@@ -68,7 +98,9 @@ impl Smp {
     pub fn set_psw(&mut self, value: u8) {
         self.psw_c = (value & 0x01) != 0;
         self.psw_z = (value & 0x02) != 0;
+        self.psw_i = (value & 0x04) != 0;
         self.psw_h = (value & 0x08) != 0;
+        self.psw_b = (value & 0x10) != 0;
         self.psw_p = (value & 0x20) != 0;
         self.psw_v = (value & 0x40) != 0;
         self.psw_n = (value & 0x80) != 0;
@@ -78,28 +110,111 @@ impl Smp {
         ((if self.psw_n { 1 } else { 0 }) << 7) |
         ((if self.psw_v { 1 } else { 0 }) << 6) |
         ((if self.psw_p { 1 } else { 0 }) << 5) |
+        ((if self.psw_b { 1 } else { 0 }) << 4) |
         ((if self.psw_h { 1 } else { 0 }) << 3) |
+        ((if self.psw_i { 1 } else { 0 }) << 2) |
         ((if self.psw_z { 1 } else { 0 }) << 1) |
         (if self.psw_c { 1 } else { 0 })
     }
 
+    pub fn state(&self) -> SmpState {
+        SmpState {
+            pc: self.reg_pc,
+            a: self.reg_a,
+            x: self.reg_x,
+            y: self.reg_y,
+            sp: self.reg_sp,
+            psw: self.get_psw(),
+            is_stopped: self.is_stopped
+        }
+    }
+
+    pub fn set_bus_logging(&mut self, enabled: bool) {
+        self.bus_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    // Clocks every cycle through to the timers and dsp as it happens instead of catching them up lazily. Output
+    //  is the same either way; this is only slower, and is here to check exactly that.
+    pub fn set_eager_sync(&mut self, enabled: bool) {
+        self.is_eager_sync_enabled = enabled;
+    }
+
+    pub fn take_bus_log(&mut self) -> Vec<BusCycle> {
+        match self.bus_log {
+            Some(ref mut log) => mem::take(log),
+            _ => Vec::new()
+        }
+    }
+
     fn is_negative(value: u32) -> bool {
         (value & 0x80) != 0
     }
 
-    fn cycles(&mut self, num_cycles: i32) {
-        self.emulator().cpu_cycles_callback(num_cycles);
-        self.cycle_count += num_cycles;
+    // Nothing but io reads and writes can see the timers or dsp, so the cycles in between are only counted, and
+    //  clocked through to them all at once before an access to $f0-$ff (and at the end of a run). Io still lands
+    //  on the exact cycle it would on hardware.
+    #[inline]
+    fn cycle(&mut self, bus_cycle: BusCycle) {
+        self.cycle_count += 1;
+        self.unsynced_cycles += 1;
+        if self.is_eager_sync_enabled {
+            self.sync();
+        }
+        if let Some(ref mut log) = self.bus_log {
+            log.push(bus_cycle);
+        }
+    }
+
+    #[inline]
+    fn sync(&mut self) {
+        if self.unsynced_cycles != 0 {
+            let num_cycles = self.unsynced_cycles;
+            self.unsynced_cycles = 0;
+            self.emulator().cpu_cycles_callback(num_cycles);
+        }
+    }
+
+    #[inline]
+    fn idle(&mut self) {
+        self.cycle(BusCycle::Idle);
     }
 
+    #[inline]
     fn read(&mut self, addr: u16) -> u8 {
-        self.cycles(1);
-        self.emulator().read_u8(addr as u32)
+        self.cycle_count += 1;
+        self.unsynced_cycles += 1;
+        if self.is_eager_sync_enabled {
+            self.sync();
+        }
+        let ret = if is_io_address(addr) {
+            self.sync();
+            self.emulator().read_u8(addr as u32)
+        } else if addr >= 0xffc0 {
+            self.emulator().read_u8(addr as u32)
+        } else {
+            self.emulator().ram()[addr as usize]
+        };
+        if let Some(ref mut log) = self.bus_log {
+            log.push(BusCycle::Read(addr, ret));
+        }
+        ret
     }
 
+    #[inline]
     fn write(&mut self, addr: u16, value: u8) {
-        self.cycles(1);
-        self.emulator().write_u8(addr as u32, value);
+        self.cycle(BusCycle::Write(addr, value));
+        if is_io_address(addr) {
+            self.sync();
+            self.emulator().write_u8(addr as u32, value);
+        } else {
+            self.emulator().ram_mut()[addr as usize] = value;
+        }
+    }
+
+    // Internal cycles that still put the next opcode's address on the bus
+    fn dummy_read_pc(&mut self) {
+        let addr = self.reg_pc;
+        self.read(addr);
     }
 
     fn read_pc(&mut self) -> u8 {
@@ -261,7 +376,7 @@ impl Smp {
 
     fn adjust_dpw(&mut self, x: u16) {
         let mut addr = self.read_pc();
-        let mut result = (self.read_dp(addr) as u16) + x;
+        let mut result = (self.read_dp(addr) as u16).wrapping_add(x);
         self.write_dp(addr, result as u8);
         addr = addr.wrapping_add(1);
         let mut high = (result >> 8) as u8;
@@ -277,25 +392,28 @@ impl Smp {
         if !cond {
             return;
         }
-        self.cycles(2);
+        self.idle();
+        self.idle();
         self.reg_pc = self.reg_pc.wrapping_add(((offset as i8) as i16) as u16);
     }
 
     fn branch_bit(&mut self, x: u8) {
         let addr = self.read_pc();
         let sp = self.read_dp(addr);
+        self.idle();
         let y = self.read_pc();
-        self.cycles(1);
         if ((sp & (1 << ((x as i32) >> 5))) != 0) == ((x & 0x10) != 0) {
             return;
         }
-        self.cycles(2);
+        self.idle();
+        self.idle();
         self.reg_pc = self.reg_pc.wrapping_add(((y as i8) as i16) as u16);
     }
 
     fn push(&mut self, x: u8) {
-        self.cycles(2);
+        self.dummy_read_pc();
         self.write_sp(x);
+        self.idle();
     }
 
     fn set_addr_bit(&mut self, opcode: u8) {
@@ -306,21 +424,21 @@ impl Smp {
         let mut y = self.read(x) as u16;
         match opcode >> 5 {
             0 | 1 => { // orc addr:bit; orc !addr:bit
-                self.cycles(1);
+                self.idle();
                 self.psw_c |= ((y & (1 << bit)) != 0) ^ ((opcode & 0x20) != 0);
             }
             2 | 3 => { // and addr:bit; and larrd:bit
                 self.psw_c &= ((y & (1 << bit)) != 0) ^ ((opcode & 0x20) != 0);
             }
             4 => { // eor addr:bit
-                self.cycles(1);
+                self.idle();
                 self.psw_c ^= (y & (1 << bit)) != 0;
             }
             5 => { // ldc addr:bit
                 self.psw_c = (y & (1 << bit)) != 0;
             }
             6 => { // stc addr:bit
-                self.cycles(1);
+                self.idle();
                 y = (y & !(1 << bit)) | ((if self.psw_c { 1 } else { 0 }) << bit);
                 self.write(x, y as u8);
             }
@@ -351,12 +469,13 @@ impl Smp {
     fn bne_dp(&mut self) {
         let addr = self.read_pc();
         let x = self.read_dp(addr);
+        self.idle();
         let y = self.read_pc();
-        self.cycles(1);
         if self.reg_a == x {
             return;
         }
-        self.cycles(2);
+        self.idle();
+        self.idle();
         self.reg_pc = self.reg_pc.wrapping_add(((y as i8) as i16) as u16);
     }
 
@@ -368,62 +487,69 @@ impl Smp {
         if x == 0 {
             return;
         }
-        self.cycles(2);
+        self.idle();
+        self.idle();
         self.reg_pc = self.reg_pc.wrapping_add(((y as i8) as i16) as u16);
     }
 
     fn bne_dp_x(&mut self) {
         let addr = self.read_pc();
-        self.cycles(1);
+        self.idle();
         let reg_x = self.reg_x;
         let x = self.read_dp(addr.wrapping_add(reg_x));
+        self.idle();
         let y = self.read_pc();
-        self.cycles(1);
         if self.reg_a == x {
             return;
         }
-        self.cycles(2);
+        self.idle();
+        self.idle();
         self.reg_pc = self.reg_pc.wrapping_add(((y as i8) as i16) as u16);
     }
 
     fn bne_y_dec(&mut self) {
+        self.dummy_read_pc();
+        self.idle();
         let x = self.read_pc();
-        self.cycles(2);
         self.reg_y = self.reg_y.wrapping_sub(1);
         if self.reg_y == 0 {
             return;
         }
-        self.cycles(2);
+        self.idle();
+        self.idle();
         self.reg_pc = self.reg_pc.wrapping_add(((x as i8) as i16) as u16);
     }
 
     fn brk(&mut self) {
-        let mut addr = self.read(0xffde) as u16;
-        addr |= (self.read(0xffdf) as u16) << 8;
-        self.cycles(2);
+        self.dummy_read_pc();
         let reg_pc = self.reg_pc;
         self.write_sp((reg_pc >> 8) as u8);
         self.write_sp(reg_pc as u8);
         let psw = self.get_psw();
         self.write_sp(psw);
+        self.idle();
+        let mut addr = self.read(0xffde) as u16;
+        addr |= (self.read(0xffdf) as u16) << 8;
         self.reg_pc = addr;
         self.psw_b = true;
         self.psw_i = false;
     }
 
     fn clv(&mut self) {
-        self.cycles(1);
+        self.dummy_read_pc();
         self.psw_v = false;
         self.psw_h = false;
     }
 
     fn cmc(&mut self) {
-        self.cycles(2);
+        self.dummy_read_pc();
+        self.idle();
         self.psw_c = !self.psw_c;
     }
 
     fn daa(&mut self) {
-        self.cycles(2);
+        self.dummy_read_pc();
+        self.idle();
         if self.psw_c || self.reg_a > 0x99 {
             self.reg_a = self.reg_a.wrapping_add(0x60);
             self.psw_c = true;
@@ -436,7 +562,8 @@ impl Smp {
     }
 
     fn das(&mut self) {
-        self.cycles(2);
+        self.dummy_read_pc();
+        self.idle();
         if !self.psw_c || self.reg_a > 0x99 {
             self.reg_a = self.reg_a.wrapping_sub(0x60);
             self.psw_c = false;
@@ -449,7 +576,10 @@ impl Smp {
     }
 
     fn div_ya(&mut self) {
-        self.cycles(11);
+        self.dummy_read_pc();
+        for _ in 0..10 {
+            self.idle();
+        }
         let ya = self.get_reg_ya();
         self.psw_v = self.reg_y >= self.reg_x;
         self.psw_h = (self.reg_y & 0x0f) >= (self.reg_x & 0x0f);
@@ -474,7 +604,7 @@ impl Smp {
     fn jmp_i_addr_x(&mut self) {
         let mut addr = self.read_pc() as u16;
         addr |= (self.read_pc() as u16) << 8;
-        self.cycles(1);
+        self.idle();
         addr = addr.wrapping_add(self.reg_x as u16);
         let mut addr2 = self.read(addr) as u16;
         addr = addr.wrapping_add(1);
@@ -484,47 +614,55 @@ impl Smp {
 
     fn jsp_dp(&mut self) {
         let addr = self.read_pc();
-        self.cycles(2);
+        self.idle();
         let reg_pc = self.reg_pc;
         self.write_sp((reg_pc >> 8) as u8);
         self.write_sp(reg_pc as u8);
+        self.idle();
         self.reg_pc = 0xff00 | (addr as u16);
     }
 
     fn jsr_addr(&mut self) {
         let mut addr = self.read_pc() as u16;
         addr |= (self.read_pc() as u16) << 8;
-        self.cycles(3);
+        self.idle();
         let reg_pc = self.reg_pc;
         self.write_sp((reg_pc >> 8) as u8);
         self.write_sp(reg_pc as u8);
+        self.idle();
+        self.idle();
         self.reg_pc = addr;
     }
 
     fn jst(&mut self, opcode: u8) {
+        self.dummy_read_pc();
+        self.idle();
+        let reg_pc = self.reg_pc;
+        self.write_sp((reg_pc >> 8) as u8);
+        self.write_sp(reg_pc as u8);
+        self.idle();
         let mut addr = 0xffde - (((opcode >> 4) << 1) as u16);
         let mut addr2 = self.read(addr) as u16;
         addr = addr.wrapping_add(1);
         addr2 |= (self.read(addr) as u16) << 8;
-        self.cycles(3);
-        let reg_pc = self.reg_pc;
-        self.write_sp((reg_pc >> 8) as u8);
-        self.write_sp(reg_pc as u8);
         self.reg_pc = addr2;
     }
 
     fn lda_i_x_inc(&mut self) {
-        self.cycles(1);
+        self.dummy_read_pc();
         let reg_x = self.reg_x;
         self.reg_a = self.read_dp(reg_x);
         self.reg_x = self.reg_x.wrapping_add(1);
-        self.cycles(1);
+        self.idle();
         let reg_a = self.reg_a;
         self.set_psw_n_z(reg_a as u32);
     }
 
     fn mul_ya(&mut self) {
-        self.cycles(8);
+        self.dummy_read_pc();
+        for _ in 0..7 {
+            self.idle();
+        }
         let ya = (self.reg_y as u16) * (self.reg_a as u16);
         self.reg_a = ya as u8;
         self.reg_y = (ya >> 8) as u8;
@@ -533,34 +671,37 @@ impl Smp {
     }
 
     fn nop(&mut self) {
-        self.cycles(1);
+        self.dummy_read_pc();
     }
 
     fn plp(&mut self) {
-        self.cycles(2);
+        self.dummy_read_pc();
+        self.idle();
         let psw = self.read_sp();
         self.set_psw(psw);
     }
 
     fn rti(&mut self) {
+        self.dummy_read_pc();
+        self.idle();
         let psw = self.read_sp();
         self.set_psw(psw);
         let mut addr = self.read_sp() as u16;
         addr |= (self.read_sp() as u16) << 8;
-        self.cycles(2);
         self.reg_pc = addr;
     }
 
     fn rts(&mut self) {
+        self.dummy_read_pc();
+        self.idle();
         let mut addr = self.read_sp() as u16;
         addr |= (self.read_sp() as u16) << 8;
-        self.cycles(2);
         self.reg_pc = addr;
     }
 
     fn sta_i_dp_x(&mut self) {
-        let mut addr = self.read_pc() + self.reg_x;
-        self.cycles(1);
+        let mut addr = self.read_pc().wrapping_add(self.reg_x);
+        self.idle();
         let mut addr2 = self.read_dp(addr) as u16;
         addr = addr.wrapping_add(1);
         addr2 |= (self.read_dp(addr) as u16) << 8;
@@ -574,7 +715,7 @@ impl Smp {
         let mut addr2 = self.read_dp(addr) as u16;
         addr = addr.wrapping_add(1);
         addr2 |= (self.read_dp(addr) as u16) << 8;
-        self.cycles(1);
+        self.idle();
         addr2 = addr2.wrapping_add(self.reg_y as u16);
         self.read(addr2);
         let reg_a = self.reg_a;
@@ -582,7 +723,7 @@ impl Smp {
     }
 
     fn sta_i_x(&mut self) {
-        self.cycles(1);
+        self.dummy_read_pc();
         let reg_x = self.reg_x;
         self.read_dp(reg_x);
         let reg_a = self.reg_a;
@@ -590,7 +731,8 @@ impl Smp {
     }
 
     fn sta_i_x_inc(&mut self) {
-        self.cycles(2);
+        self.dummy_read_pc();
+        self.idle();
         let reg_x = self.reg_x;
         let reg_a = self.reg_a;
         self.write_dp(reg_x, reg_a);
@@ -608,12 +750,16 @@ impl Smp {
     }
 
     fn sleep_stop(&mut self) {
-        self.cycles(2);
+        self.dummy_read_pc();
+        self.idle();
         self.is_stopped = true;
     }
 
     fn xcn(&mut self) {
-        self.cycles(4);
+        self.dummy_read_pc();
+        for _ in 0..3 {
+            self.idle();
+        }
         self.reg_a = (self.reg_a >> 4) | (self.reg_a << 4);
         let reg_a = self.reg_a;
         self.set_psw_n_z(reg_a as u32);
@@ -622,7 +768,7 @@ impl Smp {
     pub fn run(&mut self, target_cycles: i32) -> i32 {
         macro_rules! adjust {
             ($op:ident, $x:expr) => ({
-                self.cycles(1);
+                self.dummy_read_pc();
                 let temp = $x;
                 $x = self.$op(temp);
             })
@@ -650,7 +796,7 @@ impl Smp {
         macro_rules! adjust_dp_x {
             ($op:ident) => ({
                 let addr = self.read_pc();
-                self.cycles(1);
+                self.idle();
                 let mut reg_x = self.reg_x;
                 let mut result = self.read_dp(addr.wrapping_add(reg_x));
                 result = self.$op(result);
@@ -673,7 +819,7 @@ impl Smp {
             ($op:ident, $x:expr) => ({
                 let mut addr = self.read_pc() as u16;
                 addr |= (self.read_pc() as u16) << 8;
-                self.cycles(1);
+                self.idle();
                 let temp = $x;
                 let y = self.read(addr.wrapping_add(temp as u16));
                 let reg_a = self.reg_a;
@@ -701,7 +847,7 @@ impl Smp {
         macro_rules! read_dp_i {
             ($op:ident, $x:expr, $y:expr) => ({
                 let addr = self.read_pc();
-                self.cycles(1);
+                self.idle();
                 let mut temp = $y;
                 let z = self.read_dp(addr.wrapping_add(temp));
                 temp = $x;
@@ -715,7 +861,7 @@ impl Smp {
                 let mut x = self.read_dp(addr) as u16;
                 addr = addr.wrapping_add(1);
                 if !$is_cpw {
-                    self.cycles(1);
+                    self.idle();
                 }
                 x |= (self.read_dp(addr) as u16) << 8;
                 let ya = self.get_reg_ya();
@@ -727,7 +873,7 @@ impl Smp {
         macro_rules! read_i_dp_x {
             ($op:ident) => ({
                 let mut addr = self.read_pc().wrapping_add(self.reg_x);
-                self.cycles(1);
+                self.idle();
                 let mut addr2 = self.read_dp(addr) as u16;
                 addr = addr.wrapping_add(1);
                 addr2 |= (self.read_dp(addr) as u16) << 8;
@@ -740,10 +886,10 @@ impl Smp {
         macro_rules! read_i_dp_y {
             ($op:ident) => ({
                 let mut addr = self.read_pc();
-                self.cycles(1);
                 let mut addr2 = self.read_dp(addr) as u16;
                 addr = addr.wrapping_add(1);
                 addr2 |= (self.read_dp(addr) as u16) << 8;
+                self.idle();
                 let reg_y = self.reg_y;
                 let x = self.read(addr2.wrapping_add(reg_y as u16));
                 let reg_a = self.reg_a;
@@ -753,7 +899,7 @@ impl Smp {
 
         macro_rules! read_i_x {
             ($op:ident) => ({
-                self.cycles(1);
+                self.dummy_read_pc();
                 let reg_x = self.reg_x;
                 let x = self.read_dp(reg_x);
                 let reg_a = self.reg_a;
@@ -763,9 +909,9 @@ impl Smp {
 
         macro_rules! set_flag {
             ($x:expr, $y:expr, $is_dest_psw_i:expr) => ({
-                self.cycles(1);
+                self.dummy_read_pc();
                 if $is_dest_psw_i {
-                    self.cycles(1);
+                    self.idle();
                 }
                 $x = $y;
             })
@@ -773,7 +919,7 @@ impl Smp {
 
         macro_rules! transfer {
             ($x:expr, $y:expr, $is_dest_reg_sp:expr) => ({
-                self.cycles(1);
+                self.dummy_read_pc();
                 $y = $x;
                 if !$is_dest_reg_sp {
                     let temp = $y;
@@ -791,7 +937,7 @@ impl Smp {
                 if !$is_cmp {
                     self.write_dp(addr, y);
                 } else {
-                    self.cycles(1);
+                    self.idle();
                 }
             })
         }
@@ -806,14 +952,14 @@ impl Smp {
                 if !$is_cmp {
                     self.write_dp(y, z);
                 } else {
-                    self.cycles(1);
+                    self.idle();
                 }
             })
         }
 
         macro_rules! write_i_x_i_y {
             ($op:ident, $is_cmp:expr) => ({
-                self.cycles(1);
+                self.dummy_read_pc();
                 let reg_y = self.reg_y;
                 let x = self.read_dp(reg_y);
                 let reg_x = self.reg_x;
@@ -823,14 +969,15 @@ impl Smp {
                     let reg_x = self.reg_x;
                     self.write_dp(reg_x, y);
                 } else {
-                    self.cycles(1);
+                    self.idle();
                 }
             })
         }
 
         macro_rules! pull {
             ($x:expr) => ({
-                self.cycles(2);
+                self.dummy_read_pc();
+                self.idle();
                 $x = self.read_sp();
             })
         }
@@ -847,7 +994,7 @@ impl Smp {
         macro_rules! write_dp_i {
             ($x:expr, $y:expr) => ({
                 let addr = self.read_pc().wrapping_add($y);
-                self.cycles(1);
+                self.idle();
                 self.read_dp(addr);
                 let temp = $x;
                 self.write_dp(addr, temp);
@@ -868,7 +1015,7 @@ impl Smp {
             ($x:expr) => ({
                 let mut addr = self.read_pc() as u16;
                 addr |= (self.read_pc() as u16) << 8;
-                self.cycles(1);
+                self.idle();
                 addr = addr.wrapping_add($x as u16);
                 self.read(addr);
                 let reg_a = self.reg_a;
@@ -1156,10 +1303,16 @@ impl Smp {
                     _ => panic!("Invalid opcode")
                 }
             } else {
-                self.cycles(2);
+                self.dummy_read_pc();
+                self.idle();
             }
         }
+        self.sync();
 
         self.cycle_count
     }
 }
+
+fn is_io_address(addr: u16) -> bool {
+    (addr & 0xfff0) == 0x00f0
+}
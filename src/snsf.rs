@@ -0,0 +1,212 @@
+// SNSF (and miniSNSF) playback. An snsf is a whole game rom, with the soundtrack normally played by running
+//  the game's own code on an emulated snes cpu, which uploads the sound driver to the apu through the ipl rom.
+//  There's no cpu here; instead the rom is searched for the upload itself, in the block format the ipl rom
+//  protocol naturally leads games to store it in (length, address, data, ..., then a zero length and the entry
+//  point), and that's replayed through a scripted host. That covers sets where the driver starts playing once
+//  it's uploaded; anything that needs further commands from the cpu can be driven by a HostScript of its own.
+
+use super::apu::Apu;
+use super::dsp::dsp::SAMPLE_RATE;
+use super::error::SnsfError;
+use super::host::{HostScript, UploadBlock};
+use super::psf::{self, PsfFile};
+
+use std::cmp::Reverse;
+use std::fs;
+use std::path::Path;
+
+pub const SNSF_VERSION: u8 = 0x23;
+
+// Guards against _lib chains that loop back on themselves
+const MAX_LIB_DEPTH: usize = 10;
+// Larger than any real cartridge; chunks past it are taken to be corrupt rather than allocated
+const MAX_ROM_LEN: usize = 0x1000000;
+const MAX_UPLOAD_BLOCKS: usize = 64;
+// Anything shorter is more likely to be a coincidence in the rom than a sound driver
+const MIN_UPLOAD_LEN: usize = 0x200;
+
+// How block_chains packs a chain's block count and total length
+const NO_CHAIN: u32 = 0;
+const CHAIN_BLOCKS_SHIFT: u32 = 25;
+const CHAIN_LEN_MASK: u32 = (1 << CHAIN_BLOCKS_SHIFT) - 1;
+
+pub struct Upload {
+    pub blocks: Vec<UploadBlock>,
+    pub entry: u16
+}
+
+pub struct Snsf {
+    pub rom: Vec<u8>,
+    pub sram: Vec<u8>,
+    // The tags of the file that was opened, not its libs
+    pub tags: Vec<(String, String)>
+}
+
+impl Snsf {
+    // Loads an snsf or minisnsf, along with the libs it names from the same directory. Each lib is loaded
+    //  (recursively) in order, and the file's own data on top of them.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snsf, SnsfError> {
+        let mut ret = Snsf {
+            rom: Vec::new(),
+            sram: Vec::new(),
+            tags: Vec::new()
+        };
+        let file = ret.load_file(path.as_ref(), 0)?;
+        ret.tags = file.tags;
+        Ok(ret)
+    }
+
+    fn load_file(&mut self, path: &Path, depth: usize) -> Result<PsfFile, SnsfError> {
+        if depth > MAX_LIB_DEPTH {
+            return Err(SnsfError::LibTooDeep);
+        }
+        let file = PsfFile::parse(&fs::read(path)?)?;
+        if file.version != SNSF_VERSION {
+            return Err(SnsfError::UnsupportedVersion(file.version));
+        }
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for lib in file.libs() {
+            self.load_file(&dir.join(lib), depth + 1)?;
+        }
+
+        // The program is a single chunk of rom; the reserved area any number of chunks of sram
+        load_chunk(&mut self.rom, &file.program)?;
+        let mut pos = 0;
+        while pos < file.reserved.len() {
+            pos += load_chunk(&mut self.sram, &file.reserved[pos..])?;
+        }
+
+        Ok(file)
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|tag| tag.0.eq_ignore_ascii_case(name)).map(|tag| tag.1.as_str())
+    }
+
+    // Samples to play before fading out, and the length of the fade, from the length and fade tags
+    pub fn play_samples(&self) -> Option<u64> {
+        self.tag("length").and_then(psf::parse_length).map(ms_to_samples)
+    }
+
+    pub fn fade_samples(&self) -> Option<u64> {
+        self.tag("fade").and_then(psf::parse_length).map(ms_to_samples)
+    }
+
+    // Finds the largest well-formed upload in the rom (the first, if there's a tie)
+    pub fn find_upload(&self) -> Option<Upload> {
+        let chains = block_chains(&self.rom);
+        let mut offsets = (0..self.rom.len())
+            .filter(|&offset| chains[offset] != NO_CHAIN && ((chains[offset] & CHAIN_LEN_MASK) as usize) >= MIN_UPLOAD_LEN)
+            .collect::<Vec<_>>();
+        // Only the entry point is left to check, which the biggest chains will usually pass
+        offsets.sort_by_key(|&offset| Reverse(chains[offset] & CHAIN_LEN_MASK));
+        offsets.iter().filter_map(|&offset| parse_upload(&self.rom[offset..])).next().map(|(_, upload)| upload)
+    }
+
+    // Boots a fresh apu and uploads the driver found in the rom. The apu is left running the driver.
+    pub fn create_apu(&self) -> Result<Box<Apu>, SnsfError> {
+        let upload = match self.find_upload() {
+            Some(upload) => upload,
+            _ => return Err(SnsfError::NoUpload)
+        };
+        let mut ret = Apu::new();
+        HostScript::ipl_upload(&upload.blocks, upload.entry).run(&mut ret)?;
+        Ok(ret)
+    }
+}
+
+// Chunks are an offset and size, then the data; returns how much of data the chunk took up
+fn load_chunk(target: &mut Vec<u8>, data: &[u8]) -> Result<usize, SnsfError> {
+    if data.len() < 8 {
+        return Err(SnsfError::InvalidChunk);
+    }
+    let offset = psf::read_u32(data) as usize;
+    let size = psf::read_u32(&data[4..]) as usize;
+    let end = match offset.checked_add(size) {
+        Some(end) if end <= MAX_ROM_LEN && size <= data.len() - 8 => end,
+        _ => return Err(SnsfError::InvalidChunk)
+    };
+    if target.len() < end {
+        target.resize(end, 0);
+    }
+    target[offset..end].copy_from_slice(&data[8..8 + size]);
+    Ok(8 + size)
+}
+
+// For each offset in rom, the chain of upload blocks starting there, if it reaches a terminator (a zero
+//  length) without running off the end of the rom or hitting a block that can't be uploaded. Each is packed
+//  into a u32 as the number of blocks plus one above the blocks' total length, with NO_CHAIN where there's no
+//  chain. A block's chain is the one at the offset after it, so working backwards this takes one pass over the
+//  rom however many blocks there are.
+fn block_chains(rom: &[u8]) -> Vec<u32> {
+    let mut ret = vec![NO_CHAIN; rom.len()];
+    for pos in (0..rom.len()).rev() {
+        if pos + 4 > rom.len() {
+            continue;
+        }
+        let len = (rom[pos] as usize) | ((rom[pos + 1] as usize) << 8);
+        let address = (rom[pos + 2] as usize) | ((rom[pos + 3] as usize) << 8);
+        if len == 0 {
+            ret[pos] = 1 << CHAIN_BLOCKS_SHIFT;
+            continue;
+        }
+
+        let next = pos + 4 + len;
+        if !is_uploadable(address, len) || next >= rom.len() || ret[next] == NO_CHAIN {
+            continue;
+        }
+        let num_blocks = (ret[next] >> CHAIN_BLOCKS_SHIFT) as usize;
+        if num_blocks <= MAX_UPLOAD_BLOCKS {
+            ret[pos] = (((num_blocks + 1) as u32) << CHAIN_BLOCKS_SHIFT) | ((ret[next] & CHAIN_LEN_MASK) + len as u32);
+        }
+    }
+    ret
+}
+
+// Blocks have to fit in ram, and can't cover the io registers
+fn is_uploadable(address: usize, len: usize) -> bool {
+    let end = address + len;
+    end <= 0x10000 && (address >= 0x100 || end <= 0xf0)
+}
+
+// Returns the total length of the blocks along with the upload, if data starts with one. Nothing is copied
+//  until the whole chain checks out.
+fn parse_upload(data: &[u8]) -> Option<(usize, Upload)> {
+    let mut spans: Vec<(u16, usize, usize)> = Vec::new();
+    let mut total_len = 0;
+    let mut pos = 0;
+    loop {
+        if pos + 4 > data.len() {
+            return None;
+        }
+        let len = (data[pos] as usize) | ((data[pos + 1] as usize) << 8);
+        let address = (data[pos + 2] as u16) | ((data[pos + 3] as u16) << 8);
+        pos += 4;
+        if len == 0 {
+            let is_entry_uploaded = spans.iter().any(|&(block_address, _, block_len)| address >= block_address && ((address - block_address) as usize) < block_len);
+            if total_len < MIN_UPLOAD_LEN || !is_entry_uploaded {
+                return None;
+            }
+            let blocks = spans.iter().map(|&(address, pos, len)| UploadBlock {
+                address,
+                data: data[pos..pos + len].to_vec()
+            }).collect();
+            return Some((total_len, Upload {
+                blocks,
+                entry: address
+            }));
+        }
+
+        if spans.len() >= MAX_UPLOAD_BLOCKS || !is_uploadable(address as usize, len) || pos + len > data.len() {
+            return None;
+        }
+        spans.push((address, pos, len));
+        total_len += len;
+        pos += len;
+    }
+}
+
+fn ms_to_samples(ms: u64) -> u64 {
+    ms * (SAMPLE_RATE as u64) / 1000
+}
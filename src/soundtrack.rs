@@ -0,0 +1,292 @@
+// A set of spc files distributed together, such as a game's soundtrack. The tracks are enumerated and their
+//  tags parsed up front; the spc data itself is only read again when a track is started, so a set opened from
+//  a directory or an archive file never has to be held in memory or unpacked to disk. An archive passed in as
+//  bytes or a reader is kept in memory, still compressed.
+
+use super::apu::{self, Apu};
+use super::error::SoundtrackError;
+use super::id666::Id666Tag;
+use super::xid6::Xid6Tag;
+use super::dsp::dsp::SAMPLE_RATE;
+
+use std::fs::{self, File};
+#[cfg(feature = "zip")]
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "zip")]
+use std::io::Cursor;
+#[cfg(feature = "zip")]
+use zip::ZipArchive;
+
+#[cfg(feature = "zip")]
+const RAR_MAGIC: &[u8] = b"Rar!";
+
+pub struct Track {
+    // File name, relative to the root of the set
+    pub name: String,
+
+    pub song_title: String,
+    pub game_title: String,
+    pub artist_name: String,
+    pub comments: String,
+
+    // Samples to play before fading out, and the length of the fade. These come from the xid6 tag when it has
+    //  exact lengths, otherwise from the id666 tag.
+    pub play_samples: Option<u64>,
+    pub fade_samples: Option<u64>,
+
+    pub xid6_tag: Option<Xid6Tag>
+}
+
+// A file that looked like a track but couldn't be read or parsed, eg. a truncated spc
+pub struct SkippedTrack {
+    pub name: String,
+    pub error: SoundtrackError
+}
+
+enum Source {
+    Directory(PathBuf),
+    #[cfg(feature = "zip")]
+    Zip(ZipArchive<Cursor<Vec<u8>>>),
+    // Entries are read from the file as they're needed
+    #[cfg(feature = "zip")]
+    ZipFile(ZipArchive<File>)
+}
+
+pub struct SoundtrackSet {
+    source: Source,
+    tracks: Vec<Track>,
+    // One bad file shouldn't make the rest of the set unplayable, so these are kept to one side instead
+    skipped_tracks: Vec<SkippedTrack>,
+    // Contents of the set's info text file, if it has one
+    info: Option<String>
+}
+
+impl SoundtrackSet {
+    // Opens every .spc file in a directory, including subdirectories
+    pub fn from_directory<P: AsRef<Path>>(path: P) -> Result<SoundtrackSet, SoundtrackError> {
+        let root = path.as_ref().to_path_buf();
+        let mut names = Vec::new();
+        find_files(&root, &root, &mut names)?;
+
+        SoundtrackSet::load(Source::Directory(root), names)
+    }
+
+    #[cfg(feature = "zip")]
+    pub fn from_zip<R: Read>(mut reader: R) -> Result<SoundtrackSet, SoundtrackError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        SoundtrackSet::from_zip_bytes(data)
+    }
+
+    #[cfg(feature = "zip")]
+    pub fn from_zip_bytes(data: Vec<u8>) -> Result<SoundtrackSet, SoundtrackError> {
+        if data.starts_with(RAR_MAGIC) {
+            return Err(SoundtrackError::UnsupportedArchive);
+        }
+        let mut archive = ZipArchive::new(Cursor::new(data))?;
+        let names = zip_file_names(&mut archive)?;
+        SoundtrackSet::load(Source::Zip(archive), names)
+    }
+
+    #[cfg(feature = "zip")]
+    fn from_zip_file(mut file: File) -> Result<SoundtrackSet, SoundtrackError> {
+        let mut magic = Vec::new();
+        (&mut file).take(RAR_MAGIC.len() as u64).read_to_end(&mut magic)?;
+        if magic == RAR_MAGIC {
+            return Err(SoundtrackError::UnsupportedArchive);
+        }
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut archive = ZipArchive::new(file)?;
+        let names = zip_file_names(&mut archive)?;
+        SoundtrackSet::load(Source::ZipFile(archive), names)
+    }
+
+    fn load(source: Source, mut names: Vec<String>) -> Result<SoundtrackSet, SoundtrackError> {
+        names.sort();
+
+        let mut ret = SoundtrackSet {
+            source,
+            tracks: Vec::new(),
+            skipped_tracks: Vec::new(),
+            info: None
+        };
+        for name in names {
+            if is_spc_name(&name) {
+                match ret.read_file(&name).and_then(|data| parse_track(name.clone(), &data)) {
+                    Ok(track) => ret.tracks.push(track),
+                    Err(e) => ret.skipped_tracks.push(SkippedTrack { name, error: e })
+                }
+            } else if is_info_name(&name) && ret.info.is_none() {
+                let data = ret.read_file(&name)?;
+                ret.info = Some(String::from_utf8_lossy(&data).into_owned());
+            }
+        }
+        Ok(ret)
+    }
+
+    // Picks a source based on what's at path: a directory, or a zip archive (which may be named .rsn)
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SoundtrackSet, SoundtrackError> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            return SoundtrackSet::from_directory(path);
+        }
+        open_archive(File::open(path)?)
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn skipped_tracks(&self) -> &[SkippedTrack] {
+        &self.skipped_tracks
+    }
+
+    pub fn info(&self) -> Option<&str> {
+        self.info.as_deref()
+    }
+
+    // Reads the complete spc file for a track
+    pub fn track_data(&mut self, index: usize) -> Result<Vec<u8>, SoundtrackError> {
+        let name = match self.tracks.get(index) {
+            Some(track) => track.name.clone(),
+            _ => return Err(SoundtrackError::TrackIndexOutOfRange(index))
+        };
+        self.read_file(&name)
+    }
+
+    pub fn create_apu(&mut self, index: usize) -> Result<Box<Apu>, SoundtrackError> {
+        let data = self.track_data(index)?;
+        Apu::from_spc_bytes(&data).map_err(|e| SoundtrackError::InvalidTrack {
+            name: self.tracks[index].name.clone(),
+            error: e
+        })
+    }
+
+    fn read_file(&mut self, name: &str) -> Result<Vec<u8>, SoundtrackError> {
+        match self.source {
+            Source::Directory(ref root) => Ok(fs::read(root.join(name))?),
+            #[cfg(feature = "zip")]
+            Source::Zip(ref mut archive) => read_zip_file(archive, name),
+            #[cfg(feature = "zip")]
+            Source::ZipFile(ref mut archive) => read_zip_file(archive, name)
+        }
+    }
+}
+
+#[cfg(feature = "zip")]
+fn open_archive(file: File) -> Result<SoundtrackSet, SoundtrackError> {
+    SoundtrackSet::from_zip_file(file)
+}
+
+#[cfg(feature = "zip")]
+fn zip_file_names<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<Vec<String>, SoundtrackError> {
+    let mut names = Vec::new();
+    for i in 0..archive.len() {
+        let file = archive.by_index(i)?;
+        if !file.is_dir() {
+            names.push(file.name().to_string());
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(feature = "zip")]
+fn read_zip_file<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Vec<u8>, SoundtrackError> {
+    let mut file = archive.by_name(name)?;
+    let mut ret = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut ret)?;
+    Ok(ret)
+}
+
+#[cfg(not(feature = "zip"))]
+fn open_archive(_: File) -> Result<SoundtrackSet, SoundtrackError> {
+    Err(SoundtrackError::UnsupportedArchive)
+}
+
+fn find_files(root: &Path, dir: &Path, names: &mut Vec<String>) -> Result<(), SoundtrackError> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_files(root, &path, names)?;
+        } else if let Ok(name) = path.strip_prefix(root) {
+            // Use the same separator as zip entry names, so tracks are named the same whatever the source
+            let name = name.components().map(|component| component.as_os_str().to_string_lossy().into_owned()).collect::<Vec<_>>().join("/");
+            names.push(name);
+        }
+    }
+    Ok(())
+}
+
+fn has_extension(name: &str, extension: &str) -> bool {
+    Path::new(name).extension().is_some_and(|x| x.to_string_lossy().eq_ignore_ascii_case(extension))
+}
+
+fn is_spc_name(name: &str) -> bool {
+    has_extension(name, "spc")
+}
+
+fn is_info_name(name: &str) -> bool {
+    has_extension(name, "txt")
+}
+
+fn parse_track(name: String, data: &[u8]) -> Result<Track, SoundtrackError> {
+    let invalid = |name: String, e| SoundtrackError::InvalidTrack { name, error: e };
+    if let Err(e) = apu::validate_spc_bytes(data) {
+        return Err(invalid(name, e));
+    }
+    let xid6_tag = match Xid6Tag::from_spc_bytes(data) {
+        Ok(tag) => tag,
+        Err(e) => return Err(invalid(name, e))
+    };
+
+    let mut ret = Track {
+        name,
+
+        song_title: String::new(),
+        game_title: String::new(),
+        artist_name: String::new(),
+        comments: String::new(),
+
+        play_samples: None,
+        fade_samples: None,
+
+        xid6_tag: None
+    };
+
+    if let Some(tag) = Id666Tag::from_spc_bytes(data) {
+        let sample_rate = SAMPLE_RATE as u64;
+        if tag.seconds_to_play_before_fading_out != 0 {
+            ret.play_samples = Some((tag.seconds_to_play_before_fading_out as u64) * sample_rate);
+            ret.fade_samples = Some((tag.fade_out_length as u64) * sample_rate / 1000);
+        }
+        ret.song_title = tag.song_title;
+        ret.game_title = tag.game_title;
+        ret.artist_name = tag.artist_name;
+        ret.comments = tag.comments;
+    }
+
+    if let Some(ref tag) = xid6_tag {
+        if let Some(ref song_name) = tag.song_name {
+            ret.song_title = song_name.clone();
+        }
+        if let Some(ref game_name) = tag.game_name {
+            ret.game_title = game_name.clone();
+        }
+        if let Some(ref artist_name) = tag.artist_name {
+            ret.artist_name = artist_name.clone();
+        }
+        if let Some(ref comments) = tag.comments {
+            ret.comments = comments.clone();
+        }
+        if let Some(play_samples) = tag.play_samples() {
+            ret.play_samples = Some(play_samples);
+            ret.fade_samples = tag.fade_samples().or(ret.fade_samples);
+        }
+    }
+    ret.xid6_tag = xid6_tag;
+
+    Ok(ret)
+}
@@ -0,0 +1,463 @@
+// SPC2 container: many songs in one file, with each song's 64kb of ram split into 256-byte blocks that are
+//  stored once however many songs share them. Songs from the same game usually share their driver and most
+//  of their samples, so a soundtrack packs down to a fraction of the size of its spc files.
+//
+// Layout (little endian throughout):
+//  0x00   "KSPC" 0x1a, major version (1), minor version, song count (u16), 7 reserved bytes
+//  0x10   one 1024-byte metadata block per song:
+//         0x000 dsp registers, 0x080 ipl rom, 0x0c0 pc (u16), a, x, y, psw, sp, channel disables,
+//         0x0c8 date dumped (year u16, month, day), 0x0cc play length and 0x0d0 fade length (ticks),
+//         0x0d4 amplification, 0x0d8 emulator, ost disc, ost track, 0x0dc copyright year (u16),
+//         0x100 ram block index (u16) for each of the 256 pages of ram,
+//         0x300 song, game, artist, dumper, comments, ost title, publisher and original file name,
+//               32 bytes each
+//  after the metadata blocks, the ram blocks themselves
+
+use super::apu::{self, Apu, DEFAULT_IPL_ROM, IPL_ROM_LEN, RAM_LEN, SPC_MAGIC, SPC_VERSION, SPC_VERSION_OFFSET, SPC_PADDING_OFFSET, SPC_RAM_OFFSET, SPC_REGS_OFFSET, SPC_IPL_ROM_OFFSET};
+use super::dsp::dsp::REG_LEN;
+use super::error::SpcError;
+use super::id666::{self, Id666Tag};
+use super::xid6::{Xid6Tag, XID6_OFFSET, TICKS_PER_SECOND};
+
+use std::boxed::Box;
+use std::collections::BTreeMap;
+use std::string::String;
+use std::vec::Vec;
+
+pub const RAM_BLOCK_LEN: usize = 256;
+
+const SPC2_MAGIC: &[u8] = b"KSPC\x1a";
+const MAJOR_VERSION: u8 = 1;
+const MINOR_VERSION: u8 = 1;
+const HEADER_LEN: usize = 0x10;
+
+const SONG_LEN: usize = 0x400;
+const DSP_REGS_OFFSET: usize = 0x000;
+const IPL_ROM_OFFSET: usize = 0x080;
+const CPU_REGS_OFFSET: usize = 0x0c0;
+const DATE_OFFSET: usize = 0x0c8;
+const PLAY_LENGTH_OFFSET: usize = 0x0cc;
+const FADE_LENGTH_OFFSET: usize = 0x0d0;
+const AMPLIFICATION_OFFSET: usize = 0x0d4;
+const EMULATOR_OFFSET: usize = 0x0d8;
+const OST_DISC_OFFSET: usize = 0x0d9;
+const OST_TRACK_OFFSET: usize = 0x0da;
+const COPYRIGHT_YEAR_OFFSET: usize = 0x0dc;
+const RAM_BLOCKS_OFFSET: usize = 0x100;
+const STRINGS_OFFSET: usize = 0x300;
+const STRING_LEN: usize = 32;
+const NUM_PAGES: usize = RAM_LEN / RAM_BLOCK_LEN;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Spc2Song {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub psw: u8,
+    pub sp: u8,
+
+    // RAM_LEN, REG_LEN and IPL_ROM_LEN bytes respectively
+    pub ram: Box<[u8]>,
+    pub dsp_regs: Box<[u8]>,
+    pub ipl_rom: Box<[u8]>,
+
+    pub song_name: String,
+    pub game_name: String,
+    pub artist_name: String,
+    pub dumper_name: String,
+    pub comments: String,
+    pub ost_title: String,
+    pub publisher: String,
+    // Name of the spc file the song came from, if any
+    pub file_name: String,
+
+    // Stored as yyyymmdd
+    pub date_dumped: Option<u32>,
+    pub emulator: u8,
+    // Zero when unknown
+    pub ost_disc: u8,
+    pub ost_track: u8,
+    pub copyright_year: u16,
+
+    // In ticks (1/64000 of a second); zero when unknown
+    pub play_length: u32,
+    pub fade_length: u32,
+    // 16.16 fixed point, or zero for the player's default
+    pub amplification: u32,
+    // One bit per voice
+    pub channel_disables: u8
+}
+
+impl Default for Spc2Song {
+    fn default() -> Spc2Song {
+        Spc2Song::new()
+    }
+}
+
+impl Spc2Song {
+    pub fn new() -> Spc2Song {
+        Spc2Song {
+            pc: 0,
+            a: 0,
+            x: 0,
+            y: 0,
+            psw: 0,
+            sp: 0,
+
+            ram: vec![0; RAM_LEN].into_boxed_slice(),
+            dsp_regs: vec![0; REG_LEN].into_boxed_slice(),
+            ipl_rom: DEFAULT_IPL_ROM.to_vec().into_boxed_slice(),
+
+            song_name: String::new(),
+            game_name: String::new(),
+            artist_name: String::new(),
+            dumper_name: String::new(),
+            comments: String::new(),
+            ost_title: String::new(),
+            publisher: String::new(),
+            file_name: String::new(),
+
+            date_dumped: None,
+            emulator: 0,
+            ost_disc: 0,
+            ost_track: 0,
+            copyright_year: 0,
+
+            play_length: 0,
+            fade_length: 0,
+            amplification: 0,
+            channel_disables: 0
+        }
+    }
+
+    // Takes the state and tags of an spc file. Where a value is in both the id666 and xid6 tags, the xid6
+    //  one wins, as it has the full string or exact length.
+    pub fn from_spc_bytes(data: &[u8]) -> Result<Spc2Song, SpcError> {
+        apu::validate_spc_bytes(data)?;
+        let xid6_tag = Xid6Tag::from_spc_bytes(data)?;
+
+        let mut ret = Spc2Song::new();
+        ret.pc = (data[0x25] as u16) | ((data[0x26] as u16) << 8);
+        ret.a = data[0x27];
+        ret.x = data[0x28];
+        ret.y = data[0x29];
+        ret.psw = data[0x2a];
+        ret.sp = data[0x2b];
+        ret.ram.copy_from_slice(&data[SPC_RAM_OFFSET..SPC_RAM_OFFSET + RAM_LEN]);
+        ret.dsp_regs.copy_from_slice(&data[SPC_REGS_OFFSET..SPC_REGS_OFFSET + REG_LEN]);
+        ret.ipl_rom.copy_from_slice(&data[SPC_IPL_ROM_OFFSET..SPC_IPL_ROM_OFFSET + IPL_ROM_LEN]);
+
+        if let Some(tag) = Id666Tag::from_spc_bytes(data) {
+            ret.song_name = tag.song_title;
+            ret.game_name = tag.game_title;
+            ret.artist_name = tag.artist_name;
+            ret.dumper_name = tag.dumper_name;
+            ret.comments = tag.comments;
+            ret.date_dumped = parse_date(&tag.date_dumped);
+            ret.emulator = tag.dumping_emulator;
+            ret.play_length = tag.seconds_to_play_before_fading_out.saturating_mul(TICKS_PER_SECOND);
+            ret.fade_length = tag.fade_out_length.saturating_mul(TICKS_PER_SECOND / 1000);
+            ret.channel_disables = tag.default_channel_disables;
+        }
+
+        if let Some(tag) = xid6_tag {
+            if let Some(play_ticks) = tag.play_ticks() {
+                ret.play_length = play_ticks.min(u32::MAX as u64) as u32;
+            }
+            let strings = vec![
+                (tag.song_name, &mut ret.song_name),
+                (tag.game_name, &mut ret.game_name),
+                (tag.artist_name, &mut ret.artist_name),
+                (tag.dumper_name, &mut ret.dumper_name),
+                (tag.comments, &mut ret.comments),
+                (tag.ost_title, &mut ret.ost_title),
+                (tag.publisher, &mut ret.publisher)];
+            for (value, field) in strings {
+                if let Some(value) = value {
+                    *field = value;
+                }
+            }
+            ret.date_dumped = tag.date_dumped.or(ret.date_dumped);
+            ret.emulator = tag.emulator.unwrap_or(ret.emulator);
+            ret.ost_disc = tag.ost_disc.unwrap_or(0);
+            ret.ost_track = tag.ost_track.map_or(0, |(track, _)| track);
+            ret.copyright_year = tag.copyright_year.unwrap_or(0);
+            ret.fade_length = tag.fade_length.unwrap_or(ret.fade_length);
+            ret.amplification = tag.amplification.unwrap_or(0);
+            ret.channel_disables = tag.muted_voices.unwrap_or(ret.channel_disables);
+        }
+
+        Ok(ret)
+    }
+
+    // Rebuilds a complete spc file, with an id666 tag and an xid6 tag for anything id666 can't hold
+    pub fn to_spc_bytes(&self) -> Vec<u8> {
+        let mut ret = vec![0; XID6_OFFSET];
+        ret[..SPC_MAGIC.len()].copy_from_slice(SPC_MAGIC);
+        ret[SPC_VERSION_OFFSET..SPC_PADDING_OFFSET].copy_from_slice(SPC_VERSION);
+        ret[SPC_PADDING_OFFSET] = 0x1a;
+        ret[SPC_PADDING_OFFSET + 1] = 0x1a;
+        ret[0x24] = 30;
+        ret[0x25] = self.pc as u8;
+        ret[0x26] = (self.pc >> 8) as u8;
+        ret[0x27] = self.a;
+        ret[0x28] = self.x;
+        ret[0x29] = self.y;
+        ret[0x2a] = self.psw;
+        ret[0x2b] = self.sp;
+
+        let mut id666_tag = Id666Tag::new();
+        id666_tag.song_title = self.song_name.clone();
+        id666_tag.game_title = self.game_name.clone();
+        id666_tag.artist_name = self.artist_name.clone();
+        id666_tag.dumper_name = self.dumper_name.clone();
+        id666_tag.comments = self.comments.clone();
+        if let Some(date) = self.date_dumped {
+            id666_tag.date_dumped = format!("{:02}/{:02}/{:04}", (date / 100) % 100, date % 100, date / 10000);
+        }
+        id666_tag.seconds_to_play_before_fading_out = self.play_length.div_ceil(TICKS_PER_SECOND);
+        id666_tag.fade_out_length = self.fade_length / (TICKS_PER_SECOND / 1000);
+        id666_tag.default_channel_disables = self.channel_disables;
+        id666_tag.dumping_emulator = self.emulator;
+        id666_tag.write_to_spc(&mut ret);
+
+        ret[SPC_RAM_OFFSET..SPC_RAM_OFFSET + RAM_LEN].copy_from_slice(&self.ram);
+        ret[SPC_REGS_OFFSET..SPC_REGS_OFFSET + REG_LEN].copy_from_slice(&self.dsp_regs);
+        ret[SPC_IPL_ROM_OFFSET..SPC_IPL_ROM_OFFSET + IPL_ROM_LEN].copy_from_slice(&self.ipl_rom);
+
+        let non_empty = |x: &String| if x.is_empty() { None } else { Some(x.clone()) };
+        let non_zero = |x: u32| if x == 0 { None } else { Some(x) };
+        let mut xid6_tag = Xid6Tag::new();
+        xid6_tag.song_name = non_empty(&self.song_name);
+        xid6_tag.game_name = non_empty(&self.game_name);
+        xid6_tag.artist_name = non_empty(&self.artist_name);
+        xid6_tag.dumper_name = non_empty(&self.dumper_name);
+        xid6_tag.comments = non_empty(&self.comments);
+        xid6_tag.ost_title = non_empty(&self.ost_title);
+        xid6_tag.publisher = non_empty(&self.publisher);
+        xid6_tag.ost_disc = non_zero(self.ost_disc as u32).map(|x| x as u8);
+        xid6_tag.ost_track = non_zero(self.ost_track as u32).map(|x| (x as u8, 0));
+        xid6_tag.copyright_year = non_zero(self.copyright_year as u32).map(|x| x as u16);
+        xid6_tag.intro_length = non_zero(self.play_length);
+        xid6_tag.fade_length = non_zero(self.fade_length);
+        xid6_tag.amplification = non_zero(self.amplification);
+        if xid6_tag != Xid6Tag::new() {
+            xid6_tag.write_to_spc(&mut ret);
+        }
+
+        ret
+    }
+
+    pub fn create_apu(&self) -> Result<Box<Apu>, SpcError> {
+        Apu::from_spc_bytes(&self.to_spc_bytes())
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Spc2 {
+    pub songs: Vec<Spc2Song>
+}
+
+impl Default for Spc2 {
+    fn default() -> Spc2 {
+        Spc2::new()
+    }
+}
+
+impl Spc2 {
+    pub fn new() -> Spc2 {
+        Spc2 {
+            songs: Vec::new()
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Spc2, SpcError> {
+        if !data.starts_with(SPC2_MAGIC) {
+            return Err(SpcError::InvalidMagic);
+        }
+        if data.len() < HEADER_LEN {
+            return Err(SpcError::Truncated { len: data.len(), expected: HEADER_LEN });
+        }
+        if data[5] != MAJOR_VERSION {
+            return Err(SpcError::UnsupportedVersion);
+        }
+        let num_songs = read_u16(&data[7..]) as usize;
+        let ram_blocks_offset = HEADER_LEN + num_songs * SONG_LEN;
+        if data.len() < ram_blocks_offset {
+            return Err(SpcError::Truncated { len: data.len(), expected: ram_blocks_offset });
+        }
+        let num_ram_blocks = (data.len() - ram_blocks_offset) / RAM_BLOCK_LEN;
+
+        let mut ret = Spc2::new();
+        for i in 0..num_songs {
+            let song_data = &data[HEADER_LEN + i * SONG_LEN..HEADER_LEN + (i + 1) * SONG_LEN];
+            let mut song = Spc2Song::new();
+
+            song.dsp_regs.copy_from_slice(&song_data[DSP_REGS_OFFSET..DSP_REGS_OFFSET + REG_LEN]);
+            song.ipl_rom.copy_from_slice(&song_data[IPL_ROM_OFFSET..IPL_ROM_OFFSET + IPL_ROM_LEN]);
+            song.pc = read_u16(&song_data[CPU_REGS_OFFSET..]);
+            song.a = song_data[CPU_REGS_OFFSET + 2];
+            song.x = song_data[CPU_REGS_OFFSET + 3];
+            song.y = song_data[CPU_REGS_OFFSET + 4];
+            song.psw = song_data[CPU_REGS_OFFSET + 5];
+            song.sp = song_data[CPU_REGS_OFFSET + 6];
+            song.channel_disables = song_data[CPU_REGS_OFFSET + 7];
+
+            let year = read_u16(&song_data[DATE_OFFSET..]) as u32;
+            if year != 0 {
+                song.date_dumped = Some(year * 10000 + (song_data[DATE_OFFSET + 2] as u32) * 100 + (song_data[DATE_OFFSET + 3] as u32));
+            }
+            song.play_length = read_u32(&song_data[PLAY_LENGTH_OFFSET..]);
+            song.fade_length = read_u32(&song_data[FADE_LENGTH_OFFSET..]);
+            song.amplification = read_u32(&song_data[AMPLIFICATION_OFFSET..]);
+            song.emulator = song_data[EMULATOR_OFFSET];
+            song.ost_disc = song_data[OST_DISC_OFFSET];
+            song.ost_track = song_data[OST_TRACK_OFFSET];
+            song.copyright_year = read_u16(&song_data[COPYRIGHT_YEAR_OFFSET..]);
+
+            for page in 0..NUM_PAGES {
+                let index = read_u16(&song_data[RAM_BLOCKS_OFFSET + page * 2..]);
+                if (index as usize) >= num_ram_blocks {
+                    return Err(SpcError::InvalidRamBlock(index));
+                }
+                let block_offset = ram_blocks_offset + (index as usize) * RAM_BLOCK_LEN;
+                song.ram[page * RAM_BLOCK_LEN..(page + 1) * RAM_BLOCK_LEN].copy_from_slice(&data[block_offset..block_offset + RAM_BLOCK_LEN]);
+            }
+
+            {
+                let mut strings = [
+                    &mut song.song_name,
+                    &mut song.game_name,
+                    &mut song.artist_name,
+                    &mut song.dumper_name,
+                    &mut song.comments,
+                    &mut song.ost_title,
+                    &mut song.publisher,
+                    &mut song.file_name];
+                for (i, field) in strings.iter_mut().enumerate() {
+                    let offset = STRINGS_OFFSET + i * STRING_LEN;
+                    **field = id666::read_string(&song_data[offset..offset + STRING_LEN]);
+                }
+            }
+
+            ret.songs.push(song);
+        }
+
+        Ok(ret)
+    }
+
+    // Appends the whole file. Each distinct ram block is stored once, in the order songs first use them;
+    //  strings longer than 32 bytes are truncated.
+    pub fn write(&self, out: &mut Vec<u8>) -> Result<(), SpcError> {
+        if self.songs.len() > 0xffff {
+            return Err(SpcError::Spc2TooLarge);
+        }
+
+        let mut data = Vec::with_capacity(HEADER_LEN + self.songs.len() * SONG_LEN);
+        data.extend_from_slice(SPC2_MAGIC);
+        data.push(MAJOR_VERSION);
+        data.push(MINOR_VERSION);
+        write_u16(&mut data, self.songs.len() as u16);
+        data.resize(HEADER_LEN, 0);
+
+        let mut ram_blocks: Vec<&[u8]> = Vec::new();
+        let mut ram_block_indices: BTreeMap<&[u8], u16> = BTreeMap::new();
+        for song in self.songs.iter() {
+            let song_data_offset = data.len();
+            data.resize(song_data_offset + SONG_LEN, 0);
+            let song_data = &mut data[song_data_offset..];
+
+            song_data[DSP_REGS_OFFSET..DSP_REGS_OFFSET + REG_LEN].copy_from_slice(&song.dsp_regs);
+            song_data[IPL_ROM_OFFSET..IPL_ROM_OFFSET + IPL_ROM_LEN].copy_from_slice(&song.ipl_rom);
+            set_u16(&mut song_data[CPU_REGS_OFFSET..], song.pc);
+            song_data[CPU_REGS_OFFSET + 2] = song.a;
+            song_data[CPU_REGS_OFFSET + 3] = song.x;
+            song_data[CPU_REGS_OFFSET + 4] = song.y;
+            song_data[CPU_REGS_OFFSET + 5] = song.psw;
+            song_data[CPU_REGS_OFFSET + 6] = song.sp;
+            song_data[CPU_REGS_OFFSET + 7] = song.channel_disables;
+
+            if let Some(date) = song.date_dumped {
+                set_u16(&mut song_data[DATE_OFFSET..], (date / 10000) as u16);
+                song_data[DATE_OFFSET + 2] = ((date / 100) % 100) as u8;
+                song_data[DATE_OFFSET + 3] = (date % 100) as u8;
+            }
+            set_u32(&mut song_data[PLAY_LENGTH_OFFSET..], song.play_length);
+            set_u32(&mut song_data[FADE_LENGTH_OFFSET..], song.fade_length);
+            set_u32(&mut song_data[AMPLIFICATION_OFFSET..], song.amplification);
+            song_data[EMULATOR_OFFSET] = song.emulator;
+            song_data[OST_DISC_OFFSET] = song.ost_disc;
+            song_data[OST_TRACK_OFFSET] = song.ost_track;
+            set_u16(&mut song_data[COPYRIGHT_YEAR_OFFSET..], song.copyright_year);
+
+            for (page, block) in song.ram.chunks(RAM_BLOCK_LEN).enumerate() {
+                let index = match ram_block_indices.get(block) {
+                    Some(&index) => index,
+                    _ => {
+                        if ram_blocks.len() > 0xffff {
+                            return Err(SpcError::Spc2TooLarge);
+                        }
+                        let index = ram_blocks.len() as u16;
+                        ram_blocks.push(block);
+                        ram_block_indices.insert(block, index);
+                        index
+                    }
+                };
+                set_u16(&mut song_data[RAM_BLOCKS_OFFSET + page * 2..], index);
+            }
+
+            let strings = [
+                &song.song_name,
+                &song.game_name,
+                &song.artist_name,
+                &song.dumper_name,
+                &song.comments,
+                &song.ost_title,
+                &song.publisher,
+                &song.file_name];
+            for (i, value) in strings.iter().enumerate() {
+                let offset = STRINGS_OFFSET + i * STRING_LEN;
+                id666::write_string(&mut song_data[offset..offset + STRING_LEN], value);
+            }
+        }
+
+        out.extend_from_slice(&data);
+        for block in ram_blocks {
+            out.extend_from_slice(block);
+        }
+        Ok(())
+    }
+}
+
+// "MM/DD/YYYY", as an id666 tag stores it
+fn parse_date(date: &str) -> Option<u32> {
+    let parts = date.split('/').map(|x| x.trim().parse::<u32>().ok()).collect::<Vec<_>>();
+    match parts.as_slice() {
+        [Some(month), Some(day), Some(year)] if *year != 0 => Some(year * 10000 + month * 100 + day),
+        _ => None
+    }
+}
+
+fn read_u16(data: &[u8]) -> u16 {
+    (data[0] as u16) | ((data[1] as u16) << 8)
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    (read_u16(data) as u32) | ((read_u16(&data[2..]) as u32) << 16)
+}
+
+fn write_u16(out: &mut Vec<u8>, value: u16) {
+    out.push(value as u8);
+    out.push((value >> 8) as u8);
+}
+
+fn set_u16(data: &mut [u8], value: u16) {
+    data[0] = value as u8;
+    data[1] = (value >> 8) as u8;
+}
+
+fn set_u32(data: &mut [u8], value: u32) {
+    set_u16(data, value as u16);
+    set_u16(&mut data[2..], (value >> 16) as u16);
+}
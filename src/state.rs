@@ -0,0 +1,89 @@
+// Read-only snapshots of emulator state for debuggers and visualizers. These are plain copies, so they stay
+//  stable while the internals they're built from change.
+
+use super::dsp::dsp::{NUM_VOICES, REG_LEN};
+use super::dsp::envelope::Mode;
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SmpState {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub psw: u8,
+    pub is_stopped: bool,
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TimerState {
+    pub is_running: bool,
+    pub target: u8,
+    pub stage_2: u8,
+    pub counter: u8,
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VoiceState {
+    pub vol_left: u8,
+    pub vol_right: u8,
+    pub pitch: u16,
+    pub source: u8,
+    pub pitch_mod: bool,
+    pub noise_on: bool,
+    pub echo_on: bool,
+
+    pub sample_start_address: u16,
+    pub loop_start_address: u16,
+    // Address of the brr block being decoded, and the index of the next sample to be read from it
+    pub brr_block_address: u16,
+    pub brr_sample_index: i32,
+    // 12-bit fractional position between decoded samples
+    pub interpolation_pos: i32,
+    pub kon_delay: i32,
+
+    pub envelope_mode: Mode,
+    pub envelope_level: i32,
+    pub adsr0: u8,
+    pub adsr1: u8,
+    pub gain: u8,
+
+    pub outx: u8,
+    pub envx: u8,
+
+    pub is_muted: bool,
+    pub is_solod: bool,
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DspState {
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_regs"))]
+    pub regs: [u8; REG_LEN],
+
+    pub vol_left: u8,
+    pub vol_right: u8,
+    pub echo_vol_left: u8,
+    pub echo_vol_right: u8,
+    pub noise_clock: u8,
+    pub noise: i32,
+    pub is_soft_reset: bool,
+    pub is_output_muted: bool,
+    pub kon: u8,
+    pub koff: u8,
+
+    pub source_dir: u8,
+    pub echo_write_enabled: bool,
+    pub echo_feedback: u8,
+    pub echo_start_address: u16,
+    pub echo_delay: u8,
+    pub echo_pos: i32,
+    pub echo_length: i32,
+
+    pub sample_count: u64,
+
+    pub voices: [VoiceState; NUM_VOICES],
+}
@@ -1,60 +1,101 @@
+use super::state::TimerState;
+
+// Stage 1 is a free-running divider off the smp clock (8khz for timers 0 and 1, 64khz for timer 2), stage 2
+//  is an 8-bit up-counter compared against the target, and stage 3 is the 4-bit counter visible at $fd-$ff
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Timer {
-    resolution: i32,
+    cycles_per_tick: i32,
+    cycles: i32,
     is_running: bool,
-    ticks: i32,
-    target: Option<u8>,
-    counter_low: u8,
-    counter_high: u8
+    target: u8,
+    stage_2: u8,
+    stage_3: u8
 }
 
 impl Timer {
-    pub fn new(resolution: i32) -> Timer {
+    pub fn new(cycles_per_tick: i32) -> Timer {
         Timer {
-            resolution: resolution,
+            cycles_per_tick,
+            cycles: 0,
             is_running: false,
-            ticks: 0,
-            target: None,
-            counter_low: 0,
-            counter_high: 0
+            target: 0,
+            stage_2: 0,
+            stage_3: 0
         }
     }
 
+    // Takes the same time however many cycles have gone by, as the smp only catches the timers up when it
+    //  touches io
     pub fn cpu_cycles_callback(&mut self, num_cycles: i32) {
-        if !self.is_running {
+        self.cycles += num_cycles;
+        let num_ticks = (self.cycles / self.cycles_per_tick) as u32;
+        self.cycles %= self.cycles_per_tick;
+        if !self.is_running || num_ticks == 0 {
             return;
         }
-        self.ticks += num_cycles;
-        while self.ticks > self.resolution {
-            self.ticks -= self.resolution;
-
-            self.counter_low += 1;
-            if let Some(target) = self.target {
-                if self.counter_low == target {
-                    self.counter_high += 1;
-                    self.counter_low = 0;
-                }
-            }
+
+        // Stage 2 is compared after it's incremented, so a target of 0 (or one stage 2 has already passed) is
+        //  only reached after wrapping around
+        let ticks_to_target = (self.target.wrapping_sub(self.stage_2).wrapping_sub(1) as u32) + 1;
+        if num_ticks < ticks_to_target {
+            self.stage_2 = self.stage_2.wrapping_add(num_ticks as u8);
+            return;
         }
+        let ticks_per_wrap = (self.target.wrapping_sub(1) as u32) + 1;
+        let num_ticks = num_ticks - ticks_to_target;
+        self.stage_2 = (num_ticks % ticks_per_wrap) as u8;
+        self.stage_3 = ((self.stage_3 as u32 + 1 + num_ticks / ticks_per_wrap) & 0x0f) as u8;
     }
 
     pub fn set_start_stop_bit(&mut self, value: bool) {
         if value && !self.is_running {
-            self.ticks = 0;
-            self.counter_low = 0;
+            self.stage_2 = 0;
+            self.stage_3 = 0;
         }
         self.is_running = value;
     }
 
     pub fn set_target(&mut self, value: u8) {
-        self.target = match value {
-            0 => None,
-            x => Some(x)
-        };
+        self.target = value;
+    }
+
+    pub fn set_counter(&mut self, value: u8) {
+        self.stage_3 = value & 0x0f;
     }
 
     pub fn read_counter(&mut self) -> u8 {
-        let ret = self.counter_high & 0x0f;
-        self.counter_high = 0;
+        let ret = self.stage_3;
+        self.stage_3 = 0;
         ret
     }
+
+    pub fn state(&self) -> TimerState {
+        TimerState {
+            is_running: self.is_running,
+            target: self.target,
+            stage_2: self.stage_2,
+            counter: self.stage_3
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.is_running
+    }
+
+    pub fn target(&self) -> u8 {
+        self.target
+    }
+
+    pub fn stage_2(&self) -> u8 {
+        self.stage_2
+    }
+
+    // Peeks at the counter without the reset a read through $fd-$ff causes
+    pub fn counter(&self) -> u8 {
+        self.stage_3
+    }
+
+    pub fn cycles_per_tick(&self) -> i32 {
+        self.cycles_per_tick
+    }
 }
@@ -0,0 +1,63 @@
+// JavaScript interface, built with the wasm feature for wasm32-unknown-unknown. Nothing here touches threads or
+//  the filesystem, so a player can live entirely inside an AudioWorkletProcessor: hand it the bytes of an spc file
+//  once, then have it fill the worklet's output each process() call.
+
+use wasm_bindgen::prelude::*;
+
+use super::apu::Apu;
+use super::dsp::dsp::{NUM_VOICES, SAMPLE_RATE};
+
+#[wasm_bindgen]
+pub struct SpcPlayer {
+    apu: Box<Apu>,
+    position: u64
+}
+
+#[wasm_bindgen]
+impl SpcPlayer {
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: &[u8]) -> Result<SpcPlayer, String> {
+        let mut apu = match Apu::from_spc_bytes(data) {
+            Ok(apu) => apu,
+            Err(e) => return Err(e.to_string())
+        };
+        apu.clear_echo_buffer();
+        Ok(SpcPlayer {
+            apu,
+            position: 0
+        })
+    }
+
+    // Output is always 32khz; the host is expected to resample if its AudioContext runs at another rate
+    pub fn sample_rate() -> u32 {
+        SAMPLE_RATE as u32
+    }
+
+    // Fills out with out.length / 2 interleaved stereo frames, scaled to [-1, 1)
+    pub fn render(&mut self, out: &mut [f32]) {
+        self.apu.render_interleaved_f32(out);
+        self.position += (out.len() / 2) as u64;
+    }
+
+    pub fn set_voice_muted(&mut self, voice: usize, is_muted: bool) -> Result<(), String> {
+        if voice >= NUM_VOICES {
+            return Err(format!("Invalid voice index: {}", voice));
+        }
+        self.apu.dsp.as_mut().unwrap().voices[voice].is_muted = is_muted;
+        Ok(())
+    }
+
+    pub fn set_voice_solo(&mut self, voice: usize, is_solod: bool) -> Result<(), String> {
+        if voice >= NUM_VOICES {
+            return Err(format!("Invalid voice index: {}", voice));
+        }
+        self.apu.dsp.as_mut().unwrap().voices[voice].is_solod = is_solod;
+        Ok(())
+    }
+
+    // Frames rendered since the spc was loaded. Returned as a double rather than a BigInt, which is exact for
+    //  a few thousand years of playback.
+    pub fn position(&self) -> f64 {
+        self.position as f64
+    }
+}
@@ -0,0 +1,289 @@
+// Extended ID666 tag, stored as a chunk after the ipl rom in an spc file. It carries metadata the fixed-size
+//  ID666 block has no room for, plus lengths in ticks (1/64000 of a second) exact enough to end a track on the
+//  sample it should.
+
+use super::error::SpcError;
+
+use std::string::String;
+use std::vec::Vec;
+
+pub const XID6_OFFSET: usize = 0x10200;
+pub const TICKS_PER_SECOND: u32 = 64000;
+
+const XID6_MAGIC: &[u8] = b"xid6";
+const HEADER_LEN: usize = 8;
+const SUB_CHUNK_HEADER_LEN: usize = 4;
+const MAX_STRING_LEN: usize = 255;
+
+// Sub-chunk types
+const TYPE_DATA: u8 = 0;
+const TYPE_STRING: u8 = 1;
+const TYPE_INTEGER: u8 = 4;
+
+// Sub-chunk ids
+const ID_SONG_NAME: u8 = 0x01;
+const ID_GAME_NAME: u8 = 0x02;
+const ID_ARTIST_NAME: u8 = 0x03;
+const ID_DUMPER_NAME: u8 = 0x04;
+const ID_DATE_DUMPED: u8 = 0x05;
+const ID_EMULATOR: u8 = 0x06;
+const ID_COMMENTS: u8 = 0x07;
+const ID_OST_TITLE: u8 = 0x10;
+const ID_OST_DISC: u8 = 0x11;
+const ID_OST_TRACK: u8 = 0x12;
+const ID_PUBLISHER: u8 = 0x13;
+const ID_COPYRIGHT_YEAR: u8 = 0x14;
+const ID_INTRO_LENGTH: u8 = 0x30;
+const ID_LOOP_LENGTH: u8 = 0x31;
+const ID_END_LENGTH: u8 = 0x32;
+const ID_FADE_LENGTH: u8 = 0x33;
+const ID_MUTED_VOICES: u8 = 0x34;
+const ID_LOOP_COUNT: u8 = 0x35;
+const ID_AMPLIFICATION: u8 = 0x36;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Xid6Tag {
+    pub song_name: Option<String>,
+    pub game_name: Option<String>,
+    pub artist_name: Option<String>,
+    pub dumper_name: Option<String>,
+    // Stored as yyyymmdd
+    pub date_dumped: Option<u32>,
+    pub emulator: Option<u8>,
+    pub comments: Option<String>,
+
+    pub ost_title: Option<String>,
+    pub ost_disc: Option<u8>,
+    // Track number (0-99) and an optional ascii suffix, eg. 12b, with 0 for none
+    pub ost_track: Option<(u8, u8)>,
+    pub publisher: Option<String>,
+    pub copyright_year: Option<u16>,
+
+    // In ticks
+    pub intro_length: Option<u32>,
+    pub loop_length: Option<u32>,
+    pub end_length: Option<u32>,
+    pub fade_length: Option<u32>,
+
+    // One bit per voice
+    pub muted_voices: Option<u8>,
+    pub loop_count: Option<u8>,
+    // 16.16 fixed point, 0x10000 being unity gain
+    pub amplification: Option<u32>
+}
+
+impl Default for Xid6Tag {
+    fn default() -> Xid6Tag {
+        Xid6Tag::new()
+    }
+}
+
+impl Xid6Tag {
+    pub fn new() -> Xid6Tag {
+        Xid6Tag {
+            song_name: None,
+            game_name: None,
+            artist_name: None,
+            dumper_name: None,
+            date_dumped: None,
+            emulator: None,
+            comments: None,
+
+            ost_title: None,
+            ost_disc: None,
+            ost_track: None,
+            publisher: None,
+            copyright_year: None,
+
+            intro_length: None,
+            loop_length: None,
+            end_length: None,
+            fade_length: None,
+
+            muted_voices: None,
+            loop_count: None,
+            amplification: None
+        }
+    }
+
+    // Returns the tag following the ipl rom in a complete spc file, or None if it doesn't have one. Rips often
+    //  carry padding or other junk after the ipl rom, so that only counts as a (broken) tag if it has the magic.
+    pub fn from_spc_bytes(data: &[u8]) -> Result<Option<Xid6Tag>, SpcError> {
+        if data.len() <= XID6_OFFSET || !data[XID6_OFFSET..].starts_with(XID6_MAGIC) {
+            return Ok(None);
+        }
+        Xid6Tag::parse(&data[XID6_OFFSET..]).map(Some)
+    }
+
+    // Parses a tag from the start of its chunk header
+    pub fn parse(data: &[u8]) -> Result<Xid6Tag, SpcError> {
+        if !data.starts_with(XID6_MAGIC) || data.len() < HEADER_LEN {
+            return Err(SpcError::InvalidXid6Tag);
+        }
+        let chunk_len = read_u32(&data[4..]) as usize;
+        let end = match HEADER_LEN.checked_add(chunk_len) {
+            Some(end) if end <= data.len() => end,
+            Some(end) => return Err(SpcError::Truncated { len: data.len(), expected: end }),
+            _ => return Err(SpcError::InvalidXid6Tag)
+        };
+
+        let mut ret = Xid6Tag::new();
+        let mut pos = HEADER_LEN;
+        while pos + SUB_CHUNK_HEADER_LEN <= end {
+            let id = data[pos];
+            let data_type = data[pos + 1];
+            let len = (data[pos + 2] as usize) | ((data[pos + 3] as usize) << 8);
+            pos += SUB_CHUNK_HEADER_LEN;
+
+            // Small values are stored in the length field itself
+            let (value, sub_chunk_data) = match data_type {
+                TYPE_DATA => (len as u32, &data[pos..pos]),
+                _ => {
+                    if pos + len > end {
+                        return Err(SpcError::InvalidXid6Tag);
+                    }
+                    let sub_chunk_data = &data[pos..pos + len];
+                    pos += (len + 3) & !3;
+                    (read_u32(sub_chunk_data), sub_chunk_data)
+                }
+            };
+
+            match id {
+                ID_SONG_NAME => ret.song_name = Some(read_string(sub_chunk_data)),
+                ID_GAME_NAME => ret.game_name = Some(read_string(sub_chunk_data)),
+                ID_ARTIST_NAME => ret.artist_name = Some(read_string(sub_chunk_data)),
+                ID_DUMPER_NAME => ret.dumper_name = Some(read_string(sub_chunk_data)),
+                ID_DATE_DUMPED => ret.date_dumped = Some(value),
+                ID_EMULATOR => ret.emulator = Some(value as u8),
+                ID_COMMENTS => ret.comments = Some(read_string(sub_chunk_data)),
+                ID_OST_TITLE => ret.ost_title = Some(read_string(sub_chunk_data)),
+                ID_OST_DISC => ret.ost_disc = Some(value as u8),
+                ID_OST_TRACK => ret.ost_track = Some(((value >> 8) as u8, value as u8)),
+                ID_PUBLISHER => ret.publisher = Some(read_string(sub_chunk_data)),
+                ID_COPYRIGHT_YEAR => ret.copyright_year = Some(value as u16),
+                ID_INTRO_LENGTH => ret.intro_length = Some(value),
+                ID_LOOP_LENGTH => ret.loop_length = Some(value),
+                ID_END_LENGTH => ret.end_length = Some(value),
+                ID_FADE_LENGTH => ret.fade_length = Some(value),
+                ID_MUTED_VOICES => ret.muted_voices = Some(value as u8),
+                ID_LOOP_COUNT => ret.loop_count = Some(value as u8),
+                ID_AMPLIFICATION => ret.amplification = Some(value),
+                _ => () // Unknown sub-chunks are skipped
+            }
+        }
+
+        Ok(ret)
+    }
+
+    // Appends the chunk, header included
+    pub fn write(&self, out: &mut Vec<u8>) {
+        let mut chunk = Vec::new();
+        write_string(&mut chunk, ID_SONG_NAME, &self.song_name);
+        write_string(&mut chunk, ID_GAME_NAME, &self.game_name);
+        write_string(&mut chunk, ID_ARTIST_NAME, &self.artist_name);
+        write_string(&mut chunk, ID_DUMPER_NAME, &self.dumper_name);
+        write_integer(&mut chunk, ID_DATE_DUMPED, self.date_dumped);
+        write_data(&mut chunk, ID_EMULATOR, self.emulator.map(|x| x as u16));
+        write_string(&mut chunk, ID_COMMENTS, &self.comments);
+        write_string(&mut chunk, ID_OST_TITLE, &self.ost_title);
+        write_data(&mut chunk, ID_OST_DISC, self.ost_disc.map(|x| x as u16));
+        write_data(&mut chunk, ID_OST_TRACK, self.ost_track.map(|(track, suffix)| ((track as u16) << 8) | (suffix as u16)));
+        write_string(&mut chunk, ID_PUBLISHER, &self.publisher);
+        write_data(&mut chunk, ID_COPYRIGHT_YEAR, self.copyright_year);
+        write_integer(&mut chunk, ID_INTRO_LENGTH, self.intro_length);
+        write_integer(&mut chunk, ID_LOOP_LENGTH, self.loop_length);
+        write_integer(&mut chunk, ID_END_LENGTH, self.end_length);
+        write_integer(&mut chunk, ID_FADE_LENGTH, self.fade_length);
+        write_data(&mut chunk, ID_MUTED_VOICES, self.muted_voices.map(|x| x as u16));
+        write_data(&mut chunk, ID_LOOP_COUNT, self.loop_count.map(|x| x as u16));
+        write_integer(&mut chunk, ID_AMPLIFICATION, self.amplification);
+
+        out.extend_from_slice(XID6_MAGIC);
+        write_u32(out, chunk.len() as u32);
+        out.extend_from_slice(&chunk);
+    }
+
+    // Replaces whatever follows the ipl rom in a complete spc file with this tag
+    pub fn write_to_spc(&self, data: &mut Vec<u8>) {
+        data.resize(XID6_OFFSET, 0);
+        self.write(data);
+    }
+
+    // Number of samples to play before fading out: the intro, loop_count repetitions of the loop (once if the
+    //  tag doesn't say), then the end. None if the tag has no intro length.
+    pub fn play_samples(&self) -> Option<u64> {
+        self.play_ticks().map(ticks_to_samples)
+    }
+
+    // The same, in ticks
+    pub fn play_ticks(&self) -> Option<u64> {
+        self.intro_length.map(|intro_length| {
+            let loop_count = self.loop_count.unwrap_or(1) as u64;
+            (intro_length as u64) + (self.loop_length.unwrap_or(0) as u64) * loop_count + (self.end_length.unwrap_or(0) as u64)
+        })
+    }
+
+    pub fn fade_samples(&self) -> Option<u64> {
+        self.fade_length.map(|fade_length| ticks_to_samples(fade_length as u64))
+    }
+}
+
+fn ticks_to_samples(ticks: u64) -> u64 {
+    ticks / 2
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    let mut ret = 0;
+    for (i, &x) in data.iter().take(4).enumerate() {
+        ret |= (x as u32) << (i * 8);
+    }
+    ret
+}
+
+fn read_string(data: &[u8]) -> String {
+    let len = data.iter().position(|&x| x == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..len]).into_owned()
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    for i in 0..4 {
+        out.push((value >> (i * 8)) as u8);
+    }
+}
+
+fn write_sub_chunk_header(out: &mut Vec<u8>, id: u8, data_type: u8, len: u16) {
+    out.push(id);
+    out.push(data_type);
+    out.push(len as u8);
+    out.push((len >> 8) as u8);
+}
+
+fn write_data(out: &mut Vec<u8>, id: u8, value: Option<u16>) {
+    if let Some(value) = value {
+        write_sub_chunk_header(out, id, TYPE_DATA, value);
+    }
+}
+
+fn write_integer(out: &mut Vec<u8>, id: u8, value: Option<u32>) {
+    if let Some(value) = value {
+        write_sub_chunk_header(out, id, TYPE_INTEGER, 4);
+        write_u32(out, value);
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, id: u8, value: &Option<String>) {
+    if let Some(ref value) = *value {
+        let mut len = value.len().min(MAX_STRING_LEN);
+        while !value.is_char_boundary(len) {
+            len -= 1;
+        }
+        let bytes = &value.as_bytes()[..len];
+        let len = bytes.len() + 1;
+        write_sub_chunk_header(out, id, TYPE_STRING, len as u16);
+        out.extend_from_slice(bytes);
+        out.push(0);
+        while out.len() & 3 != 0 {
+            out.push(0);
+        }
+    }
+}
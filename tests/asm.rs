@@ -0,0 +1,138 @@
+extern crate snes_apu;
+
+use snes_apu::apu::Apu;
+use snes_apu::asm::{self, assemble, disassemble};
+use snes_apu::error::AsmError;
+use snes_apu::host::IplUploader;
+
+#[test]
+fn assembles_program() {
+    let assembly = assemble("
+        count = 3
+        .org $0200
+start:  mov $12, #$34       ; dp, imm
+        mov $12, $34
+        mov a, !$0012
+        mov x, #count
+loop:   mov a, table+x
+        and1 c, /$1234.5
+        set1 $56.7
+        dec x
+        bpl loop
+        bra done
+table:  .db 1, 2, 'A', \"hi\"
+        .dw start, >table
+done:   jmp !done
+").unwrap();
+
+    assert_eq!(assembly.blocks.len(), 1);
+    assert_eq!(assembly.blocks[0].address, 0x0200);
+    assert_eq!(assembly.symbol("start"), Some(0x0200));
+    assert_eq!(assembly.symbol("loop"), Some(0x020b));
+    assert_eq!(assembly.symbol("count"), Some(3));
+    assert_eq!(assembly.blocks[0].data, vec![
+        0x8f, 0x34, 0x12,
+        0xfa, 0x34, 0x12,
+        0xe5, 0x12, 0x00,
+        0xcd, 0x03,
+        0xf5, 0x18, 0x02,
+        0x6a, 0x34, 0xb2,
+        0xe2, 0x56,
+        0x1d,
+        0x10, 0xf5,
+        0x2f, 0x09,
+        0x01, 0x02, 0x41, 0x68, 0x69,
+        0x00, 0x02, 0x02, 0x00,
+        0x5f, 0x21, 0x02]);
+}
+
+#[test]
+fn disassembles_program() {
+    let lines = disassemble(&[0x8f, 0x34, 0x12, 0xd7, 0x80, 0x6a, 0x34, 0xb2, 0xde, 0x12, 0xfb, 0x3f, 0x00, 0x04], 0x0400);
+    let expected = [
+        (0x0400, "mov $12, #$34"),
+        (0x0403, "mov [$80]+y, a"),
+        (0x0405, "and1 c, /!$1234.5"),
+        (0x0408, "cbne $12+x, $0406"),
+        (0x040b, "call !$0400")];
+    assert_eq!(lines.len(), expected.len());
+    for (line, &(address, text)) in lines.iter().zip(expected.iter()) {
+        assert_eq!(line.0, address);
+        assert_eq!(line.1, text);
+    }
+}
+
+#[test]
+fn all_opcodes_round_trip() {
+    for opcode in 0..256 {
+        let data = [opcode as u8, 0x12, 0x34];
+        let (text, len) = asm::disassemble_instruction(&data, 0x0400);
+        assert_eq!(len, asm::instruction_len(opcode as u8));
+        let assembly = assemble(&format!(".org $0400\n{}", text)).unwrap_or_else(|e| panic!("{}: {}", text, e));
+        assert_eq!(assembly.blocks[0].data, &data[..len], "{}", text);
+    }
+}
+
+#[test]
+fn assembled_program_runs() {
+    let assembly = assemble("
+        .org $0300
+        mov a, #0
+        mov x, #10
+loop:   clrc
+        adc a, #3
+        dec x
+        bne loop
+        mov $f6, a
+        bra *
+").unwrap();
+
+    let mut apu = Apu::new();
+    {
+        let mut uploader = IplUploader::new(&mut apu).unwrap();
+        for block in assembly.blocks.iter() {
+            uploader.upload(block.address, &block.data).unwrap();
+        }
+        uploader.jump(assembly.symbol("loop").unwrap() - 4).unwrap();
+    }
+    let mut left = [0; 16];
+    let mut right = [0; 16];
+    apu.render(&mut left, &mut right, 16);
+    assert_eq!(apu.read_port(2), 30);
+
+    let mut loaded = Apu::new();
+    assembly.load_into(&mut loaded);
+    assert_eq!(&loaded.ram()[0x0300..0x0300 + assembly.blocks[0].data.len()], &assembly.blocks[0].data[..]);
+}
+
+#[test]
+fn invalid_programs_are_rejected() {
+    match assemble("nop\nfoo a") {
+        Err(AsmError::UnknownInstruction { line: 2, ref mnemonic }) if mnemonic == "foo" => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+    match assemble("mov ya, #1") {
+        Err(AsmError::InvalidOperands { line: 1 }) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+    match assemble("jmp !nowhere") {
+        Err(AsmError::UndefinedSymbol { line: 1, ref name }) if name == "nowhere" => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+    match assemble("a:\na:") {
+        Err(AsmError::DuplicateSymbol { line: 2, .. }) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+    match assemble("mov a, #$100") {
+        Err(AsmError::OutOfRange { line: 1, value: 0x100 }) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+    match assemble("bra far\n.ds 200\nfar: nop") {
+        Err(AsmError::BranchOutOfRange { line: 1, offset: 200 }) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+    match assemble("mov a, #(1") {
+        Err(AsmError::Syntax { line: 1, .. }) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+}
@@ -0,0 +1,174 @@
+#![cfg(feature = "capi")]
+
+extern crate snes_apu;
+extern crate spc;
+
+use snes_apu::apu::Apu;
+use snes_apu::capi::*;
+use snes_apu::dsp::dsp::SAMPLE_RATE;
+use spc::spc::Spc;
+
+use std::fs::File;
+use std::io::Read;
+use std::ptr;
+
+fn spc_path(name: &str) -> String {
+    format!("{}/test/{}.spc", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn spc_data(name: &str) -> Vec<u8> {
+    let mut ret = Vec::new();
+    File::open(spc_path(name)).unwrap().read_to_end(&mut ret).unwrap();
+    ret
+}
+
+fn create(name: &str) -> *mut SnesApu {
+    let data = spc_data(name);
+    let mut apu = ptr::null_mut();
+    assert_eq!(unsafe { snes_apu_create_from_spc(data.as_ptr(), data.len(), &mut apu) }, SNES_APU_OK);
+    assert!(!apu.is_null());
+    apu
+}
+
+fn render(apu: *mut SnesApu, num_frames: usize) -> Vec<i16> {
+    let mut ret = vec![0; num_frames * 2];
+    assert_eq!(unsafe { snes_apu_render_i16(apu, ret.as_mut_ptr(), num_frames) }, SNES_APU_OK);
+    ret
+}
+
+#[test]
+fn renders_same_output_as_apu() {
+    let apu = create("ferris-nu");
+    let actual = render(apu, 10000);
+    unsafe { snes_apu_destroy(apu) };
+
+    let mut expected_apu = Apu::from_spc(&Spc::load(spc_path("ferris-nu")).unwrap());
+    expected_apu.clear_echo_buffer();
+    let mut left = vec![0; 10000];
+    let mut right = vec![0; 10000];
+    expected_apu.render(&mut left, &mut right, 10000);
+    for i in 0..10000 {
+        assert_eq!(actual[i * 2], left[i]);
+        assert_eq!(actual[i * 2 + 1], right[i]);
+    }
+}
+
+#[test]
+fn sample_rate_matches_dsp() {
+    assert_eq!(SNES_APU_SAMPLE_RATE as usize, SAMPLE_RATE);
+}
+
+#[test]
+fn renders_float() {
+    let apu = create("smashit");
+    let apu_f32 = create("smashit");
+    let expected = render(apu, 4000);
+    let mut actual = vec![0.0; 8000];
+    assert_eq!(unsafe { snes_apu_render_f32(apu_f32, actual.as_mut_ptr(), 4000) }, SNES_APU_OK);
+    for i in 0..8000 {
+        assert_eq!(actual[i], (expected[i] as f32) / 32768.0);
+    }
+    unsafe {
+        snes_apu_destroy(apu);
+        snes_apu_destroy(apu_f32);
+    }
+}
+
+#[test]
+fn invalid_spc_is_rejected() {
+    let data = spc_data("broken/b0rked");
+    let mut apu = ptr::NonNull::<SnesApu>::dangling().as_ptr();
+    assert_eq!(unsafe { snes_apu_create_from_spc(data.as_ptr(), data.len(), &mut apu) }, SNES_APU_ERROR_INVALID_SPC);
+    assert!(apu.is_null());
+
+    let data = spc_data("smashit");
+    assert_eq!(unsafe { snes_apu_create_from_spc(data.as_ptr(), 0x1000, &mut apu) }, SNES_APU_ERROR_INVALID_SPC);
+}
+
+#[test]
+fn null_pointers_are_rejected() {
+    let mut apu = ptr::null_mut();
+    let mut samples = [0; 2];
+    unsafe {
+        assert_eq!(snes_apu_create_from_spc(ptr::null(), 0, &mut apu), SNES_APU_ERROR_NULL_POINTER);
+        assert_eq!(snes_apu_render_i16(ptr::null_mut(), samples.as_mut_ptr(), 1), SNES_APU_ERROR_NULL_POINTER);
+        assert_eq!(snes_apu_seek(ptr::null_mut(), 0), SNES_APU_ERROR_NULL_POINTER);
+        snes_apu_destroy(ptr::null_mut());
+    }
+}
+
+#[test]
+fn mute_solo_and_render_check_arguments() {
+    let apu = create("smashit");
+    unsafe {
+        assert_eq!(snes_apu_set_voice_muted(apu, 7, true), SNES_APU_OK);
+        assert_eq!(snes_apu_set_voice_muted(apu, 8, true), SNES_APU_ERROR_INVALID_ARGUMENT);
+        assert_eq!(snes_apu_set_voice_solo(apu, 8, true), SNES_APU_ERROR_INVALID_ARGUMENT);
+
+        // Twice this many samples doesn't fit in a usize
+        let mut samples = [0; 2];
+        let mut float_samples = [0.0; 2];
+        assert_eq!(snes_apu_render_i16(apu, samples.as_mut_ptr(), usize::MAX / 2 + 1), SNES_APU_ERROR_INVALID_ARGUMENT);
+        assert_eq!(snes_apu_render_f32(apu, float_samples.as_mut_ptr(), usize::MAX / 2 + 1), SNES_APU_ERROR_INVALID_ARGUMENT);
+
+        // Soloing a silent voice silences everything else
+        for i in 0..8 {
+            assert_eq!(snes_apu_set_voice_muted(apu, i, true), SNES_APU_OK);
+        }
+    }
+    assert!(render(apu, 4000).iter().all(|&sample| sample == 0));
+    unsafe { snes_apu_destroy(apu) };
+}
+
+#[test]
+fn seek_matches_rendering() {
+    let apu = create("ferris-nu");
+    render(apu, 20000);
+    let expected = render(apu, 1000);
+
+    let mut position = 0;
+    unsafe { snes_apu_position(apu, &mut position) };
+    assert_eq!(position, 21000);
+
+    assert_eq!(unsafe { snes_apu_seek(apu, 20000) }, SNES_APU_OK);
+    assert!(render(apu, 1000) == expected);
+
+    let other = create("ferris-nu");
+    assert_eq!(unsafe { snes_apu_seek(other, 20000) }, SNES_APU_OK);
+    assert!(render(other, 1000) == expected);
+    unsafe {
+        snes_apu_destroy(apu);
+        snes_apu_destroy(other);
+    }
+}
+
+#[test]
+fn save_and_load_state() {
+    let apu = create("smashit");
+    render(apu, 32000);
+
+    let mut len = 0;
+    unsafe {
+        assert_eq!(snes_apu_save_state(apu, ptr::null_mut(), 0, &mut len), SNES_APU_OK);
+        let mut small = vec![0; len - 1];
+        assert_eq!(snes_apu_save_state(apu, small.as_mut_ptr(), small.len(), &mut len), SNES_APU_ERROR_BUFFER_TOO_SMALL);
+    }
+    let mut state = vec![0; len];
+    assert_eq!(unsafe { snes_apu_save_state(apu, state.as_mut_ptr(), state.len(), &mut len) }, SNES_APU_OK);
+
+    let expected = render(apu, 32000);
+    let other = create("ferris-nu");
+    unsafe {
+        assert_eq!(snes_apu_load_state(other, state.as_ptr(), 10), SNES_APU_ERROR_INVALID_STATE);
+        assert_eq!(snes_apu_load_state(other, state.as_ptr(), state.len()), SNES_APU_OK);
+    }
+    assert!(render(other, 32000) == expected);
+
+    let mut position = 0;
+    unsafe { snes_apu_position(other, &mut position) };
+    assert_eq!(position, 64000);
+    unsafe {
+        snes_apu_destroy(apu);
+        snes_apu_destroy(other);
+    }
+}
@@ -0,0 +1,236 @@
+#![cfg(feature = "std")]
+
+extern crate snes_apu;
+extern crate spc;
+
+mod common;
+
+use snes_apu::apu::Apu;
+use snes_apu::dsp::dsp::AccuracyMode;
+use spc::spc::Spc;
+
+// Step numbers below follow the 32-step pipeline from blargg's DSP notes (as used in snes_spc and higan): voice 0's
+//  V3c is step 30 and its V4 step 31, voice n's V4 is step 2 + 3 * (n - 1) for n > 0, voice n's V7/V8/V9 are steps
+//  2/3/4 + 3 * n (with voice 7's at 23/24/25), and the sample is output at step 27.
+const VOICE_7_V4: i32 = 20;
+const OUTPUT_STEP: i32 = 27;
+
+// Voices 0-3 play a sample of two blocks that loops on the second. Everything is set up through the dsp directly,
+//  so nothing else touches the registers while it runs.
+fn voice_apu(accuracy_mode: AccuracyMode, kon: u8, gain: u8, extra_regs: &[(u8, u8)]) -> Box<Apu> {
+    let mut apu = Apu::new();
+    for byte in apu.ram_mut().iter_mut() {
+        *byte = 0;
+    }
+    common::load_sample(&mut apu, &common::sample(common::LOOP_HEADER));
+
+    apu.dsp.as_mut().unwrap().set_accuracy_mode(accuracy_mode);
+    common::set_registers(&mut apu, &[(0x6c, 0x20), (0x5d, common::DIR), (0x0c, 0x7f), (0x1c, 0x7f), (0x2c, 0x00), (0x3c, 0x00)]);
+    for voice in 0..8 {
+        let base = voice << 4;
+        common::set_registers(&mut apu, &[(base, 0x7f), (base | 0x01, 0x7f), (base | 0x02, 0x00), (base | 0x03, 0x10), (base | 0x04, 0x00),
+                                          (base | 0x05, 0x00), (base | 0x07, gain)]);
+    }
+    common::set_registers(&mut apu, extra_regs);
+    common::set_registers(&mut apu, &[(0x4c, kon)]);
+    apu
+}
+
+fn run_clocks(apu: &mut Apu, num_clocks: i32) {
+    let dsp = apu.dsp.as_mut().unwrap();
+    dsp.cycles_callback(num_clocks);
+    dsp.flush();
+}
+
+fn take_output(apu: &mut Apu) -> (Vec<i16>, Vec<i16>) {
+    let output_buffer = &mut apu.dsp.as_mut().unwrap().output_buffer;
+    let num_samples = output_buffer.get_sample_count();
+    let mut left = vec![0; num_samples as usize];
+    let mut right = vec![0; num_samples as usize];
+    output_buffer.read(&mut left, &mut right, num_samples);
+    (left, right)
+}
+
+// Writes voice 7's left volume just before the step given, then returns the sample output in that same sample
+fn output_after_volume_write_at(step: i32) -> (i16, i16) {
+    let mut apu = voice_apu(AccuracyMode::Clock, 0x80, 0x7f, &[(0x70, 0x00)]);
+    run_clocks(&mut apu, 100 * 32);
+    take_output(&mut apu);
+
+    run_clocks(&mut apu, step);
+    apu.dsp.as_mut().unwrap().set_register(0x70, 0x7f);
+    run_clocks(&mut apu, OUTPUT_STEP + 1 - step);
+    let (left, right) = take_output(&mut apu);
+    assert_eq!(left.len(), 1);
+    (left[0], right[0])
+}
+
+#[test]
+fn register_write_takes_effect_at_the_step_that_reads_it() {
+    // Voice 7's left volume is read at its V4, so a write up to that step makes it into this sample's output
+    let (left, right) = output_after_volume_write_at(VOICE_7_V4);
+    assert!(left != 0);
+    assert!(right != 0);
+
+    for &step in [VOICE_7_V4 + 1, OUTPUT_STEP].iter() {
+        let (left, right) = output_after_volume_write_at(step);
+        assert_eq!(left, 0, "write before step {} was heard in the same sample", step);
+        assert!(right != 0);
+    }
+}
+
+#[test]
+fn register_write_in_sample_mode_takes_effect_next_sample() {
+    let mut apu = voice_apu(AccuracyMode::Sample, 0x80, 0x7f, &[(0x70, 0x00)]);
+    run_clocks(&mut apu, 100 * 32);
+    let (left, _) = take_output(&mut apu);
+    assert!(left.iter().all(|&sample| sample == 0));
+
+    run_clocks(&mut apu, 16);
+    apu.dsp.as_mut().unwrap().set_register(0x70, 0x7f);
+    run_clocks(&mut apu, 16);
+    let (left, right) = take_output(&mut apu);
+    assert_eq!(left.len(), 1);
+    assert!(left[0] != 0);
+    assert!(right[0] != 0);
+}
+
+// Runs the pipeline a clock at a time and returns the steps at which the given register's value changed
+fn steps_changing(apu: &mut Apu, address: u8, num_samples: i32) -> Vec<i32> {
+    let mut ret = Vec::new();
+    let mut last_value = apu.dsp.as_mut().unwrap().get_register(address);
+    for clock in 0..(num_samples * 32) {
+        run_clocks(apu, 1);
+        let value = apu.dsp.as_mut().unwrap().get_register(address);
+        if value != last_value {
+            let step = clock % 32;
+            if !ret.contains(&step) {
+                ret.push(step);
+            }
+        }
+        last_value = value;
+    }
+    ret
+}
+
+#[test]
+fn voice_registers_read_back_at_their_pipeline_steps() {
+    // A linear gain increase of 32 a sample changes ENVX every sample, and OUTX with it. ENDX is only set, which
+    //  happens once the voices reach the end of the first block (after a key on's ENDX clear).
+    let new_apu = || voice_apu(AccuracyMode::Clock, 0x81, 0xdf, &[]);
+
+    assert_eq!(steps_changing(&mut new_apu(), 0x08, 64), vec![4]);
+    assert_eq!(steps_changing(&mut new_apu(), 0x78, 64), vec![25]);
+    assert_eq!(steps_changing(&mut new_apu(), 0x09, 64), vec![3]);
+    assert_eq!(steps_changing(&mut new_apu(), 0x79, 64), vec![24]);
+
+    let mut apu = new_apu();
+    let endx_steps = steps_changing(&mut apu, 0x7c, 64);
+    assert_eq!(apu.dsp.as_mut().unwrap().get_register(0x7c), 0x81);
+    assert_eq!(endx_steps, vec![2, 23]);
+}
+
+#[test]
+fn matches_sample_mode_one_sample_later() {
+    // With every register set up front and envelopes that don't depend on the rate counter, the pipeline computes
+    //  exactly what the sample renderer does. Its output lags by a sample, as keys are polled at the end of a
+    //  sample (step 30) rather than the start.
+    let extra_regs = [
+        (0x12, 0x34), (0x13, 0x08), (0x22, 0x00), (0x23, 0x20), (0x32, 0x9a), (0x33, 0x04),
+        (0x10, 0x40), (0x21, 0x20), (0x2d, 0x02),
+        (0x6c, 0x00), (0x6d, 0x20), (0x7d, 0x02), (0x0d, 0x50), (0x2c, 0x40), (0x3c, 0xc0), (0x4d, 0x0d),
+        (0x0f, 0x40), (0x1f, 0x20), (0x2f, 0x10)];
+    let mut sample_apu = voice_apu(AccuracyMode::Sample, 0x0f, 0x7f, &extra_regs);
+    let mut clock_apu = voice_apu(AccuracyMode::Clock, 0x0f, 0x7f, &extra_regs);
+    let num_samples = 16000;
+    run_clocks(&mut sample_apu, num_samples * 32);
+    run_clocks(&mut clock_apu, (num_samples + 1) * 32);
+    let (sample_left, sample_right) = take_output(&mut sample_apu);
+    let (clock_left, clock_right) = take_output(&mut clock_apu);
+
+    assert_eq!(clock_left[0], 0);
+    assert_eq!(clock_right[0], 0);
+    assert!(sample_left.iter().filter(|&&sample| sample != 0).count() > (num_samples as usize) / 2);
+    for i in 0..(num_samples as usize) {
+        assert_eq!((clock_left[i + 1], clock_right[i + 1]), (sample_left[i], sample_right[i]), "sample {}", i);
+    }
+}
+
+fn render_spc(name: &str, accuracy_mode: AccuracyMode, num_samples: usize) -> (Vec<i16>, Vec<i16>) {
+    let spc = Spc::load(format!("{}/test/{}.spc", env!("CARGO_MANIFEST_DIR"), name)).unwrap();
+    let mut apu = Apu::from_spc(&spc);
+    apu.clear_echo_buffer();
+    apu.dsp.as_mut().unwrap().set_accuracy_mode(accuracy_mode);
+
+    let mut left = vec![0; num_samples];
+    let mut right = vec![0; num_samples];
+    for (left, right) in left.chunks_mut(512).zip(right.chunks_mut(512)) {
+        let len = left.len() as i32;
+        apu.render(left, right, len);
+    }
+    (left, right)
+}
+
+// Songs write registers mid-sample, which the two modes see at different points, and the envelope rate counter
+//  runs a sample earlier relative to key on in the pipeline. So rather than matching exactly the two should stay
+//  close: the same level block by block, and the same waveform (a sample apart) to within a small error.
+fn check_spc(name: &str) {
+    const BLOCK_LEN: usize = 1024;
+    let num_samples = 32000 * 5;
+    let (sample_left, sample_right) = render_spc(name, AccuracyMode::Sample, num_samples);
+    let (clock_left, clock_right) = render_spc(name, AccuracyMode::Clock, num_samples);
+
+    let energy = |left: &[i16], right: &[i16], start: usize, len: usize| {
+        (start..start + len).map(|i| (left[i] as f64).powi(2) + (right[i] as f64).powi(2)).sum::<f64>()
+    };
+    let mut error = 0.0;
+    for i in 0..(num_samples - 1) {
+        error += ((sample_left[i] as f64) - (clock_left[i + 1] as f64)).powi(2);
+        error += ((sample_right[i] as f64) - (clock_right[i + 1] as f64)).powi(2);
+    }
+    let signal = energy(&sample_left, &sample_right, 0, num_samples - 1);
+    let snr = 10.0 * (signal / error).log10();
+    assert!(snr > 15.0, "{}: signal to error ratio {:.1}db", name, snr);
+
+    for block in 0..(num_samples / BLOCK_LEN - 1) {
+        let start = block * BLOCK_LEN;
+        let sample_level = energy(&sample_left, &sample_right, start, BLOCK_LEN).max(1.0);
+        let clock_level = energy(&clock_left, &clock_right, start + 1, BLOCK_LEN).max(1.0);
+        let difference = 10.0 * (sample_level / clock_level).log10();
+        assert!(difference.abs() < 0.5, "{}: block {} level differs by {:.2}db", name, block, difference);
+    }
+}
+
+#[test]
+fn ferris_nu_matches_sample_mode() {
+    check_spc("ferris-nu");
+}
+
+#[test]
+fn smashit_matches_sample_mode() {
+    check_spc("smashit");
+}
+
+#[test]
+fn registers_read_back_in_sample_mode() {
+    // Reads return the register mirror, so written values read back as is (KON, which is cleared once polled, and
+    //  ENDX aside) and ENVX/OUTX follow the voice
+    let mut apu = voice_apu(AccuracyMode::Sample, 0x00, 0xdf, &[]);
+    {
+        let dsp = apu.dsp.as_mut().unwrap();
+        for &(address, value) in [(0x15, 0x8f), (0x16, 0xe0), (0x2c, 0x12), (0x3d, 0x05), (0x5d, 0x03), (0x7f, 0xfe)].iter() {
+            dsp.set_register(address, value);
+            assert_eq!(dsp.get_register(address), value);
+            assert_eq!(dsp.get_register(address | 0x80), value);
+        }
+        assert_eq!(dsp.get_register(0x08), 0);
+        dsp.set_register(0x4c, 0x01);
+    }
+
+    run_clocks(&mut apu, 32 * 32);
+    let dsp = apu.dsp.as_mut().unwrap();
+    let envx = dsp.get_register(0x08);
+    assert!(envx > 0 && envx < 0x7f, "ENVX {:02x}", envx);
+    assert!(dsp.get_register(0x09) != 0);
+    assert_eq!(dsp.get_register(0x18), 0);
+}
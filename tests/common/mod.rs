@@ -0,0 +1,71 @@
+// Fixtures shared by the integration tests: a sample to play, a sample directory pointing at it, and ways of
+//  getting register settings into the dsp, either directly or through a program on the smp.
+
+// Each test file only uses some of these
+#![allow(dead_code)]
+
+use snes_apu::apu::Apu;
+use snes_apu::asm::assemble;
+
+pub const DIR: u8 = 0x03;
+const SAMPLE_ADDRESS: usize = 0x0700;
+const PROGRAM_ADDRESS: u16 = 0x0200;
+
+pub const LOOP_HEADER: u8 = 0xc3;
+pub const END_HEADER: u8 = 0xc1;
+
+// A block of arbitrary waveform followed by a block of square wave. The second block's header decides whether the
+//  sample loops on it (LOOP_HEADER) or ends there (END_HEADER).
+pub fn sample(end_header: u8) -> [u8; 18] {
+    [0xc0, 0x17, 0x7f, 0xf0, 0x81, 0x23, 0x45, 0x9a, 0xcd,
+     end_header, 0x77, 0x77, 0x77, 0x77, 0x88, 0x88, 0x88, 0x88]
+}
+
+// Puts sample at $0700 and points every entry of the directory at DIR ($0300-$06ff) at it, looping on its last
+//  block, so whatever source a voice picks it plays the same thing
+pub fn load_sample(apu: &mut Apu, sample: &[u8]) {
+    let ram = apu.ram_mut();
+    let loop_address = SAMPLE_ADDRESS + sample.len() - 9;
+    let entry = [SAMPLE_ADDRESS as u8, (SAMPLE_ADDRESS >> 8) as u8, loop_address as u8, (loop_address >> 8) as u8];
+    for dir_entry in ram[((DIR as usize) << 8)..SAMPLE_ADDRESS].chunks_mut(4) {
+        dir_entry.copy_from_slice(&entry);
+    }
+    ram[SAMPLE_ADDRESS..SAMPLE_ADDRESS + sample.len()].copy_from_slice(sample);
+}
+
+// Writes each (address, value) pair straight to the dsp, in order
+pub fn set_registers(apu: &mut Apu, regs: &[(u8, u8)]) {
+    let dsp = apu.dsp.as_mut().unwrap();
+    for &(address, value) in regs.iter() {
+        dsp.set_register(address, value);
+    }
+}
+
+// Assembles a program at $0200 that writes each of regs to the dsp through $f2/$f3, the way a driver would, and
+//  then carries on with body. It's loaded along with sample, and the smp is left about to start it.
+pub fn program_apu(regs: &[(u8, u8)], body: &str, sample: &[u8]) -> Box<Apu> {
+    let table = regs.iter().map(|&(address, value)| format!("        .db ${:02x}, ${:02x}\n", address, value)).collect::<String>();
+    let assembly = assemble(&format!("
+        .org ${:04x}
+        mov x, #0
+init:   mov a, regs+x
+        mov $f2, a
+        inc x
+        mov a, regs+x
+        mov $f3, a
+        inc x
+        cmp x, #regs_end-regs
+        bne init
+{}
+
+regs:
+{}regs_end:
+", PROGRAM_ADDRESS, body, table)).unwrap();
+    assert!(assembly.symbol("regs_end").unwrap() as usize <= (DIR as usize) << 8, "program runs into the sample directory");
+
+    let mut apu = Apu::new();
+    assembly.load_into(&mut apu);
+    load_sample(&mut apu, sample);
+    apu.smp.as_mut().unwrap().reg_pc = PROGRAM_ADDRESS;
+    apu
+}
@@ -0,0 +1,123 @@
+extern crate snes_apu;
+
+mod common;
+
+use snes_apu::apu::Apu;
+use snes_apu::dsp::dsp::AccuracyMode;
+
+const FILL: u8 = 0x55;
+
+// With no voices playing and no feedback, every echo write is a 0, so the bytes of ram the echo buffer has passed
+//  over are exactly the ones that are no longer FILL. The very first sample goes to the power-on ESA of $6000.
+fn echo_apu(accuracy_mode: AccuracyMode, esa: u8, edl: u8) -> Box<Apu> {
+    let mut apu = Apu::new();
+    for byte in apu.ram_mut().iter_mut() {
+        *byte = FILL;
+    }
+
+    apu.dsp.as_mut().unwrap().set_accuracy_mode(accuracy_mode);
+    common::set_registers(&mut apu, &[(0x6c, 0x00), (0x0d, 0x00), (0x2c, 0x00), (0x3c, 0x00), (0x6d, esa), (0x7d, edl)]);
+    apu
+}
+
+fn run_samples(apu: &mut Apu, num_samples: i32) {
+    let dsp = apu.dsp.as_mut().unwrap();
+    dsp.cycles_callback(num_samples * 32);
+    dsp.flush();
+}
+
+fn set_register(apu: &mut Apu, address: u8, value: u8) {
+    apu.dsp.as_mut().unwrap().set_register(address, value);
+}
+
+fn is_written(apu: &Apu, start: usize, end: usize) -> bool {
+    apu.ram()[start..end].iter().all(|&byte| byte == 0)
+}
+
+fn is_untouched(apu: &Apu, start: usize, end: usize) -> bool {
+    apu.ram()[start..end].iter().all(|&byte| byte == FILL)
+}
+
+fn modes() -> [AccuracyMode; 2] {
+    [AccuracyMode::Sample, AccuracyMode::Clock]
+}
+
+#[test]
+fn buffer_wraps_around_into_page_zero() {
+    for &mode in modes().iter() {
+        // ESA $ff with EDL 1 is 2kb starting at $ff00, so all but the first page of it is at $0000-$06ff
+        let mut apu = echo_apu(mode, 0xff, 0x01);
+        run_samples(&mut apu, 1 + 0x800 / 4);
+
+        assert!(is_written(&apu, 0xff00, 0x10000));
+        assert!(is_written(&apu, 0x0000, 0x0700));
+        assert!(is_untouched(&apu, 0x0700, 0x6000));
+        assert!(is_untouched(&apu, 0x6004, 0xff00));
+    }
+}
+
+#[test]
+fn edl_zero_uses_four_byte_buffer() {
+    for &mode in modes().iter() {
+        let mut apu = echo_apu(mode, 0x40, 0x00);
+        run_samples(&mut apu, 100);
+
+        assert!(is_written(&apu, 0x4000, 0x4004));
+        assert!(is_untouched(&apu, 0x0000, 0x4000));
+        assert!(is_untouched(&apu, 0x4004, 0x6000));
+    }
+}
+
+#[test]
+fn esa_is_latched_each_sample() {
+    for &mode in modes().iter() {
+        // ESA is latched at the end of each sample (step 29 on hardware) for use in the next, so the sample after
+        //  the write still goes to the old buffer. After that the buffer moves, keeping its position.
+        let mut apu = echo_apu(mode, 0x40, 0x01);
+        run_samples(&mut apu, 11);
+        assert!(is_written(&apu, 0x4004, 0x402c));
+        assert!(is_untouched(&apu, 0x402c, 0x4030));
+
+        set_register(&mut apu, 0x6d, 0x50);
+        run_samples(&mut apu, 1);
+        assert!(is_written(&apu, 0x402c, 0x4030));
+        assert!(is_untouched(&apu, 0x4030, 0x4034));
+
+        run_samples(&mut apu, 1);
+        assert!(is_untouched(&apu, 0x4030, 0x4034));
+        assert!(is_untouched(&apu, 0x5000, 0x5030));
+        assert!(is_written(&apu, 0x5030, 0x5034));
+    }
+}
+
+#[test]
+fn edl_is_latched_when_buffer_wraps() {
+    for &mode in modes().iter() {
+        // Lengthening the buffer partway through a lap only takes effect once the position wraps back to 0
+        let mut apu = echo_apu(mode, 0x40, 0x01);
+        run_samples(&mut apu, 11);
+        set_register(&mut apu, 0x7d, 0x02);
+        run_samples(&mut apu, 0x800 / 4 - 10);
+        assert!(is_written(&apu, 0x4000, 0x4800));
+        assert!(is_untouched(&apu, 0x4800, 0x5000));
+
+        run_samples(&mut apu, 0x1000 / 4);
+        assert!(is_written(&apu, 0x4000, 0x5000));
+        assert!(is_untouched(&apu, 0x5000, 0x6000));
+    }
+}
+
+#[test]
+fn clear_echo_buffer_wraps_around() {
+    let mut apu = echo_apu(AccuracyMode::Sample, 0xff, 0x01);
+    apu.clear_echo_buffer();
+    assert!(apu.ram()[0xff00..0x10000].iter().all(|&byte| byte == 0xff));
+    assert!(apu.ram()[0x0000..0x0700].iter().all(|&byte| byte == 0xff));
+    assert!(is_untouched(&apu, 0x0700, 0xff00));
+
+    let mut apu = echo_apu(AccuracyMode::Sample, 0x40, 0x00);
+    apu.clear_echo_buffer();
+    assert!(apu.ram()[0x4000..0x4004].iter().all(|&byte| byte == 0xff));
+    assert!(is_untouched(&apu, 0x0000, 0x4000));
+    assert!(is_untouched(&apu, 0x4004, 0x10000));
+}
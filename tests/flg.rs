@@ -0,0 +1,82 @@
+extern crate snes_apu;
+
+mod common;
+
+use snes_apu::apu::Apu;
+use snes_apu::dsp::dsp::AccuracyMode;
+
+// Voice 0 plays a looping two block sample at full volume, set up through the dsp directly (the ipl rom only
+//  touches the zero page while it waits for the host)
+fn playing_apu(accuracy_mode: AccuracyMode) -> Box<Apu> {
+    let mut apu = Apu::new();
+    common::load_sample(&mut apu, &common::sample(common::LOOP_HEADER));
+    apu.dsp.as_mut().unwrap().set_accuracy_mode(accuracy_mode);
+    common::set_registers(&mut apu, &[
+        (0x6c, 0x20), (0x5d, common::DIR), (0x0c, 0x7f), (0x1c, 0x7f), (0x2c, 0x00), (0x3c, 0x00),
+        (0x00, 0x7f), (0x01, 0x7f), (0x02, 0x00), (0x03, 0x10), (0x04, 0x00), (0x05, 0x00), (0x07, 0x7f),
+        (0x4c, 0x01)]);
+    apu
+}
+
+fn render(apu: &mut Apu) -> (Vec<i16>, Vec<i16>) {
+    let mut left = vec![0; 256];
+    let mut right = vec![0; 256];
+    apu.render(&mut left, &mut right, 256);
+    (left, right)
+}
+
+fn is_silent(output: &(Vec<i16>, Vec<i16>)) -> bool {
+    output.0.iter().chain(output.1.iter()).all(|&sample| sample == 0)
+}
+
+#[test]
+fn mute_silences_output_while_voices_keep_running() {
+    for &mode in [AccuracyMode::Sample, AccuracyMode::Clock].iter() {
+        let mut apu = playing_apu(mode);
+        render(&mut apu);
+        assert!(!is_silent(&render(&mut apu)));
+
+        apu.dsp.as_mut().unwrap().set_register(0x6c, 0x60);
+        render(&mut apu);
+        assert!(is_silent(&render(&mut apu)));
+
+        // The voice carries on underneath: its envelope is untouched and it's still stepping through the sample
+        let dsp = apu.dsp.as_mut().unwrap();
+        assert_eq!(dsp.get_register(0x08), 0x7f);
+        assert!(dsp.get_register(0x09) != 0);
+        assert_eq!(dsp.get_register(0x7c) & 0x01, 0x01);
+        dsp.set_register(0x7c, 0x00);
+        assert_eq!(dsp.get_register(0x7c) & 0x01, 0x00);
+        render(&mut apu);
+        assert_eq!(apu.dsp.as_mut().unwrap().get_register(0x7c) & 0x01, 0x01);
+
+        // Unmuting brings it straight back, with no key on needed
+        apu.dsp.as_mut().unwrap().set_register(0x6c, 0x20);
+        render(&mut apu);
+        assert!(!is_silent(&render(&mut apu)));
+    }
+}
+
+#[test]
+fn flg_reads_back_e0_after_power_on() {
+    let mut apu = Apu::new();
+    {
+        let dsp = apu.dsp.as_mut().unwrap();
+        assert_eq!(dsp.get_register(0x6c), 0xe0);
+        assert!(dsp.is_soft_reset());
+    }
+    apu.write_u8(0xf2, 0x6c);
+    assert_eq!(apu.read_u8(0xf3), 0xe0);
+}
+
+#[test]
+fn power_on_flg_mutes_and_disables_echo_writes() {
+    let mut apu = Apu::new();
+    for byte in apu.ram_mut()[0x6000..0xd000].iter_mut() {
+        *byte = 0x55;
+    }
+
+    let output = render(&mut apu);
+    assert!(is_silent(&output));
+    assert!(apu.ram()[0x6000..0xd000].iter().all(|&byte| byte == 0x55));
+}
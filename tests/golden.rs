@@ -0,0 +1,188 @@
+#![cfg(feature = "std")]
+
+extern crate snes_apu;
+extern crate spc;
+
+use snes_apu::apu::Apu;
+use spc::spc::Spc;
+
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+// Set this to rewrite the reference files from the current output instead of comparing against them
+const BLESS_VAR: &str = "SNES_APU_BLESS";
+
+const NUM_VOICES: usize = 8;
+const SECONDS: usize = 10;
+const BLOCK_LEN: usize = 512;
+const NUM_BLOCKS: usize = SECONDS * 32000 / BLOCK_LEN;
+
+// Voices render ahead of the mix a little, so their outputs are collected as they're produced
+const RENDER_LEN: usize = 64;
+
+struct Fnv {
+    hash: u64,
+}
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv { hash: 0xcbf29ce484222325 }
+    }
+
+    fn add(&mut self, value: i16) {
+        for &byte in [value as u8, ((value as u16) >> 8) as u8].iter() {
+            self.hash ^= byte as u64;
+            self.hash = self.hash.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+struct Block {
+    mix: u64,
+    voices: [u64; NUM_VOICES],
+}
+
+impl Block {
+    fn parse(line: &str) -> Block {
+        let hashes = line.split_whitespace().map(|hash| u64::from_str_radix(hash, 16).unwrap()).collect::<Vec<_>>();
+        assert_eq!(hashes.len(), 1 + NUM_VOICES, "malformed reference line: {}", line);
+        let mut voices = [0; NUM_VOICES];
+        voices.copy_from_slice(&hashes[1..]);
+        Block {
+            mix: hashes[0],
+            voices,
+        }
+    }
+
+    fn format(&self) -> String {
+        let mut ret = format!("{:016x}", self.mix);
+        for hash in self.voices.iter() {
+            write!(ret, " {:016x}", hash).unwrap();
+        }
+        ret
+    }
+}
+
+fn render(spc_path: &str) -> Vec<Block> {
+    let spc = Spc::load(spc_path).unwrap();
+    let mut apu = Apu::from_spc(&spc);
+    apu.clear_echo_buffer();
+
+    let mut mix = Vec::with_capacity(NUM_BLOCKS * BLOCK_LEN * 2);
+    let mut voices = (0..NUM_VOICES).map(|_| Vec::with_capacity(NUM_BLOCKS * BLOCK_LEN * 2)).collect::<Vec<_>>();
+    let mut left = [0; RENDER_LEN];
+    let mut right = [0; RENDER_LEN];
+    let mut last_sample_count = apu.dsp.as_ref().unwrap().sample_count();
+    while mix.len() < NUM_BLOCKS * BLOCK_LEN * 2 {
+        apu.render(&mut left, &mut right, RENDER_LEN as i32);
+        for i in 0..RENDER_LEN {
+            mix.push(left[i]);
+            mix.push(right[i]);
+        }
+
+        let dsp = apu.dsp.as_ref().unwrap();
+        let sample_count = dsp.sample_count();
+        let num_new_samples = (sample_count - last_sample_count) as usize;
+        last_sample_count = sample_count;
+        for (i, voice) in dsp.voices.iter().enumerate() {
+            let buffer = &voice.output_buffer;
+            assert!(num_new_samples <= buffer.buffer.len());
+            for j in 0..num_new_samples {
+                let index = (buffer.pos as usize + buffer.buffer.len() - num_new_samples + j) % buffer.buffer.len();
+                let output = buffer.buffer[index];
+                voices[i].push(output.left_out as i16);
+                voices[i].push(output.right_out as i16);
+            }
+        }
+    }
+
+    let hash_block = |samples: &[i16], index: usize| {
+        let mut fnv = Fnv::new();
+        for &sample in samples[index * BLOCK_LEN * 2..(index + 1) * BLOCK_LEN * 2].iter() {
+            fnv.add(sample);
+        }
+        fnv.hash
+    };
+    (0..NUM_BLOCKS).map(|index| {
+        let mut voice_hashes = [0; NUM_VOICES];
+        for i in 0..NUM_VOICES {
+            voice_hashes[i] = hash_block(&voices[i], index);
+        }
+        Block {
+            mix: hash_block(&mix, index),
+            voices: voice_hashes,
+        }
+    }).collect()
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    let mut ret = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    ret.push("tests");
+    ret.push("golden");
+    ret.push(format!("{}.txt", name));
+    ret
+}
+
+fn describe_block(index: usize) -> String {
+    format!("block {} (samples {}..{}, {:.3}s)", index, index * BLOCK_LEN, (index + 1) * BLOCK_LEN, ((index * BLOCK_LEN) as f64) / 32000.0)
+}
+
+fn check(name: &str) {
+    let spc_path = format!("{}/test/{}.spc", env!("CARGO_MANIFEST_DIR"), name);
+    let actual = render(&spc_path);
+    let path = reference_path(name);
+
+    if env::var_os(BLESS_VAR).is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut file = File::create(&path).unwrap();
+        for block in actual.iter() {
+            writeln!(file, "{}", block.format()).unwrap();
+        }
+        return;
+    }
+
+    let mut contents = String::new();
+    File::open(&path)
+        .unwrap_or_else(|_| panic!("missing reference {}; run with {}=1 to create it", path.display(), BLESS_VAR))
+        .read_to_string(&mut contents).unwrap();
+    let expected = contents.lines().map(Block::parse).collect::<Vec<_>>();
+    assert_eq!(expected.len(), actual.len(), "reference {} has the wrong number of blocks", path.display());
+
+    let first_mix_difference = (0..NUM_BLOCKS).find(|&index| expected[index].mix != actual[index].mix);
+    let first_mix_difference = match first_mix_difference {
+        Some(index) => index,
+        _ => return
+    };
+
+    let mut report = format!("{}: output differs from reference, first at {}\n", name, describe_block(first_mix_difference));
+    for i in 0..NUM_VOICES {
+        let differing_blocks = (0..NUM_BLOCKS).filter(|&index| expected[index].voices[i] != actual[index].voices[i]).collect::<Vec<_>>();
+        match differing_blocks.first() {
+            Some(&index) => writeln!(report, "  voice {}: diverges at {}, {} of {} blocks differ", i, describe_block(index), differing_blocks.len(), NUM_BLOCKS).unwrap(),
+            _ => writeln!(report, "  voice {}: matches", i).unwrap()
+        }
+    }
+    let num_mix_differences = (0..NUM_BLOCKS).filter(|&index| expected[index].mix != actual[index].mix).count();
+    writeln!(report, "  mix: {} of {} blocks differ", num_mix_differences, NUM_BLOCKS).unwrap();
+    write!(report, "If this change is intended, rerun with {}=1 to update the references", BLESS_VAR).unwrap();
+    panic!("{}", report);
+}
+
+#[test]
+fn ferris_nu() {
+    check("ferris-nu");
+}
+
+#[test]
+fn smashit() {
+    check("smashit");
+}
+
+#[test]
+fn b0rked_is_rejected() {
+    let path = format!("{}/test/broken/b0rked.spc", env!("CARGO_MANIFEST_DIR"));
+    assert!(Spc::load(&path).is_err());
+}
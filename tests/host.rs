@@ -0,0 +1,123 @@
+extern crate snes_apu;
+
+use snes_apu::apu::Apu;
+use snes_apu::error::HostError;
+use snes_apu::host::{HostCommand, HostScript, IplUploader, UploadBlock};
+
+fn test_program() -> Vec<UploadBlock> {
+    vec![
+        // mov $f5, #$5a; bra -2
+        UploadBlock { address: 0x0200, data: vec![0x8f, 0x5a, 0xf5, 0x2f, 0xfe] },
+        UploadBlock { address: 0x1000, data: (0..300).map(|x| x as u8).collect() },
+        UploadBlock { address: 0x3000, data: Vec::new() }]
+}
+
+#[test]
+fn ipl_upload_runs_program() {
+    let mut apu = Apu::new();
+    HostScript::ipl_upload(&test_program(), 0x0200).run(&mut apu).unwrap();
+    assert_eq!(&apu.ram()[0x0200..0x0205], &[0x8f, 0x5a, 0xf5, 0x2f, 0xfe]);
+    assert!(apu.ram()[0x1000..0x1000 + 300].iter().enumerate().all(|(i, &x)| x == i as u8));
+
+    let mut left = [0; 16];
+    let mut right = [0; 16];
+    apu.render(&mut left, &mut right, 16);
+    assert_eq!(apu.read_port(1), 0x5a);
+    assert_eq!(apu.smp_state().pc & 0xfff0, 0x0200);
+}
+
+#[test]
+fn wait_times_out() {
+    let mut script = HostScript::new();
+    script.commands.push(HostCommand::WaitPort(0, 0xaa));
+    script.commands.push(HostCommand::WaitPort(0, 0x12));
+    script.timeout_samples = 100;
+    match script.run(&mut Apu::new()) {
+        Err(HostError::Timeout { command: 1 }) => (),
+        x => panic!("Unexpected result: {:?}", x)
+    }
+}
+
+#[test]
+fn ports_are_separate_in_each_direction() {
+    let mut apu = Apu::new();
+    apu.write_port(2, 0x34);
+    assert_eq!(apu.read_port(2), 0);
+    assert_eq!(apu.read_u8(0xf6), 0x34);
+    apu.write_u8(0xf6, 0x56);
+    assert_eq!(apu.read_port(2), 0x56);
+    assert_eq!(apu.read_u8(0xf6), 0x34);
+}
+
+#[test]
+fn control_clears_input_ports_only() {
+    let mut apu = Apu::new();
+    for port in 0..4 {
+        apu.write_port(port, 0x11 * (port as u8 + 1));
+        apu.write_u8(0xf4 + port as u32, 0xa0 + port as u8);
+    }
+
+    apu.write_u8(0xf1, 0x10);
+    assert_eq!(apu.read_u8(0xf4), 0);
+    assert_eq!(apu.read_u8(0xf5), 0);
+    assert_eq!(apu.read_u8(0xf6), 0x33);
+    assert_eq!(apu.read_u8(0xf7), 0x44);
+
+    apu.write_u8(0xf1, 0x30);
+    for port in 0..4 {
+        assert_eq!(apu.read_u8(0xf4 + port as u32), 0);
+        assert_eq!(apu.read_port(port), 0xa0 + port as u8);
+    }
+}
+
+#[test]
+fn uploader_loads_and_starts_program() {
+    let mut apu = Apu::new();
+    {
+        let mut uploader = IplUploader::new(&mut apu).unwrap();
+        // Lengths either side of the kick wrapping around
+        for &(address, len) in [(0x1000u16, 255usize), (0x2000, 256), (0x3000, 257), (0x4000, 1)].iter() {
+            let data = (0..len).map(|x| (x as u8) ^ (address >> 8) as u8).collect::<Vec<_>>();
+            uploader.upload(address, &data).unwrap();
+        }
+        uploader.upload(0x0200, &[0x8f, 0x5a, 0xf5, 0x2f, 0xfe]).unwrap();
+        uploader.jump(0x0200).unwrap();
+    }
+    for &(address, len) in [(0x1000usize, 255usize), (0x2000, 256), (0x3000, 257), (0x4000, 1)].iter() {
+        assert!(apu.ram()[address..address + len].iter().enumerate().all(|(i, &x)| x == (i as u8) ^ (address >> 8) as u8));
+    }
+
+    let mut left = [0; 16];
+    let mut right = [0; 16];
+    apu.render(&mut left, &mut right, 16);
+    assert_eq!(apu.read_port(1), 0x5a);
+}
+
+#[test]
+fn uploader_matches_script() {
+    let mut expected = Apu::new();
+    HostScript::ipl_upload(&test_program(), 0x0200).run(&mut expected).unwrap();
+
+    let mut actual = Apu::new();
+    {
+        let mut uploader = IplUploader::new(&mut actual).unwrap();
+        for block in test_program() {
+            uploader.upload(block.address, &block.data).unwrap();
+        }
+        uploader.jump(0x0200).unwrap();
+    }
+    assert!(actual.ram() == expected.ram());
+    assert_eq!(actual.smp_state().pc, expected.smp_state().pc);
+}
+
+#[test]
+fn uploader_needs_ipl_rom() {
+    // An apu that's already running a driver never says it's ready
+    let mut apu = Apu::new();
+    HostScript::ipl_upload(&test_program(), 0x0200).run(&mut apu).unwrap();
+    match IplUploader::new(&mut apu) {
+        Err(HostError::Timeout { command: 0 }) => (),
+        Err(e) => panic!("Unexpected error: {:?}", e),
+        Ok(_) => panic!("Uploader started")
+    }
+}
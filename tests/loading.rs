@@ -0,0 +1,115 @@
+#![cfg(feature = "std")]
+
+extern crate snes_apu;
+extern crate spc;
+
+use snes_apu::apu::Apu;
+use snes_apu::error::SpcError;
+use spc::spc::Spc;
+
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+
+const SMASHIT: &[u8] = include_bytes!("../test/smashit.spc");
+
+fn render(apu: &mut Apu, num_samples: usize) -> (Vec<i16>, Vec<i16>) {
+    let mut left = vec![0; num_samples];
+    let mut right = vec![0; num_samples];
+    apu.render(&mut left, &mut right, num_samples as i32);
+    (left, right)
+}
+
+#[test]
+fn from_spc_bytes_matches_from_spc() {
+    let mut expected_apu = Apu::from_spc(&Spc::load(format!("{}/test/smashit.spc", env!("CARGO_MANIFEST_DIR"))).unwrap());
+    let mut actual_apu = Apu::from_spc_bytes(SMASHIT).unwrap();
+    assert_eq!(actual_apu.smp_state().pc, expected_apu.smp_state().pc);
+    assert!(actual_apu.dsp_state().regs[..] == expected_apu.dsp_state().regs[..]);
+    assert!(render(&mut actual_apu, 32000) == render(&mut expected_apu, 32000));
+}
+
+#[test]
+fn from_reader_matches_from_spc_bytes() {
+    let mut expected_apu = Apu::from_spc_bytes(SMASHIT).unwrap();
+    let file = File::open(format!("{}/test/smashit.spc", env!("CARGO_MANIFEST_DIR"))).unwrap();
+    let mut actual_apu = Apu::from_reader(file).unwrap();
+    assert!(render(&mut actual_apu, 32000) == render(&mut expected_apu, 32000));
+
+    let mut actual_apu = Apu::from_reader(Cursor::new(SMASHIT)).unwrap();
+    expected_apu = Apu::from_spc_bytes(SMASHIT).unwrap();
+    assert!(render(&mut actual_apu, 32000) == render(&mut expected_apu, 32000));
+}
+
+#[test]
+fn invalid_magic_is_rejected() {
+    let mut data = SMASHIT.to_vec();
+    data[0] = b'X';
+    match Apu::from_spc_bytes(&data) {
+        Err(SpcError::InvalidMagic) => (),
+        _ => panic!("Expected invalid magic")
+    }
+    match Apu::from_spc_bytes(b"Not an spc") {
+        Err(SpcError::InvalidMagic) => (),
+        _ => panic!("Expected invalid magic")
+    }
+
+    let mut data = SMASHIT.to_vec();
+    data[0x21] = 0;
+    match Apu::from_spc_bytes(&data) {
+        Err(SpcError::InvalidMagic) => (),
+        _ => panic!("Expected invalid magic")
+    }
+}
+
+#[test]
+fn unsupported_version_is_rejected() {
+    let mut data = SMASHIT.to_vec();
+    data[0x1f] = b'2';
+    match Apu::from_spc_bytes(&data) {
+        Err(SpcError::UnsupportedVersion) => (),
+        _ => panic!("Expected unsupported version")
+    }
+}
+
+#[test]
+fn truncated_data_is_rejected() {
+    match Apu::from_spc_bytes(&SMASHIT[..0x8000]) {
+        Err(SpcError::Truncated { len, expected }) => {
+            assert_eq!(len, 0x8000);
+            assert_eq!(expected, 0x10200);
+        },
+        _ => panic!("Expected truncated data")
+    }
+    // Down to the magic, and before the 0x1a bytes after it
+    for &len in [0x10, 0x22].iter() {
+        match Apu::from_spc_bytes(&SMASHIT[..len]) {
+            Err(SpcError::Truncated { len: actual_len, expected }) => {
+                assert_eq!(actual_len, len);
+                assert_eq!(expected, 0x10200);
+            },
+            _ => panic!("Expected truncated data")
+        }
+    }
+
+    let path = format!("{}/test/broken/b0rked.spc", env!("CARGO_MANIFEST_DIR"));
+    match Apu::from_reader(File::open(path).unwrap()) {
+        Err(SpcError::Truncated { len, .. }) => assert_eq!(len, 58978),
+        _ => panic!("Expected truncated data")
+    }
+}
+
+#[test]
+fn read_errors_are_passed_through() {
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::ConnectionReset, "upload aborted"))
+        }
+    }
+
+    match Apu::from_reader(FailingReader) {
+        Err(SpcError::Io(ref e)) if e.kind() == io::ErrorKind::ConnectionReset => (),
+        _ => panic!("Expected io error")
+    }
+}
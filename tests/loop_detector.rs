@@ -0,0 +1,163 @@
+extern crate snes_apu;
+
+mod common;
+
+use snes_apu::apu::Apu;
+use snes_apu::id666::Id666Tag;
+use snes_apu::loop_detector::{LoopDetector, SongLength};
+
+// A timer-paced sequencer on voices 0-3 at full volume. Each note is keyed on and held for 25 ticks of timer 0
+//  at 100hz, ie. 8000 samples.
+fn sequencer_apu(brr_header: u8, sequence: &str, extra_regs: &[(u8, u8)]) -> Box<Apu> {
+    let mut regs = vec![(0x5d, common::DIR), (0x0c, 0x7f), (0x1c, 0x7f)];
+    regs.extend_from_slice(extra_regs);
+    for voice in 0..4 {
+        let base = voice << 4;
+        regs.extend_from_slice(&[(base, 0x7f), (base | 0x01, 0x7f), (base | 0x04, 0x00), (base | 0x05, 0xff), (base | 0x06, 0xe0)]);
+    }
+    common::program_apu(&regs, &format!("
+        mov $fa, #80
+        mov $f1, #$01
+{}
+
+note:   mov $f2, #$03
+        mov $f3, a
+        mov $f2, #$13
+        mov $f3, a
+        mov $f2, #$23
+        mov $f3, a
+        mov $f2, #$33
+        mov $f3, a
+        mov $f2, #$4c
+        mov $f3, #$0f
+        mov y, #25
+wait:   mov a, $fd
+        beq wait
+        dbnz y, wait
+        ret
+", sequence), &common::sample(brr_header))
+}
+
+// An intro note, then three notes over and over
+const LOOPING_SEQUENCE: &str = "
+        mov a, #$08
+        call note
+loop:   mov a, #$10
+        call note
+        mov a, #$12
+        call note
+        mov a, #$14
+        call note
+        bra loop";
+
+// An intro note, then a loop of the same note six times and another once. The end of the stream keeps repeating
+//  that one note, but the loop is the seven note one.
+const REPEATED_PHRASE_SEQUENCE: &str = "
+        mov a, #$08
+        call note
+loop:   mov x, #6
+phrase: mov a, #$10
+        call note
+        dec x
+        bne phrase
+        mov a, #$14
+        call note
+        bra loop";
+
+// A single note of a sample that doesn't loop (the flags in the second block's header decide)
+const ONE_SHOT_SEQUENCE: &str = "
+        mov a, #$10
+        call note
+        bra *";
+
+// Full echo on top of the voices, so the output clips
+const ECHO_REGS: &[(u8, u8)] = &[(0x6c, 0x00), (0x2c, 0x7f), (0x3c, 0x7f), (0x4d, 0x0f), (0x6d, 0x80), (0x7d, 0x01), (0x0f, 0x7f)];
+const NO_ECHO_REGS: &[(u8, u8)] = &[(0x6c, 0x20)];
+
+#[test]
+fn finds_intro_and_loop() {
+    // The detector has to cope with the clipped output
+    let mut apu = sequencer_apu(common::LOOP_HEADER, LOOPING_SEQUENCE, ECHO_REGS);
+    let mut left = [0; 8000];
+    let mut right = [0; 8000];
+    apu.render(&mut left, &mut right, 8000);
+    assert!(left.contains(&-32768));
+
+    let play_length = LoopDetector::new().detect(&mut sequencer_apu(common::LOOP_HEADER, LOOPING_SEQUENCE, ECHO_REGS));
+    match play_length.length {
+        SongLength::Looping { intro_samples, loop_samples } => {
+            // Setting up the dsp takes a few samples, and the first timer tick comes up to a tick early depending
+            //  on where the divider was
+            assert!((7600..=8100).contains(&intro_samples), "Unexpected intro length: {}", intro_samples);
+            assert!((23999..=24001).contains(&loop_samples), "Unexpected loop length: {}", loop_samples);
+        },
+        _ => panic!("No loop found")
+    }
+    // The intro and two loops, rounded up to whole seconds
+    assert_eq!(play_length.seconds_to_play_before_fading_out, 2);
+    assert_eq!(play_length.fade_out_length, 10000);
+}
+
+#[test]
+fn repeated_phrase_within_loop_is_not_the_loop() {
+    match LoopDetector::new().detect(&mut sequencer_apu(common::LOOP_HEADER, REPEATED_PHRASE_SEQUENCE, NO_ECHO_REGS)).length {
+        SongLength::Looping { intro_samples, loop_samples } => {
+            assert!((7600..=8100).contains(&intro_samples), "Unexpected intro length: {}", intro_samples);
+            assert!((55999..=56001).contains(&loop_samples), "Unexpected loop length: {}", loop_samples);
+        },
+        _ => panic!("No loop found")
+    }
+}
+
+#[test]
+fn bundled_spc_ends_where_its_tag_says() {
+    // ferris-nu repeats plenty of phrases on its way through, but plays once and ends after 121 seconds
+    let data = include_bytes!("../test/ferris-nu.spc");
+    let tag = Id666Tag::from_spc_bytes(data).unwrap();
+    let mut apu = Apu::from_spc_bytes(data).unwrap();
+    apu.clear_echo_buffer();
+    let play_length = LoopDetector::new().detect(&mut apu);
+    match play_length.length {
+        SongLength::Ends { .. } => (),
+        _ => panic!("Expected the song to end")
+    }
+    assert_eq!(play_length.seconds_to_play_before_fading_out, tag.seconds_to_play_before_fading_out as i32);
+}
+
+#[test]
+fn too_few_repetitions_count_as_two() {
+    for &min_loop_repetitions in [-1, 0, 1].iter() {
+        let mut detector = LoopDetector::new();
+        detector.min_loop_repetitions = min_loop_repetitions;
+        match detector.detect(&mut sequencer_apu(common::LOOP_HEADER, LOOPING_SEQUENCE, ECHO_REGS)).length {
+            SongLength::Looping { loop_samples, .. } => assert!((23999..=24001).contains(&loop_samples)),
+            _ => panic!("No loop found")
+        }
+    }
+}
+
+#[test]
+fn detects_permanent_silence() {
+    let mut detector = LoopDetector::new();
+    detector.silence_seconds = 1;
+    let play_length = detector.detect(&mut sequencer_apu(common::END_HEADER, ONE_SHOT_SEQUENCE, NO_ECHO_REGS));
+    match play_length.length {
+        SongLength::Ends { length_samples } => assert!(length_samples > 0 && length_samples < 100, "Unexpected length: {}", length_samples),
+        _ => panic!("Silence not detected")
+    }
+    assert_eq!(play_length.seconds_to_play_before_fading_out, 1);
+    assert_eq!(play_length.fade_out_length, 0);
+}
+
+#[test]
+fn gives_up_after_max_seconds() {
+    let mut detector = LoopDetector::new();
+    detector.max_seconds = 1;
+    let play_length = detector.detect(&mut sequencer_apu(common::LOOP_HEADER, "bra *", NO_ECHO_REGS));
+    match play_length.length {
+        SongLength::Unknown => (),
+        _ => panic!("Unexpected length")
+    }
+    assert_eq!(play_length.seconds_to_play_before_fading_out, 1);
+}
+
@@ -0,0 +1,197 @@
+#![cfg(feature = "std")]
+
+extern crate snes_apu;
+
+mod common;
+
+use snes_apu::apu::Apu;
+use snes_apu::midi::MidiExporter;
+
+use std::fs;
+
+type Event = (u64, Vec<u8>);
+
+fn read_var_len(data: &[u8], pos: &mut usize) -> u64 {
+    let mut ret = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        ret = (ret << 7) | ((byte & 0x7f) as u64);
+        if (byte & 0x80) == 0 {
+            return ret;
+        }
+    }
+}
+
+fn read_u32(data: &[u8]) -> usize {
+    ((data[0] as usize) << 24) | ((data[1] as usize) << 16) | ((data[2] as usize) << 8) | (data[3] as usize)
+}
+
+// Splits a type 1 file into its tracks' events with absolute times, checking the chunk lengths on the way
+fn parse_smf(data: &[u8]) -> (u16, Vec<Vec<Event>>) {
+    assert_eq!(&data[0..4], b"MThd");
+    assert_eq!(read_u32(&data[4..]), 6);
+    assert_eq!(&data[8..10], &[0, 1]);
+    let num_tracks = ((data[10] as usize) << 8) | (data[11] as usize);
+    let division = ((data[12] as u16) << 8) | (data[13] as u16);
+
+    let mut tracks = Vec::new();
+    let mut pos = 14;
+    for _ in 0..num_tracks {
+        assert_eq!(&data[pos..pos + 4], b"MTrk");
+        let end = pos + 8 + read_u32(&data[pos + 4..]);
+        pos += 8;
+
+        let mut events = Vec::new();
+        let mut time = 0;
+        while pos < end {
+            time += read_var_len(data, &mut pos);
+            let len = match data[pos] {
+                0xff => {
+                    let mut data_pos = pos + 2;
+                    let data_len = read_var_len(data, &mut data_pos) as usize;
+                    data_pos - pos + data_len
+                },
+                status if (status & 0xf0) == 0xc0 => 2,
+                status if status >= 0x80 => 3,
+                status => panic!("Unexpected status byte: {:02x}", status)
+            };
+            events.push((time, data[pos..pos + len].to_vec()));
+            pos += len;
+        }
+        assert_eq!(pos, end);
+        assert_eq!(events.last().unwrap().1, vec![0xff, 0x2f, 0x00]);
+        tracks.push(events);
+    }
+    assert_eq!(pos, data.len());
+
+    (division, tracks)
+}
+
+// Plays one note on voice 0: key on, a pitch slide up a semitone, then key off, with a sample that loops forever
+//  and an envelope that holds at full level
+fn test_apu(source: u8) -> Box<Apu> {
+    let regs = [(0x6c, 0x20), (0x5d, common::DIR), (0x00, 0x40), (0x01, 0x20), (0x02, 0x00), (0x03, 0x10), (0x04, source),
+                (0x05, 0xff), (0x06, 0xe0), (0x0c, 0x7f), (0x1c, 0x7f)];
+    common::program_apu(&regs, "
+        mov $f2, #$4c
+        mov $f3, #$01
+        call wait
+        mov $f2, #$03
+        mov $f3, #$10
+        mov $f2, #$02
+        mov $f3, #$f4
+        call wait
+        mov $f2, #$5c
+        mov $f3, #$01
+        bra *
+
+wait:   mov $10, #20
+outer:  mov y, #0
+inner:  dbnz y, inner
+        dbnz $10, outer
+        ret
+", &common::sample(common::LOOP_HEADER))
+}
+
+fn channel_events(events: &[Event], status: u8) -> Vec<Event> {
+    events.iter().filter(|event| (event.1[0] & 0xf0) == status).cloned().collect()
+}
+
+#[test]
+fn exports_key_on_pitch_slide_and_key_off() {
+    let mut apu = test_apu(0x05);
+    let mut exporter = MidiExporter::new();
+    exporter.set_base_note(0x05, 60.0);
+    exporter.set_pitch_bend_range(2);
+    let (division, tracks) = parse_smf(&exporter.export(&mut apu, 4000));
+
+    assert_eq!(division, 16000);
+    assert_eq!(tracks.len(), 9);
+    for (i, track) in tracks[1..].iter().enumerate() {
+        // Pitch bend sensitivity through rpn 0
+        let channel = i as u8;
+        assert!(track.contains(&(0, vec![0xb0 | channel, 101, 0])));
+        assert!(track.contains(&(0, vec![0xb0 | channel, 100, 0])));
+        assert!(track.contains(&(0, vec![0xb0 | channel, 6, 2])));
+    }
+    for track in tracks[2..].iter() {
+        assert!(channel_events(track, 0x90).is_empty());
+    }
+
+    let track = &tracks[1];
+    assert_eq!(channel_events(track, 0xc0).iter().map(|event| event.1.clone()).collect::<Vec<_>>(), vec![vec![0xc0, 0x05]]);
+
+    let note_ons = channel_events(track, 0x90);
+    let note_offs = channel_events(track, 0x80);
+    assert_eq!(note_ons.len(), 1);
+    assert_eq!(note_offs.len(), 1);
+    let (on_time, ref note_on) = note_ons[0];
+    let (off_time, ref note_off) = note_offs[0];
+    // Sources below $80 are in bank 0
+    assert!(track.contains(&(on_time, vec![0xb0, 0, 0])));
+    assert_eq!(note_on[1], 60);
+    assert_eq!(note_off[1], 60);
+    assert!(off_time > on_time + 1000 && off_time < 4000);
+    // VOL $40 at the envelope's full ENVX of $7f
+    assert_eq!(note_on[2], 0x40);
+
+    // $10f4 is just over a semitone up, so just over half of the +-2 semitone range
+    let bends = channel_events(track, 0xe0);
+    assert_eq!(bends.len(), 1);
+    let (bend_time, ref bend) = bends[0];
+    assert!(bend_time > on_time && bend_time < off_time);
+    let value = (bend[1] as i32) | ((bend[2] as i32) << 7);
+    let expected = 8192.0 + 12.0 * (0x10f4 as f32 / 4096.0).log2() / 2.0 * 8192.0;
+    assert_eq!(value, expected.round() as i32);
+}
+
+#[test]
+fn high_sources_use_second_bank() {
+    let mut apu = test_apu(0x85);
+    let mut exporter = MidiExporter::new();
+    exporter.set_base_note(0x85, 60.0);
+    let (_, tracks) = parse_smf(&exporter.export(&mut apu, 2000));
+
+    let track = &tracks[1];
+    let bank_select = track.iter().position(|event| event.1 == vec![0xb0, 0, 1]).unwrap();
+    let program_change = track.iter().position(|event| event.1 == vec![0xc0, 0x05]).unwrap();
+    let note_on = track.iter().position(|event| (event.1[0] & 0xf0) == 0x90).unwrap();
+    assert!(bank_select < program_change && program_change < note_on);
+}
+
+#[test]
+fn notes_are_paired_in_bundled_spc() {
+    let mut apu = Apu::from_spc_bytes(&fs::read("test/ferris-nu.spc").unwrap()).unwrap();
+    let mut exporter = MidiExporter::new();
+    exporter.set_pitch_bend_range(24);
+    let (_, tracks) = parse_smf(&exporter.export(&mut apu, 32000 * 5));
+
+    let mut num_notes = 0;
+    for (i, track) in tracks[1..].iter().enumerate() {
+        let channel = i as u8;
+        let mut sounding = None;
+        for (_, event) in track.iter() {
+            if event[0] == 0xff {
+                continue;
+            }
+            assert_eq!(event[0] & 0x0f, channel);
+            match event[0] & 0xf0 {
+                0x90 => {
+                    assert_eq!(sounding, None);
+                    assert!(event[2] >= 1 && event[2] <= 127);
+                    sounding = Some(event[1]);
+                    num_notes += 1;
+                },
+                0x80 => {
+                    assert_eq!(sounding, Some(event[1]));
+                    sounding = None;
+                },
+                0xe0 => assert!(event[1] < 0x80 && event[2] < 0x80),
+                _ => ()
+            }
+        }
+        assert_eq!(sounding, None);
+    }
+    assert!(num_notes > 0);
+}
@@ -0,0 +1,69 @@
+#![cfg(all(feature = "serde", feature = "std"))]
+
+extern crate snes_apu;
+extern crate spc;
+extern crate serde_json;
+
+use snes_apu::apu::Apu;
+use snes_apu::dsp::dsp::AccuracyMode;
+use spc::spc::Spc;
+
+const RENDER_LEN: usize = 1024;
+
+fn load(name: &str) -> Box<Apu> {
+    let spc = Spc::load(format!("{}/test/{}.spc", env!("CARGO_MANIFEST_DIR"), name)).unwrap();
+    let mut apu = Apu::from_spc(&spc);
+    apu.clear_echo_buffer();
+    apu
+}
+
+fn render(apu: &mut Apu, num_chunks: usize) -> Vec<i16> {
+    let mut left = [0; RENDER_LEN];
+    let mut right = [0; RENDER_LEN];
+    let mut ret = Vec::new();
+    for _ in 0..num_chunks {
+        apu.render(&mut left, &mut right, RENDER_LEN as i32);
+        ret.extend_from_slice(&left);
+        ret.extend_from_slice(&right);
+    }
+    ret
+}
+
+fn check_round_trip(mut apu: Box<Apu>) {
+    render(&mut apu, 20);
+
+    let json = serde_json::to_string(&apu).unwrap();
+    let mut restored: Box<Apu> = serde_json::from_str(&json).unwrap();
+
+    assert!(render(&mut apu, 100) == render(&mut restored, 100), "restored apu diverged from the original");
+    assert_eq!(serde_json::to_string(&apu).unwrap(), serde_json::to_string(&restored).unwrap());
+}
+
+#[test]
+fn round_trip_restores_playback() {
+    check_round_trip(load("ferris-nu"));
+}
+
+#[test]
+fn round_trip_restores_clock_accurate_playback() {
+    let mut apu = load("smashit");
+    apu.dsp.as_mut().unwrap().set_accuracy_mode(AccuracyMode::Clock);
+    check_round_trip(apu);
+}
+
+#[test]
+fn state_snapshots_serialize() {
+    let apu = load("smashit");
+    let json = serde_json::to_string(&apu.dsp_state()).unwrap();
+    let state: snes_apu::state::DspState = serde_json::from_str(&json).unwrap();
+    assert!(state.regs[..] == apu.dsp_state().regs[..]);
+    assert_eq!(state.voices[7].pitch, apu.voice_state(7).pitch);
+}
+
+#[test]
+fn truncated_ram_is_rejected() {
+    let apu = load("smashit");
+    let mut value: serde_json::Value = serde_json::to_value(&apu).unwrap();
+    value["ram"].as_array_mut().unwrap().truncate(100);
+    assert!(serde_json::from_value::<Box<Apu>>(value).is_err());
+}
@@ -0,0 +1,109 @@
+extern crate snes_apu;
+
+mod common;
+
+use snes_apu::apu::Apu;
+use snes_apu::silence_detector::SilenceDetector;
+
+// A single note on voice 0, keyed on once and then left alone. The flags in the second block's header
+//  decide whether the sample loops.
+fn one_note_apu(brr_header: u8, volume: u8) -> Box<Apu> {
+    let regs = [(0x5d, common::DIR), (0x0c, 0x7f), (0x1c, 0x7f), (0x6c, 0x20),
+                (0x00, volume), (0x01, volume), (0x02, 0x00), (0x03, 0x10), (0x04, 0x00), (0x05, 0xff), (0x06, 0xe0)];
+    common::program_apu(&regs, "
+        mov $f2, #$4c
+        mov $f3, #$01
+        bra *
+", &common::sample(brr_header))
+}
+
+// Renders up to max_samples in small blocks and returns how many it took to reach the end of the track
+fn render_until_end(apu: &mut Apu, max_samples: i32) -> Option<i32> {
+    let mut left = [0; 256];
+    let mut right = [0; 256];
+    let mut rendered = 0;
+    while rendered < max_samples {
+        apu.render(&mut left, &mut right, 256);
+        rendered += 256;
+        if apu.is_end_of_track() {
+            return Some(rendered);
+        }
+    }
+    None
+}
+
+#[test]
+fn counts_samples_within_threshold_as_silent() {
+    let mut detector = SilenceDetector::new(4, 10);
+    detector.process(&[4; 9], &[-4; 9]);
+    assert!(!detector.is_output_silent());
+    detector.process(&[0], &[0]);
+    assert!(detector.is_output_silent());
+
+    detector.process(&[0], &[5]);
+    assert!(!detector.is_output_silent());
+
+    detector.process(&[0; 10], &[0; 10]);
+    detector.reset();
+    assert!(!detector.is_output_silent());
+    assert!(!detector.is_end_of_track());
+}
+
+#[test]
+fn full_scale_negative_sample_is_loud() {
+    let mut detector = SilenceDetector::new(i16::MAX, 1);
+    detector.process(&[-32768], &[0]);
+    assert!(!detector.is_output_silent());
+    detector.process(&[0], &[-32768]);
+    assert!(!detector.is_output_silent());
+    detector.process(&[32767], &[-32767]);
+    assert!(detector.is_output_silent());
+}
+
+#[test]
+fn echo_buffer_holding_full_scale_negative_sample_is_not_silent() {
+    let mut apu = Apu::new();
+    for pair in apu.ram_mut().chunks_mut(2) {
+        pair[0] = 0x00;
+        pair[1] = 0x80;
+    }
+
+    let dsp = apu.dsp.as_mut().unwrap();
+    dsp.set_register(0x2c, 0x00);
+    dsp.set_register(0x3c, 0x00);
+    assert!(dsp.is_echo_silent(i16::MAX));
+    dsp.set_register(0x2c, 0x7f);
+    assert!(!dsp.is_echo_silent(i16::MAX));
+}
+
+#[test]
+fn ends_track_after_one_shot_note_dies() {
+    let mut apu = one_note_apu(common::END_HEADER, 0x7f);
+    apu.set_silence_detector(Some(SilenceDetector::new(0, 3200)));
+
+    let samples = render_until_end(&mut apu, 32000).expect("track never ended");
+    // The note lasts about 64 samples; it then takes 3200 samples of silence to end the track
+    assert!((3200..=3200 + 1024).contains(&samples), "ended after {} samples", samples);
+
+    // Once the track has ended it stays ended
+    let mut left = [0; 256];
+    let mut right = [0; 256];
+    apu.render(&mut left, &mut right, 256);
+    assert!(apu.is_end_of_track());
+}
+
+#[test]
+fn looping_note_never_ends_track() {
+    let mut apu = one_note_apu(common::LOOP_HEADER, 0x7f);
+    apu.set_silence_detector(Some(SilenceDetector::new(0, 3200)));
+    assert_eq!(render_until_end(&mut apu, 32000), None);
+}
+
+#[test]
+fn held_note_with_volume_down_never_ends_track() {
+    // The output is silent, but the voice is still keyed on and could be turned back up at any time
+    let mut apu = one_note_apu(common::LOOP_HEADER, 0x00);
+    apu.set_silence_detector(Some(SilenceDetector::new(0, 3200)));
+    assert_eq!(render_until_end(&mut apu, 32000), None);
+    assert!(apu.silence_detector().unwrap().is_output_silent());
+}
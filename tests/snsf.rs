@@ -0,0 +1,159 @@
+#![cfg(feature = "snsf")]
+
+extern crate flate2;
+extern crate snes_apu;
+
+use snes_apu::error::SnsfError;
+use snes_apu::psf::PsfFile;
+use snes_apu::snsf::{Snsf, SNSF_VERSION};
+
+use flate2::Crc;
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+const UPLOAD_OFFSET: usize = 0x1234;
+
+// A rom with a driver upload in it: mov $f5, #$5a; bra -2, padded out to look like a real driver, then a
+//  block of sample data
+fn test_rom() -> Vec<u8> {
+    let mut program = vec![0x8f, 0x5a, 0xf5, 0x2f, 0xfe];
+    program.resize(0x200, 0);
+
+    let mut ret = vec![0xff; 0x8000];
+    let mut upload = Vec::new();
+    for &(address, ref data) in [(0x0200u16, program), (0x2000, vec![0x11; 0x40])].iter() {
+        upload.extend_from_slice(&[data.len() as u8, (data.len() >> 8) as u8, address as u8, (address >> 8) as u8]);
+        upload.extend_from_slice(data);
+    }
+    upload.extend_from_slice(&[0, 0, 0x00, 0x02]);
+    ret[UPLOAD_OFFSET..UPLOAD_OFFSET + upload.len()].copy_from_slice(&upload);
+    ret
+}
+
+fn chunk(offset: u32, data: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::new();
+    for &x in [offset, data.len() as u32].iter() {
+        ret.extend_from_slice(&[x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8]);
+    }
+    ret.extend_from_slice(data);
+    ret
+}
+
+fn psf(version: u8, reserved: &[u8], program: &[u8], tags: &str) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(program).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let mut crc = Crc::new();
+    crc.update(&compressed);
+
+    let mut ret = b"PSF".to_vec();
+    ret.push(version);
+    for &x in [reserved.len() as u32, compressed.len() as u32, crc.sum()].iter() {
+        ret.extend_from_slice(&[x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8]);
+    }
+    ret.extend_from_slice(reserved);
+    ret.extend_from_slice(&compressed);
+    if !tags.is_empty() {
+        ret.extend_from_slice(b"[TAG]");
+        ret.extend_from_slice(tags.as_bytes());
+    }
+    ret
+}
+
+fn test_dir(name: &str, files: &[(&str, Vec<u8>)]) -> PathBuf {
+    let ret = env::temp_dir().join(format!("snes-apu-snsf-{}", name));
+    let _ = fs::remove_dir_all(&ret);
+    fs::create_dir_all(&ret).unwrap();
+    for &(file_name, ref data) in files.iter() {
+        fs::write(ret.join(file_name), data).unwrap();
+    }
+    ret
+}
+
+#[test]
+fn parses_psf_tags() {
+    let data = psf(SNSF_VERSION, &[], &chunk(0, &[1, 2, 3]), "title=Song\r\n  artist = Someone \ncomment=One\ncomment=Two\n_lib=base.snsflib\n_lib2=extra.snsflib\nnot a tag\n");
+    let file = PsfFile::parse(&data).unwrap();
+    assert_eq!(file.version, SNSF_VERSION);
+    assert_eq!(file.program, chunk(0, &[1, 2, 3]));
+    assert_eq!(file.tag("TITLE"), Some("Song"));
+    assert_eq!(file.tag("artist"), Some("Someone"));
+    assert_eq!(file.tag("comment"), Some("One\nTwo"));
+    assert_eq!(file.libs(), vec!["base.snsflib", "extra.snsflib"]);
+}
+
+#[test]
+fn plays_minisnsf_through_lib() {
+    // The minisnsf patches the immediate in the driver's first instruction
+    let mini = psf(SNSF_VERSION, &chunk(0, &[0xab]), &chunk((UPLOAD_OFFSET + 5) as u32, &[0x77]), "_lib=test.snsflib\nlength=1:02.5\nfade=10");
+    let dir = test_dir("minisnsf", &[
+        ("test.snsflib", psf(SNSF_VERSION, &[], &chunk(0, &test_rom()), "")),
+        ("song.minisnsf", mini)]);
+
+    let snsf = Snsf::load(dir.join("song.minisnsf")).unwrap();
+    assert_eq!(snsf.rom.len(), 0x8000);
+    assert_eq!(snsf.sram, vec![0xab]);
+    assert_eq!(snsf.play_samples(), Some(62500 * 32));
+    assert_eq!(snsf.fade_samples(), Some(10 * 32000));
+
+    let upload = snsf.find_upload().unwrap();
+    assert_eq!(upload.entry, 0x0200);
+    assert_eq!(upload.blocks.len(), 2);
+
+    let mut apu = snsf.create_apu().unwrap();
+    let mut left = [0; 16];
+    let mut right = [0; 16];
+    apu.render(&mut left, &mut right, 16);
+    assert_eq!(apu.read_port(1), 0x77);
+    assert!(apu.ram()[0x2000..0x2040].iter().all(|&x| x == 0x11));
+}
+
+#[test]
+fn invalid_files_are_rejected() {
+    let mut corrupt = psf(SNSF_VERSION, &[], &chunk(0, &test_rom()), "");
+    corrupt[20] ^= 0xff;
+    let dir = test_dir("invalid", &[
+        ("corrupt.snsf", corrupt),
+        ("other.psf", psf(0x01, &[], &chunk(0, &[0]), "")),
+        ("loop.minisnsf", psf(SNSF_VERSION, &[], &chunk(0, &[0]), "_lib=loop.minisnsf")),
+        ("empty.snsf", psf(SNSF_VERSION, &[], &chunk(0, &[0xff; 0x1000]), "")),
+        ("past_end.snsf", psf(SNSF_VERSION, &[], &chunk(0xffffffff, &[0]), ""))]);
+
+    match Snsf::load(dir.join("corrupt.snsf")) {
+        Err(SnsfError::CrcMismatch) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+    match Snsf::load(dir.join("other.psf")) {
+        Err(SnsfError::UnsupportedVersion(0x01)) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+    match Snsf::load(dir.join("loop.minisnsf")) {
+        Err(SnsfError::LibTooDeep) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+    match Snsf::load(dir.join("missing.snsf")) {
+        Err(SnsfError::Io(_)) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+    match Snsf::load(dir.join("empty.snsf")).unwrap().create_apu() {
+        Err(SnsfError::NoUpload) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+    match PsfFile::parse(b"PSF\x23\x00") {
+        Err(SnsfError::Truncated { len: 5, expected: 16 }) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+    match Snsf::load(dir.join("past_end.snsf")) {
+        Err(SnsfError::InvalidChunk) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+    match PsfFile::parse(b"PSF\x23\xff\xff\xff\xff\xff\xff\xff\xff\x00\x00\x00\x00") {
+        Err(SnsfError::Truncated { len: 16, .. }) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+}
@@ -0,0 +1,189 @@
+#![cfg(feature = "std")]
+
+extern crate snes_apu;
+#[cfg(feature = "zip")]
+extern crate zip;
+
+use snes_apu::apu::Apu;
+use snes_apu::error::SoundtrackError;
+use snes_apu::soundtrack::SoundtrackSet;
+use snes_apu::xid6::{Xid6Tag, TICKS_PER_SECOND};
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+#[cfg(feature = "zip")]
+use std::io::{Cursor, Write};
+#[cfg(feature = "zip")]
+use zip::ZipWriter;
+#[cfg(feature = "zip")]
+use zip::write::FileOptions;
+
+const FERRIS_NU: &[u8] = include_bytes!("../test/ferris-nu.spc");
+const SMASHIT: &[u8] = include_bytes!("../test/smashit.spc");
+const B0RKED: &[u8] = include_bytes!("../test/broken/b0rked.spc");
+const INFO: &str = "Demo soundtrack\r\n01 nu\r\n02 Smash It\r\n";
+
+// smashit has no id666 tag, so give it an xid6 one
+fn tagged_smashit() -> Vec<u8> {
+    let mut tag = Xid6Tag::new();
+    tag.song_name = Some("Smash It".to_string());
+    tag.intro_length = Some(10 * TICKS_PER_SECOND);
+    tag.fade_length = Some(TICKS_PER_SECOND);
+    let mut ret = SMASHIT.to_vec();
+    tag.write_to_spc(&mut ret);
+    ret
+}
+
+fn set_files() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("info.txt", INFO.as_bytes().to_vec()),
+        ("02 smashit.SPC", tagged_smashit()),
+        ("sub/01 ferris-nu.spc", FERRIS_NU.to_vec()),
+    ]
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let ret = env::temp_dir().join(format!("snes-apu-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&ret);
+    ret
+}
+
+fn write_directory(name: &str, files: &[(&'static str, Vec<u8>)]) -> PathBuf {
+    let ret = temp_dir(name);
+    for &(file_name, ref data) in files.iter() {
+        let path = ret.join(file_name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+    ret
+}
+
+#[cfg(feature = "zip")]
+fn write_zip(files: &[(&'static str, Vec<u8>)]) -> Vec<u8> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for &(name, ref data) in files.iter() {
+        writer.start_file(name, FileOptions::default()).unwrap();
+        writer.write_all(data).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+fn check_set(set: &mut SoundtrackSet) {
+    assert_eq!(set.info(), Some(INFO));
+
+    {
+        let tracks = set.tracks();
+        assert_eq!(tracks.len(), 2);
+
+        assert_eq!(tracks[0].name, "02 smashit.SPC");
+        assert_eq!(tracks[0].song_title, "Smash It");
+        assert_eq!(tracks[0].play_samples, Some(10 * 32000));
+        assert_eq!(tracks[0].fade_samples, Some(32000));
+        assert!(tracks[0].xid6_tag.is_some());
+
+        assert_eq!(tracks[1].name, "sub/01 ferris-nu.spc");
+        assert_eq!(tracks[1].song_title, "nu");
+        assert!(tracks[1].xid6_tag.is_none());
+    }
+
+    let mut left = vec![0; 4000];
+    let mut right = vec![0; 4000];
+    let mut expected_left = vec![0; 4000];
+    let mut expected_right = vec![0; 4000];
+    set.create_apu(1).unwrap().render(&mut left, &mut right, 4000);
+    Apu::from_spc_bytes(FERRIS_NU).unwrap().render(&mut expected_left, &mut expected_right, 4000);
+    assert!(left == expected_left && right == expected_right);
+
+    assert!(set.track_data(0).unwrap() == tagged_smashit());
+    match set.create_apu(2) {
+        Err(SoundtrackError::TrackIndexOutOfRange(2)) => (),
+        _ => panic!("Expected index out of range")
+    }
+}
+
+#[test]
+fn loads_directory() {
+    let dir = write_directory("directory", &set_files());
+    let mut set = SoundtrackSet::open(&dir).unwrap();
+    check_set(&mut set);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+fn with_bad_tracks(mut files: Vec<(&'static str, Vec<u8>)>) -> Vec<(&'static str, Vec<u8>)> {
+    files.push(("03 b0rked.spc", B0RKED.to_vec()));
+    files.push(("04 truncated.spc", FERRIS_NU[..0x1000].to_vec()));
+    files
+}
+
+fn check_skipped_tracks(set: &SoundtrackSet) {
+    let skipped = set.skipped_tracks();
+    assert_eq!(skipped.len(), 2);
+    for (track, &name) in skipped.iter().zip(["03 b0rked.spc", "04 truncated.spc"].iter()) {
+        assert_eq!(track.name, name);
+        match track.error {
+            SoundtrackError::InvalidTrack { name: ref error_name, .. } if error_name == name => (),
+            ref e => panic!("Expected invalid track, got {}", e)
+        }
+    }
+}
+
+#[test]
+fn bad_tracks_are_skipped() {
+    let dir = write_directory("invalid", &with_bad_tracks(set_files()));
+    let mut set = SoundtrackSet::from_directory(&dir).unwrap();
+    check_set(&mut set);
+    check_skipped_tracks(&set);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn loads_zip() {
+    let mut set = SoundtrackSet::from_zip_bytes(write_zip(&set_files())).unwrap();
+    check_set(&mut set);
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn bad_tracks_in_zip_are_skipped() {
+    let mut set = SoundtrackSet::from_zip_bytes(write_zip(&with_bad_tracks(set_files()))).unwrap();
+    check_set(&mut set);
+    check_skipped_tracks(&set);
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn opens_zip_named_rsn() {
+    let dir = temp_dir("rsn");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("demo.rsn");
+    fs::write(&path, write_zip(&set_files())).unwrap();
+    let mut set = SoundtrackSet::open(&path).unwrap();
+    check_set(&mut set);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn rar_is_unsupported() {
+    match SoundtrackSet::from_zip_bytes(b"Rar!\x1a\x07\x00".to_vec()) {
+        Err(SoundtrackError::UnsupportedArchive) => (),
+        _ => panic!("Expected unsupported archive")
+    }
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn rar_file_is_unsupported() {
+    let dir = temp_dir("rar");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("demo.rsn");
+    fs::write(&path, b"Rar!\x1a\x07\x00").unwrap();
+    match SoundtrackSet::open(&path) {
+        Err(SoundtrackError::UnsupportedArchive) => (),
+        _ => panic!("Expected unsupported archive")
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,139 @@
+extern crate snes_apu;
+
+use snes_apu::apu::{Apu, RAM_LEN};
+use snes_apu::error::SpcError;
+use snes_apu::spc2::{Spc2, Spc2Song, RAM_BLOCK_LEN};
+use snes_apu::xid6::TICKS_PER_SECOND;
+
+const FERRIS_NU: &[u8] = include_bytes!("../test/ferris-nu.spc");
+const SMASHIT: &[u8] = include_bytes!("../test/smashit.spc");
+
+fn render(apu: &mut Apu, num_samples: usize) -> (Vec<i16>, Vec<i16>) {
+    let mut left = vec![0; num_samples];
+    let mut right = vec![0; num_samples];
+    apu.render(&mut left, &mut right, num_samples as i32);
+    (left, right)
+}
+
+fn test_set() -> Spc2 {
+    let mut ret = Spc2::new();
+    for &(name, data) in [("ferris-nu.spc", FERRIS_NU), ("smashit.spc", SMASHIT)].iter() {
+        let mut song = Spc2Song::from_spc_bytes(data).unwrap();
+        song.file_name = name.to_string();
+        ret.songs.push(song);
+    }
+    ret
+}
+
+fn write(spc2: &Spc2) -> Vec<u8> {
+    let mut ret = Vec::new();
+    spc2.write(&mut ret).unwrap();
+    ret
+}
+
+#[test]
+fn round_trips() {
+    let expected = test_set();
+    let data = write(&expected);
+    assert_eq!(&data[..5], b"KSPC\x1a");
+    assert_eq!((data.len() - 0x10 - 2 * 0x400) % RAM_BLOCK_LEN, 0);
+
+    let actual = Spc2::parse(&data).unwrap();
+    assert_eq!(actual.songs.len(), 2);
+    for (actual, expected) in actual.songs.iter().zip(expected.songs.iter()) {
+        assert!(actual.ram == expected.ram);
+        assert!(actual.dsp_regs == expected.dsp_regs);
+        assert!(actual.ipl_rom == expected.ipl_rom);
+        assert_eq!((actual.pc, actual.a, actual.x, actual.y, actual.psw, actual.sp), (expected.pc, expected.a, expected.x, expected.y, expected.psw, expected.sp));
+        assert_eq!(actual.file_name, expected.file_name);
+        assert_eq!(actual.play_length, expected.play_length);
+        assert_eq!(actual.fade_length, expected.fade_length);
+    }
+}
+
+#[test]
+fn reconstructed_songs_render_like_originals() {
+    let spc2 = Spc2::parse(&write(&test_set())).unwrap();
+    for (song, original) in spc2.songs.iter().zip([FERRIS_NU, SMASHIT].iter()) {
+        let mut expected_apu = Apu::from_spc_bytes(original).unwrap();
+        let mut actual_apu = Apu::from_spc_bytes(&song.to_spc_bytes()).unwrap();
+        assert!(render(&mut actual_apu, 32000) == render(&mut expected_apu, 32000));
+
+        let mut actual_apu = song.create_apu().unwrap();
+        let mut expected_apu = Apu::from_spc_bytes(original).unwrap();
+        assert!(render(&mut actual_apu, 1000) == render(&mut expected_apu, 1000));
+    }
+}
+
+#[test]
+fn shared_ram_blocks_are_stored_once() {
+    let mut spc2 = Spc2::new();
+    let song = Spc2Song::from_spc_bytes(SMASHIT).unwrap();
+    spc2.songs.push(song.clone());
+    let single_len = write(&spc2).len();
+
+    // A second copy only costs its metadata, plus one block for a page that differs
+    let mut other = song.clone();
+    other.ram[0x1234] ^= 0xff;
+    spc2.songs.push(song);
+    spc2.songs.push(other);
+    assert_eq!(write(&spc2).len(), single_len + 2 * 0x400 + RAM_BLOCK_LEN);
+    assert!(single_len < 0x10 + 0x400 + RAM_LEN);
+}
+
+#[test]
+fn metadata_round_trips() {
+    let mut song = Spc2Song::new();
+    song.song_name = "Song".to_string();
+    song.game_name = "Game".to_string();
+    song.artist_name = "Artist".to_string();
+    song.dumper_name = "Dumper".to_string();
+    song.comments = "Comments".to_string();
+    song.ost_title = "Soundtrack".to_string();
+    song.publisher = "Publisher".to_string();
+    song.file_name = "song.spc".to_string();
+    song.date_dumped = Some(20171211);
+    song.emulator = 2;
+    song.ost_disc = 1;
+    song.ost_track = 12;
+    song.copyright_year = 1995;
+    song.play_length = 90 * TICKS_PER_SECOND + 123;
+    song.fade_length = 10 * TICKS_PER_SECOND;
+    song.amplification = 0x18000;
+    song.channel_disables = 0x81;
+
+    let mut spc2 = Spc2::new();
+    spc2.songs.push(song.clone());
+    assert_eq!(Spc2::parse(&write(&spc2)).unwrap().songs[0], song);
+
+    // Going through an spc file keeps everything but the file name, with the exact lengths in the xid6 tag
+    let mut actual = Spc2Song::from_spc_bytes(&song.to_spc_bytes()).unwrap();
+    actual.file_name = song.file_name.clone();
+    assert_eq!(actual, song);
+}
+
+#[test]
+fn invalid_data_is_rejected() {
+    let mut data = write(&test_set());
+    match Spc2::parse(&data[..0x100]) {
+        Err(SpcError::Truncated { len: 0x100, expected: 0x810 }) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+    match Spc2::parse(SMASHIT) {
+        Err(SpcError::InvalidMagic) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+
+    data[0x10 + 0x100] = 0xff;
+    data[0x10 + 0x101] = 0xff;
+    match Spc2::parse(&data) {
+        Err(SpcError::InvalidRamBlock(0xffff)) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+
+    data[5] = 2;
+    match Spc2::parse(&data) {
+        Err(SpcError::UnsupportedVersion) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ()))
+    }
+}
@@ -0,0 +1,232 @@
+// Single-instruction SPC700 conformance tests.
+//
+// Test vectors are JSON arrays of tests in the format used by the community single step test sets:
+//
+//   { "name": "...",
+//     "initial": { "pc": .., "a": .., "x": .., "y": .., "sp": .., "psw": .., "ram": [[address, value], ...] },
+//     "final": { same as initial },
+//     "cycles": [[address, value, "read" | "write" | "wait"], ...] }
+//
+// Besides the final state, the bus activity of every cycle is checked: the address and value of each read and
+//  write, and where the cpu is idle. Hand-written vectors can give "cycle_count" instead of "cycles", in which
+//  case only the number of cycles is checked.
+//
+// The bundled vectors in tests/spc700 weren't recorded from this emulator. opcodes.json has six vectors for each
+//  of the 256 opcodes, covering every addressing mode and both outcomes of each branch, with the documented cycle
+//  counts. It's generated by generate.py, a separate reference model written from nocash's fullsnes and
+//  anomie's SPC700 doc. alu.json holds hand-picked flag edge cases of adc, sbc, daa, das, div, addw and subw.
+//  Neither checks individual bus cycles; the full community set (SingleStepTests spc700) does, but is too big
+//  to bundle, so point SPC700_TESTS_DIR at a checkout of its json files to run it as well. Tests that touch the
+//  io registers at $f0-$ff are skipped, as those aren't plain memory on the apu.
+
+extern crate snes_apu;
+extern crate serde_json;
+
+use snes_apu::apu::Apu;
+use snes_apu::smp::BusCycle;
+
+use serde_json::Value;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const EXTERNAL_TESTS_VAR: &str = "SPC700_TESTS_DIR";
+
+const MAX_REPORTED_FAILURES: usize = 20;
+
+struct CpuState {
+    pc: u16,
+    a: u8,
+    x: u8,
+    y: u8,
+    sp: u8,
+    psw: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+impl CpuState {
+    fn parse(value: &Value) -> CpuState {
+        let field = |name: &str| value[name].as_u64().unwrap_or_else(|| panic!("missing field {}", name));
+        CpuState {
+            pc: field("pc") as u16,
+            a: field("a") as u8,
+            x: field("x") as u8,
+            y: field("y") as u8,
+            sp: field("sp") as u8,
+            psw: field("psw") as u8,
+            ram: value["ram"].as_array().unwrap().iter().map(|entry| {
+                (entry[0].as_u64().unwrap() as u16, entry[1].as_u64().unwrap() as u8)
+            }).collect(),
+        }
+    }
+}
+
+struct Test {
+    name: String,
+    initial: CpuState,
+    expected: CpuState,
+    cycles: Option<Vec<Value>>,
+    cycle_count: Option<usize>,
+}
+
+impl Test {
+    fn parse(value: &Value) -> Test {
+        Test {
+            name: value["name"].as_str().unwrap_or("").to_string(),
+            initial: CpuState::parse(&value["initial"]),
+            expected: CpuState::parse(&value["final"]),
+            cycles: value["cycles"].as_array().cloned(),
+            cycle_count: value["cycle_count"].as_u64().map(|count| count as usize),
+        }
+    }
+
+    fn touches_io(&self) -> bool {
+        let is_io = |address: u16| (0x00f0..0x0100).contains(&address);
+        self.initial.ram.iter().chain(self.expected.ram.iter()).any(|&(address, _)| is_io(address)) ||
+            self.cycles.iter().flat_map(|cycles| cycles.iter()).any(|cycle| match cycle[0].as_u64() {
+                Some(address) => is_io(address as u16),
+                _ => false
+            })
+    }
+
+    fn compare_cycles(&self, actual: &[BusCycle], ret: &mut Vec<String>) {
+        let cycles = match self.cycles {
+            Some(ref cycles) => cycles,
+            _ => {
+                if let Some(count) = self.cycle_count {
+                    if actual.len() != count {
+                        ret.push(format!("cycles: expected {}, got {}", count, actual.len()));
+                    }
+                }
+                return;
+            }
+        };
+        if actual.len() != cycles.len() {
+            ret.push(format!("cycles: expected {}, got {}", cycles.len(), actual.len()));
+        }
+        for (i, (expected, actual)) in cycles.iter().zip(actual.iter()).enumerate() {
+            let expected = match (expected[2].as_str(), expected[0].as_u64(), expected[1].as_u64()) {
+                (Some("read"), Some(address), Some(value)) => BusCycle::Read(address as u16, value as u8),
+                (Some("write"), Some(address), Some(value)) => BusCycle::Write(address as u16, value as u8),
+                (Some("wait"), _, _) => BusCycle::Idle,
+                _ => panic!("malformed cycle {} in {}", i, self.name)
+            };
+            if *actual != expected {
+                ret.push(format!("cycle {}: expected {}, got {}", i, describe_cycle(expected), describe_cycle(*actual)));
+                return;
+            }
+        }
+    }
+
+    // Runs the test, returning a description of every mismatch
+    fn run(&self) -> Vec<String> {
+        let mut apu = Apu::new();
+        // Map ram over the ipl rom so vectors can place code and data anywhere
+        apu.write_u8(0xf1, 0x00);
+        for &(address, value) in self.initial.ram.iter() {
+            apu.ram_mut()[address as usize] = value;
+        }
+
+        let bus_log = {
+            let smp = apu.smp.as_mut().unwrap();
+            smp.set_bus_logging(true);
+            smp.reg_pc = self.initial.pc;
+            smp.reg_a = self.initial.a;
+            smp.reg_x = self.initial.x;
+            smp.reg_y = self.initial.y;
+            smp.reg_sp = self.initial.sp;
+            smp.set_psw(self.initial.psw);
+            smp.run(1);
+            smp.take_bus_log()
+        };
+
+        let mut ret = Vec::new();
+        {
+            let smp = apu.smp.as_ref().unwrap();
+            let mut compare = |name: &str, actual: u32, expected: u32| {
+                if actual != expected {
+                    ret.push(format!("{}: expected ${:02x}, got ${:02x}", name, expected, actual));
+                }
+            };
+            compare("pc", smp.reg_pc as u32, self.expected.pc as u32);
+            compare("a", smp.reg_a as u32, self.expected.a as u32);
+            compare("x", smp.reg_x as u32, self.expected.x as u32);
+            compare("y", smp.reg_y as u32, self.expected.y as u32);
+            compare("sp", smp.reg_sp as u32, self.expected.sp as u32);
+            compare("psw", smp.get_psw() as u32, self.expected.psw as u32);
+        }
+        self.compare_cycles(&bus_log, &mut ret);
+        for &(address, value) in self.expected.ram.iter() {
+            let actual = apu.ram()[address as usize];
+            if actual != value {
+                ret.push(format!("ram ${:04x}: expected ${:02x}, got ${:02x}", address, value, actual));
+            }
+        }
+        ret
+    }
+}
+
+fn describe_cycle(cycle: BusCycle) -> String {
+    match cycle {
+        BusCycle::Read(address, value) => format!("read ${:02x} from ${:04x}", value, address),
+        BusCycle::Write(address, value) => format!("write ${:02x} to ${:04x}", value, address),
+        BusCycle::Idle => "idle".to_string()
+    }
+}
+
+fn vector_files(dir: &Path) -> Vec<PathBuf> {
+    let mut ret = fs::read_dir(dir).unwrap_or_else(|e| panic!("couldn't read {}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .collect::<Vec<_>>();
+    ret.sort();
+    ret
+}
+
+fn run_files(files: &[PathBuf]) {
+    let mut num_run = 0;
+    let mut num_skipped = 0;
+    let mut failures = Vec::new();
+    for path in files.iter() {
+        let mut contents = String::new();
+        File::open(path).unwrap().read_to_string(&mut contents).unwrap();
+        let tests: Value = serde_json::from_str(&contents).unwrap_or_else(|e| panic!("couldn't parse {}: {}", path.display(), e));
+        for value in tests.as_array().unwrap().iter() {
+            let test = Test::parse(value);
+            if test.touches_io() {
+                num_skipped += 1;
+                continue;
+            }
+            num_run += 1;
+            let mismatches = test.run();
+            if !mismatches.is_empty() {
+                failures.push(format!("{} ({}):\n    {}", test.name, path.file_name().unwrap().to_string_lossy(), mismatches.join("\n    ")));
+            }
+        }
+    }
+
+    println!("ran {} tests, skipped {} touching io registers", num_run, num_skipped);
+    if !failures.is_empty() {
+        let mut report = failures.iter().take(MAX_REPORTED_FAILURES).cloned().collect::<Vec<_>>().join("\n");
+        if failures.len() > MAX_REPORTED_FAILURES {
+            report.push_str(&format!("\n... and {} more", failures.len() - MAX_REPORTED_FAILURES));
+        }
+        panic!("{} of {} tests failed:\n{}", failures.len(), num_run, report);
+    }
+}
+
+#[test]
+fn bundled_vectors() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("spc700");
+    run_files(&vector_files(&dir));
+}
+
+#[test]
+fn external_vectors() {
+    match env::var_os(EXTERNAL_TESTS_VAR) {
+        Some(dir) => run_files(&vector_files(Path::new(&dir))),
+        _ => println!("{} not set, skipping external test vectors", EXTERNAL_TESTS_VAR)
+    }
+}
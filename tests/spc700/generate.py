@@ -0,0 +1,369 @@
+# Per-opcode SPC700 reference model, written from nocash's fullsnes (SPC700 opcode and cycle tables, ALU flag
+# descriptions) and anomie's SPC700 doc (DIV, DAA/DAS, the 16-bit ops). Deliberately not derived from the
+# emulator. Generates six vectors for each of the 256 opcodes, with fixed seeds so the output is reproducible:
+#
+#   python3 tests/spc700/generate.py tests/spc700/opcodes.json
+import json, random, sys
+N,V,P,B,H,I,Z,C=0x80,0x40,0x20,0x10,0x08,0x04,0x02,0x01
+rng=random.Random(0x5bc700)
+
+# fullsnes cycle counts; branches add 2 when taken
+CYCLES={}
+def cyc(ops,n):
+    for o in ops: CYCLES[o]=n
+cyc([0x00],2)
+cyc([0x10,0x30,0x50,0x70,0x90,0xb0,0xd0,0xf0],2)
+cyc([0x20,0x40,0x60,0x80,0xe0],2); cyc([0xa0,0xc0],3)
+cyc([i*16+1 for i in range(16)],8)
+cyc([i*16+2 for i in range(16)],4)
+cyc([i*16+3 for i in range(16)],5)
+for hi in range(0,0xc,2):
+    b=hi<<4
+    cyc([b+4],3); cyc([b+0x14],4); cyc([b+5],4); cyc([b+0x15],5); cyc([b+6],3); cyc([b+0x16],5)
+    cyc([b+7],6); cyc([b+0x17],6); cyc([b+8],2); cyc([b+0x18],5); cyc([b+9],6); cyc([b+0x19],5)
+cyc([0xc4],4); cyc([0xd4],5); cyc([0xe4],3); cyc([0xf4],4)
+cyc([0xc5],5); cyc([0xd5],6); cyc([0xe5],4); cyc([0xf5],5)
+cyc([0xc6],4); cyc([0xd6],6); cyc([0xe6],3); cyc([0xf6],5)
+cyc([0xc7],7); cyc([0xd7],7); cyc([0xe7],6); cyc([0xf7],6)
+cyc([0xc8],2); cyc([0xd8],4); cyc([0xe8],2); cyc([0xf8],3)
+cyc([0xc9],5); cyc([0xd9],5); cyc([0xe9],4); cyc([0xf9],4)
+cyc([0x0a],5); cyc([0x1a],6); cyc([0x2a],5); cyc([0x3a],6); cyc([0x4a],4); cyc([0x5a],4); cyc([0x6a],4)
+cyc([0x7a],5); cyc([0x8a],5); cyc([0x9a],5); cyc([0xaa],4); cyc([0xba],5); cyc([0xca],6); cyc([0xda],5)
+cyc([0xea],5); cyc([0xfa],5)
+cyc([0x0b,0x2b,0x4b,0x6b,0x8b,0xab,0xcb],4); cyc([0x1b,0x3b,0x5b,0x7b,0x9b,0xbb,0xdb],5)
+cyc([0xeb],3); cyc([0xfb],4)
+cyc([0x0c,0x2c,0x4c,0x6c,0x8c,0xac,0xcc],5); cyc([0x1c,0x3c,0x5c,0x7c,0x9c,0xbc,0xdc,0xfc],2); cyc([0xec],4)
+cyc([0x0d,0x2d,0x4d,0x6d],4); cyc([0x1d,0x3d,0x5d,0x7d,0x8d,0x9d,0xad,0xbd,0xcd,0xdd,0xfd],2); cyc([0xed],3)
+cyc([0x0e,0x4e],6); cyc([0x1e,0x5e],4); cyc([0x2e],5); cyc([0x3e,0x7e],3); cyc([0x6e],5)
+cyc([0x8e,0xae,0xce,0xee],4); cyc([0x9e],12); cyc([0xbe],3); cyc([0xde],6); cyc([0xfe],4)
+cyc([0x0f],8); cyc([0x1f],6); cyc([0x2f],4); cyc([0x3f],8); cyc([0x4f],6); cyc([0x5f],3); cyc([0x6f],5)
+cyc([0x7f],6); cyc([0x8f],5); cyc([0x9f],5); cyc([0xaf],4); cyc([0xbf],4); cyc([0xcf],9); cyc([0xdf],3)
+cyc([0xef,0xff],3)
+assert len(CYCLES)==256, len(CYCLES)
+
+NAMES=[None]*256
+
+def is_io(a): return 0xf0<=a<=0xff
+
+class Io(Exception): pass
+
+class Cpu:
+    def __init__(s, regs, mem):
+        s.pc,s.a,s.x,s.y,s.sp,s.psw=regs
+        s.mem=mem            # address -> value; every address the instruction touches must be in here
+        s.extra=0
+    def rd(s,a):
+        a&=0xffff
+        if is_io(a): raise Io()
+        if a not in s.mem: s.mem[a]=rng.randrange(256)
+        return s.mem[a]
+    def wr(s,a,v):
+        a&=0xffff
+        if is_io(a): raise Io()
+        s.mem[a]=v&0xff
+    def fetch(s):
+        v=s.rd(s.pc); s.pc=(s.pc+1)&0xffff; return v
+    def fetchw(s):
+        lo=s.fetch(); return lo|(s.fetch()<<8)
+    def dp(s,d): return ((s.psw&P) and 0x100 or 0)|(d&0xff)
+    def rdw_dp(s,d): return s.rd(s.dp(d))|(s.rd(s.dp(d+1))<<8)
+    def push(s,v): s.wr(0x100|s.sp,v); s.sp=(s.sp-1)&0xff
+    def pop(s): s.sp=(s.sp+1)&0xff; return s.rd(0x100|s.sp)
+    def flag(s,f,on):
+        if on: s.psw|=f
+        else: s.psw&=~f
+    def nz(s,v,bits=8):
+        s.flag(N,v&(1<<(bits-1))); s.flag(Z,(v&((1<<bits)-1))==0)
+        return v
+    def branch(s,cond):
+        r=s.fetch()
+        if cond:
+            s.pc=(s.pc+(r-256 if r&0x80 else r))&0xffff; s.extra=2
+
+    # alu
+    def adc(s,a,m):
+        c=s.psw&C; r=a+m+c
+        s.flag(C,r>0xff); s.flag(H,(a&0xf)+(m&0xf)+c>0xf); s.flag(V,~(a^m)&(a^r)&0x80)
+        return s.nz(r&0xff)
+    def sbc(s,a,m): return s.adc(a,m^0xff)
+    def cmp(s,a,m):
+        r=a-m; s.flag(C,r>=0); s.nz(r&0xff); return a
+    def or_(s,a,m): return s.nz(a|m)
+    def and_(s,a,m): return s.nz(a&m)
+    def eor(s,a,m): return s.nz(a^m)
+    def asl(s,m): s.flag(C,m&0x80); return s.nz((m<<1)&0xff)
+    def rol(s,m): c=s.psw&C; s.flag(C,m&0x80); return s.nz(((m<<1)|c)&0xff)
+    def lsr(s,m): s.flag(C,m&1); return s.nz(m>>1)
+    def ror(s,m): c=s.psw&C; s.flag(C,m&1); return s.nz((m>>1)|(c<<7))
+    def inc(s,m): return s.nz((m+1)&0xff)
+    def dec(s,m): return s.nz((m-1)&0xff)
+
+    def step(s):
+        op=s.fetch()
+        lo,hi=op&0xf,op>>4
+        alu=[s.or_,s.and_,s.eor,s.cmp,s.adc,s.sbc]
+        if lo==0:
+            if op in (0x00,): NAMES[op]='nop'
+            elif hi&1:
+                conds={0x10:lambda:not s.psw&N,0x30:lambda:s.psw&N,0x50:lambda:not s.psw&V,0x70:lambda:s.psw&V,
+                       0x90:lambda:not s.psw&C,0xb0:lambda:s.psw&C,0xd0:lambda:not s.psw&Z,0xf0:lambda:s.psw&Z}
+                NAMES[op]={0x10:'bpl',0x30:'bmi',0x50:'bvc',0x70:'bvs',0x90:'bcc',0xb0:'bcs',0xd0:'bne',0xf0:'beq'}[op]+' r'
+                s.branch(conds[op]())
+            else:
+                f,on,name={0x20:(P,0,'clrp'),0x40:(P,1,'setp'),0x60:(C,0,'clrc'),0x80:(C,1,'setc'),
+                           0xa0:(I,1,'ei'),0xc0:(I,0,'di')}.get(op,(None,None,None))
+                if op==0xe0: NAMES[op]='clrv'; s.psw&=~(V|H)
+                else: NAMES[op]=name; s.flag(f,on)
+        elif lo==1:
+            NAMES[op]='tcall %d'%hi
+            v=0xffde-2*hi
+            s.push(s.pc>>8); s.push(s.pc&0xff)
+            s.pc=s.rd(v)|(s.rd(v+1)<<8)
+        elif lo==2:
+            bit=hi>>1; d=s.dp(s.fetch())
+            NAMES[op]='%s d.%d'%('clr1' if hi&1 else 'set1',bit)
+            m=s.rd(d); s.wr(d,(m&~(1<<bit)) if hi&1 else (m|(1<<bit)))
+        elif lo==3:
+            bit=hi>>1; m=s.rd(s.dp(s.fetch()))
+            NAMES[op]='%s d.%d,r'%('bbc' if hi&1 else 'bbs',bit)
+            s.branch(bool(m&(1<<bit))!=bool(hi&1))
+        elif lo in (4,5,6,7,8,9) and hi<0xc:
+            f=alu[hi>>1]; name=['or','and','eor','cmp','adc','sbc'][hi>>1]
+            if lo==8 and hi&1:
+                NAMES[op]=name+' d,#i'; i=s.fetch(); d=s.dp(s.fetch()); r=f(s.rd(d),i)
+                if name!='cmp': s.wr(d,r)
+                return
+            if lo==9:
+                if hi&1:
+                    NAMES[op]=name+' (x),(y)'; m=s.rd(s.dp(s.y)); d=s.dp(s.x); r=f(s.rd(d),m)
+                else:
+                    NAMES[op]=name+' dd,ds'; m=s.rd(s.dp(s.fetch())); d=s.dp(s.fetch()); r=f(s.rd(d),m)
+                if name!='cmp': s.wr(d,r)
+                return
+            NAMES[op],addr=s.ea(lo,hi&1)
+            m=s.fetch() if addr is None else s.rd(addr)
+            NAMES[op]=name+' a,'+NAMES[op]
+            s.a=f(s.a,m)
+        elif lo in (4,5,6,7) and hi>=0xc:
+            mode,addr=s.ea(lo,hi&1)
+            if hi<0xe: NAMES[op]='mov %s,a'%mode; s.wr(addr,s.a)
+            else: NAMES[op]='mov a,%s'%mode; s.a=s.nz(s.rd(addr))
+        elif lo==8:
+            if op==0xc8: NAMES[op]='cmp x,#i'; s.cmp(s.x,s.fetch())
+            elif op==0xd8: NAMES[op]='mov d,x'; s.wr(s.dp(s.fetch()),s.x)
+            elif op==0xe8: NAMES[op]='mov a,#i'; s.a=s.nz(s.fetch())
+            else: NAMES[op]='mov x,d'; s.x=s.nz(s.rd(s.dp(s.fetch())))
+        elif lo==9:
+            if op==0xc9: NAMES[op]='mov !a,x'; s.wr(s.fetchw(),s.x)
+            elif op==0xd9: NAMES[op]='mov d+y,x'; s.wr(s.dp(s.fetch()+s.y),s.x)
+            elif op==0xe9: NAMES[op]='mov x,!a'; s.x=s.nz(s.rd(s.fetchw()))
+            else: NAMES[op]='mov x,d+y'; s.x=s.nz(s.rd(s.dp(s.fetch()+s.y)))
+        elif lo==0xa:
+            if op in (0x0a,0x2a,0x4a,0x6a,0x8a,0xaa,0xca,0xea):
+                w=s.fetchw(); addr=w&0x1fff; bit=w>>13; m=s.rd(addr); b=(m>>bit)&1; c=s.psw&C
+                if op==0x0a: NAMES[op]='or1 c,m.b'; s.flag(C,c|b)
+                elif op==0x2a: NAMES[op]='or1 c,/m.b'; s.flag(C,c|(b^1))
+                elif op==0x4a: NAMES[op]='and1 c,m.b'; s.flag(C,c&b)
+                elif op==0x6a: NAMES[op]='and1 c,/m.b'; s.flag(C,c&(b^1))
+                elif op==0x8a: NAMES[op]='eor1 c,m.b'; s.flag(C,c^b)
+                elif op==0xaa: NAMES[op]='mov1 c,m.b'; s.flag(C,b)
+                elif op==0xca: NAMES[op]='mov1 m.b,c'; s.wr(addr,(m&~(1<<bit))|(c<<bit))
+                else: NAMES[op]='not1 m.b'; s.wr(addr,m^(1<<bit))
+            elif op==0xfa:
+                NAMES[op]='mov dd,ds'; m=s.rd(s.dp(s.fetch())); s.wr(s.dp(s.fetch()),m)
+            else:
+                d=s.fetch(); w=s.rdw_dp(d); ya=(s.y<<8)|s.a
+                if op in (0x1a,0x3a):
+                    NAMES[op]='decw d' if op==0x1a else 'incw d'
+                    r=s.nz((w+(1 if op==0x3a else -1))&0xffff,16)
+                    s.wr(s.dp(d),r&0xff); s.wr(s.dp(d+1),r>>8)
+                elif op==0x5a:
+                    NAMES[op]='cmpw ya,d'; r=ya-w; s.flag(C,r>=0); s.nz(r&0xffff,16)
+                elif op in (0x7a,0x9a):
+                    NAMES[op]='addw ya,d' if op==0x7a else 'subw ya,d'
+                    m=w if op==0x7a else w^0xffff
+                    s.flag(C,op==0x9a)
+                    lo_=s.a+(m&0xff)+(s.psw&C); c=lo_>0xff
+                    a=s.y; mm=m>>8; r=a+mm+c
+                    s.flag(C,r>0xff); s.flag(H,(a&0xf)+(mm&0xf)+c>0xf); s.flag(V,~(a^mm)&(a^r)&0x80)
+                    res=((r&0xff)<<8)|(lo_&0xff); s.nz(res,16); s.a=res&0xff; s.y=res>>8
+                elif op==0xba:
+                    NAMES[op]='movw ya,d'; s.nz(w,16); s.a=w&0xff; s.y=w>>8
+                else:
+                    NAMES[op]='movw d,ya'; s.wr(s.dp(d),s.a); s.wr(s.dp(d+1),s.y)
+        elif lo==0xb or (lo==0xc and op not in (0xcc,0xdc,0xec,0xfc) and not (hi&1)):
+            names=['asl','rol','lsr','ror','dec','inc']
+            if hi<0xc:
+                f=[s.asl,s.rol,s.lsr,s.ror,s.dec,s.inc][hi>>1]
+                if lo==0xb:
+                    d=s.fetch(); addr=s.dp(d+s.x if hi&1 else d)
+                    NAMES[op]=names[hi>>1]+(' d+x' if hi&1 else ' d')
+                else:
+                    addr=s.fetchw(); NAMES[op]=names[hi>>1]+' !a'
+                s.wr(addr,f(s.rd(addr)))
+            elif op==0xcb: NAMES[op]='mov d,y'; s.wr(s.dp(s.fetch()),s.y)
+            elif op==0xdb: NAMES[op]='mov d+x,y'; s.wr(s.dp(s.fetch()+s.x),s.y)
+            elif op==0xeb: NAMES[op]='mov y,d'; s.y=s.nz(s.rd(s.dp(s.fetch())))
+            elif op==0xfb: NAMES[op]='mov y,d+x'; s.y=s.nz(s.rd(s.dp(s.fetch()+s.x)))
+        elif lo==0xc:
+            if op==0xcc: NAMES[op]='mov !a,y'; s.wr(s.fetchw(),s.y)
+            elif op==0xec: NAMES[op]='mov y,!a'; s.y=s.nz(s.rd(s.fetchw()))
+            elif op==0xdc: NAMES[op]='dec y'; s.y=s.dec(s.y)
+            elif op==0xfc: NAMES[op]='inc y'; s.y=s.inc(s.y)
+            else:
+                f=[s.asl,s.rol,s.lsr,s.ror,s.dec,s.inc][hi>>1]
+                NAMES[op]=['asl','rol','lsr','ror','dec','inc'][hi>>1]+' a'; s.a=f(s.a)
+        elif lo==0xd:
+            if op==0x0d: NAMES[op]='push psw'; s.push(s.psw)
+            elif op==0x2d: NAMES[op]='push a'; s.push(s.a)
+            elif op==0x4d: NAMES[op]='push x'; s.push(s.x)
+            elif op==0x6d: NAMES[op]='push y'; s.push(s.y)
+            elif op==0x1d: NAMES[op]='dec x'; s.x=s.dec(s.x)
+            elif op==0x3d: NAMES[op]='inc x'; s.x=s.inc(s.x)
+            elif op==0x5d: NAMES[op]='mov x,a'; s.x=s.nz(s.a)
+            elif op==0x7d: NAMES[op]='mov a,x'; s.a=s.nz(s.x)
+            elif op==0x8d: NAMES[op]='mov y,#i'; s.y=s.nz(s.fetch())
+            elif op==0x9d: NAMES[op]='mov x,sp'; s.x=s.nz(s.sp)
+            elif op==0xad: NAMES[op]='cmp y,#i'; s.cmp(s.y,s.fetch())
+            elif op==0xbd: NAMES[op]='mov sp,x'; s.sp=s.x
+            elif op==0xcd: NAMES[op]='mov x,#i'; s.x=s.nz(s.fetch())
+            elif op==0xdd: NAMES[op]='mov a,y'; s.a=s.nz(s.y)
+            elif op==0xed: NAMES[op]='notc'; s.psw^=C
+            elif op==0xfd: NAMES[op]='mov y,a'; s.y=s.nz(s.a)
+        elif lo==0xe:
+            if op in (0x0e,0x4e):
+                NAMES[op]='tset1 !a' if op==0x0e else 'tclr1 !a'
+                addr=s.fetchw(); m=s.rd(addr); s.nz((s.a-m)&0xff)
+                s.wr(addr,(m|s.a) if op==0x0e else (m&~s.a))
+            elif op==0x1e: NAMES[op]='cmp x,!a'; s.cmp(s.x,s.rd(s.fetchw()))
+            elif op==0x5e: NAMES[op]='cmp y,!a'; s.cmp(s.y,s.rd(s.fetchw()))
+            elif op==0x3e: NAMES[op]='cmp x,d'; s.cmp(s.x,s.rd(s.dp(s.fetch())))
+            elif op==0x7e: NAMES[op]='cmp y,d'; s.cmp(s.y,s.rd(s.dp(s.fetch())))
+            elif op==0x2e: NAMES[op]='cbne d,r'; m=s.rd(s.dp(s.fetch())); s.branch(m!=s.a)
+            elif op==0xde: NAMES[op]='cbne d+x,r'; m=s.rd(s.dp(s.fetch()+s.x)); s.branch(m!=s.a)
+            elif op==0x6e:
+                NAMES[op]='dbnz d,r'; d=s.dp(s.fetch()); m=(s.rd(d)-1)&0xff; s.wr(d,m); s.branch(m!=0)
+            elif op==0xfe: NAMES[op]='dbnz y,r'; s.y=(s.y-1)&0xff; s.branch(s.y!=0)
+            elif op==0x8e: NAMES[op]='pop psw'; s.psw=s.pop()
+            elif op==0xae: NAMES[op]='pop a'; s.a=s.pop()
+            elif op==0xce: NAMES[op]='pop x'; s.x=s.pop()
+            elif op==0xee: NAMES[op]='pop y'; s.y=s.pop()
+            elif op==0x9e:
+                NAMES[op]='div ya,x'
+                ya=(s.y<<8)|s.a; x=s.x
+                s.flag(V,s.y>=x); s.flag(H,(s.y&0xf)>=(x&0xf))
+                if s.y<(x<<1): a=ya//x; y=ya%x
+                else: a=255-(ya-(x<<9))//(256-x); y=x+(ya-(x<<9))%(256-x)
+                s.a=s.nz(a&0xff); s.y=y&0xff
+            elif op==0xbe:
+                NAMES[op]='das'; a=s.a
+                if not s.psw&C or a>0x99: a=(a-0x60)&0xff; s.psw&=~C
+                if not s.psw&H or (a&0xf)>9: a=(a-6)&0xff
+                s.a=s.nz(a)
+        else:
+            if op==0x0f:
+                NAMES[op]='brk'; s.push(s.pc>>8); s.push(s.pc&0xff); s.push(s.psw)
+                s.psw=(s.psw|B)&~I; s.pc=s.rd(0xffde)|(s.rd(0xffdf)<<8)
+            elif op==0x1f: NAMES[op]='jmp [!a+x]'; addr=s.fetchw()+s.x; s.pc=s.rd(addr)|(s.rd(addr+1)<<8)
+            elif op==0x2f: NAMES[op]='bra r'; s.branch(True); s.extra=0
+            elif op==0x3f:
+                NAMES[op]='call !a'; t=s.fetchw(); s.push(s.pc>>8); s.push(s.pc&0xff); s.pc=t
+            elif op==0x4f:
+                NAMES[op]='pcall u'; t=0xff00|s.fetch(); s.push(s.pc>>8); s.push(s.pc&0xff); s.pc=t
+            elif op==0x5f: NAMES[op]='jmp !a'; s.pc=s.fetchw()
+            elif op==0x6f: NAMES[op]='ret'; lo_=s.pop(); s.pc=lo_|(s.pop()<<8)
+            elif op==0x7f: NAMES[op]='reti'; s.psw=s.pop(); lo_=s.pop(); s.pc=lo_|(s.pop()<<8)
+            elif op==0x8f: NAMES[op]='mov d,#i'; i=s.fetch(); s.wr(s.dp(s.fetch()),i)
+            elif op==0x9f: NAMES[op]='xcn a'; s.a=s.nz(((s.a>>4)|(s.a<<4))&0xff)
+            elif op==0xaf: NAMES[op]='mov (x)+,a'; s.wr(s.dp(s.x),s.a); s.x=(s.x+1)&0xff
+            elif op==0xbf: NAMES[op]='mov a,(x)+'; s.a=s.nz(s.rd(s.dp(s.x))); s.x=(s.x+1)&0xff
+            elif op==0xcf: NAMES[op]='mul ya'; r=s.y*s.a; s.a=r&0xff; s.y=r>>8; s.nz(s.y)
+            elif op==0xdf:
+                NAMES[op]='daa'; a=s.a
+                if s.psw&C or a>0x99: a=(a+0x60)&0xff; s.psw|=C
+                if s.psw&H or (a&0xf)>9: a=(a+6)&0xff
+                s.a=s.nz(a)
+            elif op in (0xef,0xff): NAMES[op]='sleep' if op==0xef else 'stop'
+
+    # Effective address for the (lo, odd row) addressing modes of the alu/mov columns
+    def ea(s,lo,odd):
+        if lo==4:
+            d=s.fetch()
+            return ('d+x',s.dp(d+s.x)) if odd else ('d',s.dp(d))
+        if lo==5:
+            a=s.fetchw()
+            return ('!a+x',(a+s.x)&0xffff) if odd else ('!a',a)
+        if lo==6:
+            if odd: return ('!a+y',(s.fetchw()+s.y)&0xffff)
+            return ('(x)',s.dp(s.x))
+        if lo==7:
+            d=s.fetch()
+            if odd: return ('[d]+y',(s.rdw_dp(d)+s.y)&0xffff)
+            return ('[d+x]',s.rdw_dp(d+s.x))
+        if lo==8:
+            return ('#i',None)
+
+def interesting_byte():
+    return rng.choice([0x00,0x01,0x0f,0x10,0x7f,0x80,0x81,0x99,0x9a,0xfe,0xff,rng.randrange(256),rng.randrange(256)])
+
+class Recording(Cpu):
+    # Remembers the value each address had before the instruction first touched it
+    def __init__(s, regs, mem):
+        Cpu.__init__(s, regs, mem)
+        s.initial={}
+    def rd(s,a):
+        a&=0xffff
+        if is_io(a): raise Io()
+        if a not in s.mem: s.mem[a]=rng.randrange(256)
+        s.initial.setdefault(a,s.mem[a])
+        return s.mem[a]
+    def wr(s,a,v):
+        a&=0xffff
+        if is_io(a): raise Io()
+        if a not in s.mem: s.mem[a]=rng.randrange(256)
+        s.initial.setdefault(a,s.mem[a])
+        s.mem[a]=v&0xff
+
+tests=[]
+VARIANTS=6
+KEYS=['pc','a','x','y','sp','psw']
+for op in range(256):
+    made=0
+    while made<VARIANTS:
+        pc=rng.randrange(0x0200,0xef00)
+        regs=[pc,interesting_byte(),rng.randrange(256),interesting_byte(),rng.randrange(256),rng.randrange(256)]
+        # Cycle the flags each condition depends on so branches go both ways
+        regs[5]=(regs[5]&~(N|V|C|Z))|[N|C,V|Z,0,N|V|Z|C,Z,C][made]
+        if op==0x9e:
+            regs[2]=rng.choice([0,1,2,0x20,0x7f,0x80,0xff,rng.randrange(256)])
+        mem={pc:op,pc+1:rng.randrange(256),pc+2:rng.randrange(256)}
+        cpu=Recording(list(regs),mem)
+        # Random memory rarely matches a or counts down to 0, so set that up for every other variant
+        if made&1:
+            if op in (0x2e,0x6e,0xde):
+                d=cpu.dp(mem[pc+1]+(regs[2] if op==0xde else 0))
+                mem[d]=regs[1] if op!=0x6e else 1
+            elif op==0xfe:
+                cpu.y=1
+        initial_regs=(cpu.pc,cpu.a,cpu.x,cpu.y,cpu.sp,cpu.psw)
+        try:
+            cpu.step()
+        except Io:
+            continue
+        code={pc,pc+1,pc+2}
+        for a in code:
+            cpu.initial.setdefault(a,cpu.mem[a])
+        # Instructions that overwrite their own code make for confusing vectors
+        if any(cpu.mem[a]!=cpu.initial[a] for a in code):
+            continue
+        final_regs=(cpu.pc,cpu.a,cpu.x,cpu.y,cpu.sp,cpu.psw)
+        addresses=sorted(cpu.initial)
+        tests.append(dict(name='%02x %s #%d'%(op,NAMES[op],made),
+                          initial=dict(zip(KEYS,initial_regs),ram=[[a,cpu.initial[a]] for a in addresses]),
+                          final=dict(zip(KEYS,final_regs),ram=[[a,cpu.mem[a]] for a in addresses]),
+                          cycle_count=CYCLES[op]+cpu.extra))
+        made+=1
+
+with open(sys.argv[1],'w') as f:
+    f.write('[\n'+',\n'.join(json.dumps(t,separators=(',',':')) for t in tests)+'\n]\n')
+print(len(tests),'vectors')
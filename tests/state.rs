@@ -0,0 +1,54 @@
+#![cfg(feature = "std")]
+
+extern crate snes_apu;
+extern crate spc;
+
+use snes_apu::apu::Apu;
+use snes_apu::dsp::envelope::Mode;
+use spc::spc::Spc;
+
+#[test]
+fn smp_state_matches_loaded_spc() {
+    let spc = Spc::load(format!("{}/test/ferris-nu.spc", env!("CARGO_MANIFEST_DIR"))).unwrap();
+    let apu = Apu::from_spc(&spc);
+    let state = apu.smp_state();
+    assert_eq!(state.pc, spc.pc);
+    assert_eq!(state.a, spc.a);
+    assert_eq!(state.x, spc.x);
+    assert_eq!(state.y, spc.y);
+    assert_eq!(state.sp, spc.sp);
+    assert_eq!(state.psw, spc.psw);
+    assert!(!state.is_stopped);
+}
+
+#[test]
+fn dsp_state_reflects_register_writes() {
+    let mut apu = Apu::new();
+    apu.write_u8(0xf2, 0x31);
+    apu.write_u8(0xf3, 0x40);
+    apu.write_u8(0xf2, 0x5d);
+    apu.write_u8(0xf3, 0x12);
+
+    let state = apu.dsp_state();
+    assert_eq!(state.regs[0x31], 0x40);
+    assert_eq!(state.source_dir, 0x12);
+    assert_eq!(state.voices[3].vol_right, 0x40);
+    assert_eq!(apu.voice_state(3).vol_right, 0x40);
+    assert!(state.voices[3].envelope_mode == Mode::Release);
+    assert!(state.is_soft_reset);
+}
+
+#[test]
+fn timer_state_does_not_reset_counter() {
+    let mut apu = Apu::new();
+    apu.write_u8(0xfb, 1);
+    apu.write_u8(0xf1, 0x02);
+    apu.cpu_cycles_callback(128 * 3);
+
+    let state = apu.timer_state(1);
+    assert!(state.is_running);
+    assert_eq!(state.target, 1);
+    assert_eq!(state.counter, 3);
+    assert_eq!(apu.timer_state(1).counter, 3);
+    assert!(!apu.timer_state(0).is_running);
+}
@@ -0,0 +1,180 @@
+extern crate snes_apu;
+
+use snes_apu::apu::Apu;
+
+use std::fs;
+
+const CONTROL: u32 = 0xf1;
+const TARGET_0: u32 = 0xfa;
+const TARGET_2: u32 = 0xfc;
+const COUNTER_0: u32 = 0xfd;
+const COUNTER_2: u32 = 0xff;
+
+fn start(apu: &mut Apu, target_address: u32, target: u8, control: u8) {
+    apu.write_u8(target_address, target);
+    apu.write_u8(CONTROL, control);
+}
+
+#[test]
+fn timer_0_ticks_at_8khz() {
+    let mut apu = Apu::new();
+    start(&mut apu, TARGET_0, 1, 0x01);
+    apu.cpu_cycles_callback(127);
+    assert_eq!(apu.timers()[0].counter(), 0);
+    apu.cpu_cycles_callback(1);
+    assert_eq!(apu.timers()[0].counter(), 1);
+    apu.cpu_cycles_callback(128);
+    assert_eq!(apu.read_u8(COUNTER_0), 2);
+}
+
+#[test]
+fn timer_2_ticks_at_64khz() {
+    let mut apu = Apu::new();
+    start(&mut apu, TARGET_2, 1, 0x04);
+    apu.cpu_cycles_callback(15);
+    assert_eq!(apu.timers()[2].counter(), 0);
+    apu.cpu_cycles_callback(1);
+    assert_eq!(apu.timers()[2].counter(), 1);
+    apu.cpu_cycles_callback(16 * 3);
+    assert_eq!(apu.read_u8(COUNTER_2), 4);
+}
+
+#[test]
+fn counter_increments_when_target_is_reached() {
+    let mut apu = Apu::new();
+    start(&mut apu, TARGET_0, 3, 0x01);
+    apu.cpu_cycles_callback(128 * 2);
+    assert_eq!(apu.timers()[0].stage_2(), 2);
+    assert_eq!(apu.timers()[0].counter(), 0);
+    apu.cpu_cycles_callback(128);
+    assert_eq!(apu.timers()[0].stage_2(), 0);
+    assert_eq!(apu.timers()[0].counter(), 1);
+}
+
+#[test]
+fn target_0_means_256() {
+    let mut apu = Apu::new();
+    start(&mut apu, TARGET_0, 0, 0x01);
+    apu.cpu_cycles_callback(128 * 255);
+    assert_eq!(apu.timers()[0].counter(), 0);
+    apu.cpu_cycles_callback(128);
+    assert_eq!(apu.timers()[0].counter(), 1);
+}
+
+#[test]
+fn counter_wraps_at_16() {
+    let mut apu = Apu::new();
+    start(&mut apu, TARGET_0, 1, 0x01);
+    apu.cpu_cycles_callback(128 * 15);
+    assert_eq!(apu.timers()[0].counter(), 15);
+    apu.cpu_cycles_callback(128);
+    assert_eq!(apu.timers()[0].counter(), 0);
+    apu.cpu_cycles_callback(128);
+    assert_eq!(apu.read_u8(COUNTER_0), 1);
+}
+
+#[test]
+fn reading_counter_resets_it() {
+    let mut apu = Apu::new();
+    start(&mut apu, TARGET_0, 1, 0x01);
+    apu.cpu_cycles_callback(128 * 5);
+    assert_eq!(apu.read_u8(COUNTER_0), 5);
+    assert_eq!(apu.read_u8(COUNTER_0), 0);
+    apu.cpu_cycles_callback(128);
+    assert_eq!(apu.read_u8(COUNTER_0), 1);
+}
+
+#[test]
+fn stopped_timer_holds_counter_until_restarted() {
+    let mut apu = Apu::new();
+    start(&mut apu, TARGET_0, 2, 0x01);
+    apu.cpu_cycles_callback(128 * 5);
+    apu.write_u8(CONTROL, 0x00);
+    assert!(!apu.timers()[0].is_running());
+    apu.cpu_cycles_callback(128 * 10);
+    assert_eq!(apu.timers()[0].counter(), 2);
+    assert_eq!(apu.timers()[0].stage_2(), 1);
+
+    // Setting the enable bit again restarts both counters
+    apu.write_u8(CONTROL, 0x01);
+    assert_eq!(apu.timers()[0].counter(), 0);
+    assert_eq!(apu.timers()[0].stage_2(), 0);
+}
+
+#[test]
+fn rewriting_enable_bit_does_not_restart() {
+    let mut apu = Apu::new();
+    start(&mut apu, TARGET_0, 1, 0x01);
+    apu.cpu_cycles_callback(128 * 3);
+    apu.write_u8(CONTROL, 0x01);
+    assert_eq!(apu.timers()[0].counter(), 3);
+}
+
+#[test]
+fn stage_1_divider_runs_while_timer_is_stopped() {
+    let mut apu = Apu::new();
+    apu.write_u8(TARGET_0, 1);
+    apu.cpu_cycles_callback(100);
+    apu.write_u8(CONTROL, 0x01);
+    apu.cpu_cycles_callback(28);
+    assert_eq!(apu.timers()[0].counter(), 1);
+}
+
+#[test]
+fn timers_are_caught_up_when_smp_run_returns() {
+    // mov $fa, #0; mov $f1, #$01; bra -2, so the timer is never touched again once it's started
+    let mut apu = Apu::new();
+    apu.ram_mut()[0x0200..0x0208].copy_from_slice(&[0x8f, 0x00, 0xfa, 0x8f, 0x01, 0xf1, 0x2f, 0xfe]);
+    let smp = apu.smp.as_mut().unwrap();
+    smp.reg_pc = 0x0200;
+    let num_cycles = smp.run(1000);
+    assert_eq!(num_cycles, 1002);
+    assert_eq!(apu.timers()[0].stage_2(), (1002 / 128) as u8);
+}
+
+fn timer_fields(apu: &Apu, index: usize) -> (bool, u8, u8, u8) {
+    let timer = &apu.timers()[index];
+    (timer.is_running(), timer.target(), timer.stage_2(), timer.counter())
+}
+
+#[test]
+fn catching_up_many_ticks_at_once_matches_one_at_a_time() {
+    // Including a target stage 2 has already passed, which it only reaches again after wrapping around
+    for &(first_target, second_target) in [(1, 1), (3, 3), (0, 0), (200, 5), (7, 0)].iter() {
+        let mut batched_apu = Apu::new();
+        let mut ticked_apu = Apu::new();
+        for apu in [&mut batched_apu, &mut ticked_apu].iter_mut() {
+            start(apu, TARGET_0, first_target, 0x01);
+        }
+        for &(num_cycles, target) in [(128 * 100 + 37, first_target), (128 * 1000 + 91, second_target), (128 * 3, second_target)].iter() {
+            batched_apu.write_u8(TARGET_0, target);
+            ticked_apu.write_u8(TARGET_0, target);
+            batched_apu.cpu_cycles_callback(num_cycles);
+            for _ in 0..num_cycles {
+                ticked_apu.cpu_cycles_callback(1);
+            }
+            assert_eq!(timer_fields(&batched_apu, 0), timer_fields(&ticked_apu, 0), "targets {} then {}", first_target, second_target);
+        }
+    }
+}
+
+#[test]
+fn lazy_sync_matches_eager_sync_on_bundled_spcs() {
+    for name in ["ferris-nu", "smashit"].iter() {
+        let data = fs::read(format!("test/{}.spc", name)).unwrap();
+        let mut lazy_apu = Apu::from_spc_bytes(&data).unwrap();
+        let mut eager_apu = Apu::from_spc_bytes(&data).unwrap();
+        eager_apu.smp.as_mut().unwrap().set_eager_sync(true);
+
+        let mut lazy = ([0; 512], [0; 512]);
+        let mut eager = ([0; 512], [0; 512]);
+        for block in 0..(32000 * 5 / 512) {
+            lazy_apu.render(&mut lazy.0, &mut lazy.1, 512);
+            eager_apu.render(&mut eager.0, &mut eager.1, 512);
+            assert!(lazy.0[..] == eager.0[..] && lazy.1[..] == eager.1[..], "{}: output differs in block {}", name, block);
+        }
+        for i in 0..3 {
+            assert_eq!(timer_fields(&lazy_apu, i), timer_fields(&eager_apu, i));
+        }
+    }
+}
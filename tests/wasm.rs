@@ -0,0 +1,79 @@
+#![cfg(feature = "wasm")]
+
+// Runs natively with `cargo test --features wasm`, and headlessly under a wasm runtime with
+//  `cargo test --features wasm --target wasm32-unknown-unknown` (using wasm-bindgen-test-runner)
+
+extern crate snes_apu;
+extern crate spc;
+#[cfg(target_arch = "wasm32")]
+extern crate wasm_bindgen_test;
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen_test::wasm_bindgen_test;
+
+use snes_apu::wasm::SpcPlayer;
+#[cfg(not(target_arch = "wasm32"))]
+use snes_apu::apu::Apu;
+#[cfg(not(target_arch = "wasm32"))]
+use spc::spc::Spc;
+
+// No filesystem under wasm, so the spcs are baked into the test binary
+const SMASHIT: &[u8] = include_bytes!("../test/smashit.spc");
+const B0RKED: &[u8] = include_bytes!("../test/broken/b0rked.spc");
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn render_size_does_not_affect_output() {
+    let mut player = SpcPlayer::new(SMASHIT).unwrap();
+    let mut expected = vec![0.0; 8000];
+    player.render(&mut expected);
+
+    // AudioWorklets render 128 frames at a time
+    let mut player = SpcPlayer::new(SMASHIT).unwrap();
+    let mut actual = vec![0.0; 8000];
+    for chunk in actual.chunks_mut(256) {
+        player.render(chunk);
+    }
+    assert_eq!(player.position(), 4000.0);
+    assert!(actual == expected);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[test]
+fn renders_same_output_as_apu() {
+    let mut player = SpcPlayer::new(SMASHIT).unwrap();
+    let mut actual = vec![0.0; 8000];
+    player.render(&mut actual);
+
+    let mut apu = Apu::from_spc(&Spc::load(format!("{}/test/smashit.spc", env!("CARGO_MANIFEST_DIR"))).unwrap());
+    apu.clear_echo_buffer();
+    let mut left = vec![0; 4000];
+    let mut right = vec![0; 4000];
+    apu.render(&mut left, &mut right, 4000);
+    for i in 0..4000 {
+        assert_eq!(actual[i * 2], (left[i] as f32) / 32768.0);
+        assert_eq!(actual[i * 2 + 1], (right[i] as f32) / 32768.0);
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn invalid_spc_is_rejected() {
+    assert!(SpcPlayer::new(B0RKED).is_err());
+    assert!(SpcPlayer::new(&SMASHIT[..0x1000]).is_err());
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn muting_every_voice_renders_silence() {
+    let mut player = SpcPlayer::new(SMASHIT).unwrap();
+    for i in 0..8 {
+        player.set_voice_muted(i, true).unwrap();
+    }
+    assert!(player.set_voice_muted(8, true).is_err());
+    assert!(player.set_voice_solo(8, true).is_err());
+
+    let mut out = vec![1.0; 2000];
+    player.render(&mut out);
+    assert!(out.iter().all(|&sample| sample == 0.0));
+}
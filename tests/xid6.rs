@@ -0,0 +1,143 @@
+extern crate snes_apu;
+
+use snes_apu::apu::Apu;
+use snes_apu::error::SpcError;
+use snes_apu::xid6::{Xid6Tag, XID6_OFFSET, TICKS_PER_SECOND};
+
+const SMASHIT: &[u8] = include_bytes!("../test/smashit.spc");
+
+fn full_tag() -> Xid6Tag {
+    let mut tag = Xid6Tag::new();
+    tag.song_name = Some("Smash It".to_string());
+    tag.game_name = Some("Demo".to_string());
+    tag.artist_name = Some("elix".to_string());
+    tag.dumper_name = Some("ferris".to_string());
+    tag.date_dumped = Some(20171211);
+    tag.emulator = Some(2);
+    tag.comments = Some("Comments with an odd length".to_string());
+    tag.ost_title = Some("Demo OST".to_string());
+    tag.ost_disc = Some(1);
+    tag.ost_track = Some((12, b'b'));
+    tag.publisher = Some("Publisher".to_string());
+    tag.copyright_year = Some(2017);
+    tag.intro_length = Some(5 * TICKS_PER_SECOND);
+    tag.loop_length = Some(20 * TICKS_PER_SECOND + 3);
+    tag.end_length = Some(TICKS_PER_SECOND);
+    tag.fade_length = Some(TICKS_PER_SECOND / 2);
+    tag.muted_voices = Some(0x81);
+    tag.loop_count = Some(3);
+    tag.amplification = Some(0x18000);
+    tag
+}
+
+#[test]
+fn round_trips_through_spc_file() {
+    let tag = full_tag();
+    let mut data = SMASHIT.to_vec();
+    tag.write_to_spc(&mut data);
+    assert_eq!(&data[XID6_OFFSET..XID6_OFFSET + 4], b"xid6");
+    assert_eq!(data.len() % 4, 0);
+
+    assert_eq!(Xid6Tag::from_spc_bytes(&data).unwrap(), Some(tag.clone()));
+    assert!(Apu::from_spc_bytes(&data).is_ok());
+
+    // Writing again replaces the old tag rather than appending another
+    let len = data.len();
+    tag.write_to_spc(&mut data);
+    assert_eq!(data.len(), len);
+}
+
+#[test]
+fn spc_without_tag_has_none() {
+    assert_eq!(Xid6Tag::from_spc_bytes(SMASHIT).unwrap(), None);
+}
+
+#[test]
+fn trailing_bytes_without_magic_are_not_a_tag() {
+    let mut data = SMASHIT.to_vec();
+    data.resize(XID6_OFFSET, 0);
+    data.extend_from_slice(&[0x00; 256]);
+    assert_eq!(Xid6Tag::from_spc_bytes(&data).unwrap(), None);
+
+    data.truncate(XID6_OFFSET);
+    data.extend_from_slice(b"ripped by someone\r\n");
+    assert_eq!(Xid6Tag::from_spc_bytes(&data).unwrap(), None);
+
+    // With the magic, a broken chunk is still an error
+    data.truncate(XID6_OFFSET);
+    data.extend_from_slice(&[b'x', b'i', b'd', b'6', 0xff, 0xff, 0xff, 0xff]);
+    match Xid6Tag::from_spc_bytes(&data) {
+        Err(SpcError::Truncated { .. }) | Err(SpcError::InvalidXid6Tag) => (),
+        _ => panic!("Expected invalid tag")
+    }
+}
+
+#[test]
+fn parses_each_sub_chunk_type() {
+    let data = [
+        b'x', b'i', b'd', b'6', 32, 0, 0, 0,
+        // String, length including the terminator, padded to 4 bytes
+        0x01, 0x01, 0x06, 0x00, b'T', b'i', b't', b'l', b'e', 0x00, 0x00, 0x00,
+        // Data stored in the header
+        0x12, 0x00, 0x61, 0x03,
+        0x35, 0x00, 0x02, 0x00,
+        // Integer
+        0x30, 0x04, 0x04, 0x00, 0x00, 0xfa, 0x00, 0x00,
+        // Unknown sub-chunks are skipped
+        0x7f, 0x00, 0x00, 0x00,
+    ];
+    let tag = Xid6Tag::parse(&data).unwrap();
+    assert_eq!(tag.song_name, Some("Title".to_string()));
+    assert_eq!(tag.ost_track, Some((3, b'a')));
+    assert_eq!(tag.loop_count, Some(2));
+    assert_eq!(tag.intro_length, Some(0xfa00));
+    assert_eq!(tag.game_name, None);
+}
+
+#[test]
+fn exact_lengths_use_intro_loop_and_loop_count() {
+    let tag = full_tag();
+    // 5s intro, 3 loops of 20s (plus 3 ticks each), 1s end
+    assert_eq!(tag.play_samples(), Some((5 * 64000 + 3 * (20 * 64000 + 3) + 64000) / 2));
+    assert_eq!(tag.fade_samples(), Some(16000));
+
+    let mut tag = Xid6Tag::new();
+    assert_eq!(tag.play_samples(), None);
+    tag.intro_length = Some(64000);
+    tag.loop_length = Some(64000);
+    assert_eq!(tag.play_samples(), Some(64000));
+}
+
+#[test]
+fn long_strings_are_truncated() {
+    let mut tag = Xid6Tag::new();
+    tag.comments = Some("x".repeat(300));
+    let mut data = Vec::new();
+    tag.write(&mut data);
+    assert_eq!(Xid6Tag::parse(&data).unwrap().comments, Some("x".repeat(255)));
+}
+
+#[test]
+fn invalid_tags_are_rejected() {
+    let mut data = Vec::new();
+    full_tag().write(&mut data);
+
+    match Xid6Tag::parse(&data[..data.len() - 4]) {
+        Err(SpcError::Truncated { .. }) => (),
+        _ => panic!("Expected truncated tag")
+    }
+
+    let mut bad_magic = data.clone();
+    bad_magic[0] = b'X';
+    match Xid6Tag::parse(&bad_magic) {
+        Err(SpcError::InvalidXid6Tag) => (),
+        _ => panic!("Expected invalid tag")
+    }
+
+    // Sub-chunk running past the end of the chunk
+    let bad_length = [b'x', b'i', b'd', b'6', 8, 0, 0, 0, 0x01, 0x01, 0x10, 0x00, b'a', b'b', b'c', 0x00];
+    match Xid6Tag::parse(&bad_length) {
+        Err(SpcError::InvalidXid6Tag) => (),
+        _ => panic!("Expected invalid tag")
+    }
+}